@@ -0,0 +1,302 @@
+//! Asset manager. Finds the asset root directory and resolves logical asset ids
+//! (e.g. map `start`) to files, caching everything that was read.
+//!
+//! Bundles mounted to the manager override the files of the asset root.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use log::info;
+
+use crate::bundle::{self, Bundle};
+
+const APP_NAME: &'static str = "rust-rpg";
+const ASSETS_DIR: &'static str = "assets";
+
+/// Environment variable with the asset root
+pub const ASSETS_ENV: &'static str = "RUST_RPG_ASSETS";
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum AssetKind {
+    Map,
+    Sound,
+    Tileset,
+    Text,
+    /// String table of a language
+    Lang,
+}
+
+impl AssetKind {
+    /// Subdirectory of the asset root
+    pub fn dir(&self) -> &'static str {
+        match self {
+            AssetKind::Map => "maps",
+            AssetKind::Sound => "sound",
+            AssetKind::Tileset => "tilesets",
+            AssetKind::Text => "text",
+            AssetKind::Lang => "lang",
+        }
+    }
+
+    /// Added to the ids without an extension
+    fn extension(&self) -> &'static str {
+        match self {
+            AssetKind::Map => "map",
+            AssetKind::Sound => "wav",
+            AssetKind::Tileset => "tileset",
+            AssetKind::Text => "txt",
+            AssetKind::Lang => "lang",
+        }
+    }
+}
+
+impl Display for AssetKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            AssetKind::Map => "map",
+            AssetKind::Sound => "sound",
+            AssetKind::Tileset => "tileset",
+            AssetKind::Text => "text",
+            AssetKind::Lang => "lang",
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct AssetError {
+    pub kind: AssetKind,
+    pub id: String,
+    pub path: PathBuf,
+    pub error: io::Error,
+}
+
+impl Display for AssetError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} asset `{}` ({}): {}", self.kind, self.id, self.path.display(), self.error)
+    }
+}
+
+impl std::error::Error for AssetError {}
+
+/// Places where the asset root is looked for, in priority order
+fn root_candidates(cli_root: Option<&Path>) -> Vec<PathBuf> {
+    let mut candidates = vec![];
+
+    if let Some(root) = cli_root {
+        candidates.push(root.to_path_buf());
+    }
+
+    if let Some(root) = std::env::var_os(ASSETS_ENV) {
+        candidates.push(PathBuf::from(root));
+    }
+
+    if let Some(dir) = std::env::current_exe().ok().and_then(|exe| exe.parent().map(Path::to_path_buf)) {
+        candidates.push(dir.join(ASSETS_DIR));
+    }
+
+    match std::env::var_os("XDG_DATA_HOME") {
+        Some(dir) => candidates.push(PathBuf::from(dir).join(APP_NAME).join(ASSETS_DIR)),
+        None => {
+            if let Some(home) = std::env::var_os("HOME") {
+                candidates.push(PathBuf::from(home).join(".local/share").join(APP_NAME).join(ASSETS_DIR));
+            }
+        }
+    }
+
+    let data_dirs = std::env::var("XDG_DATA_DIRS")
+        .unwrap_or(String::from("/usr/local/share:/usr/share"));
+
+    for dir in data_dirs.split(':').filter(|dir| !dir.is_empty()) {
+        candidates.push(PathBuf::from(dir).join(APP_NAME).join(ASSETS_DIR));
+    }
+
+    candidates.push(PathBuf::from(ASSETS_DIR));
+    candidates
+}
+
+/// Finds the asset root. The root given on the command line must exist,
+/// the other places are just tried one by one.
+pub fn find_root(cli_root: Option<&Path>) -> Result<PathBuf, String> {
+    if let Some(root) = cli_root {
+        if !root.is_dir() {
+            return Err(format!("asset directory {} does not exist", root.display()));
+        }
+    }
+
+    let candidates = root_candidates(cli_root);
+
+    match candidates.iter().find(|dir| dir.is_dir()) {
+        Some(root) => Ok(root.clone()),
+        None => {
+            let tried: Vec<String> = candidates.iter()
+                .map(|dir| format!("  {}", dir.display()))
+                .collect();
+
+            Err(format!("asset directory is not found, tried:\n{}\n\
+                         Use --assets <dir> or {}=<dir> to set it", tried.join("\n"), ASSETS_ENV))
+        }
+    }
+}
+
+/// Bundle files of the directory in name order, so they are mounted in a stable order
+pub fn find_bundles(dir: &Path) -> Vec<PathBuf> {
+    let mut bundles: Vec<PathBuf> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().map_or(false, |ext| ext == bundle::EXTENSION))
+            .collect(),
+        Err(_) => vec![],
+    };
+
+    bundles.sort();
+    bundles
+}
+
+pub struct AssetManager {
+    root: PathBuf,
+    /// The last mounted bundle has the highest priority
+    bundles: Vec<Bundle>,
+    cache: RefCell<HashMap<(AssetKind, String), Rc<Vec<u8>>>>,
+}
+
+impl AssetManager {
+    pub fn new(root: &Path) -> Self {
+        info!("Asset root: {}", root.display());
+
+        AssetManager {
+            root: root.to_path_buf(),
+            bundles: vec![],
+            cache: RefCell::new(HashMap::new()),
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn mount(&mut self, bundle: Bundle) {
+        info!("Mounted bundle {} ({} files)", bundle.name(), bundle.paths().count());
+
+        self.bundles.push(bundle);
+        self.cache.borrow_mut().clear();
+    }
+
+    pub fn bundles(&self) -> &[Bundle] {
+        &self.bundles
+    }
+
+    /// Path of the asset relative to the asset root, e.g. `maps/start.map`.
+    /// `.map`, `.wav` etc. is added if the id has no extension.
+    fn relative_path(&self, kind: AssetKind, id: &str) -> String {
+        match Path::new(id).extension() {
+            Some(_) => format!("{}/{}", kind.dir(), id),
+            None => format!("{}/{}.{}", kind.dir(), id, kind.extension()),
+        }
+    }
+
+    /// The bundle which has the asset
+    fn bundle(&self, kind: AssetKind, id: &str) -> Option<&Bundle> {
+        let path = self.relative_path(kind, id);
+        self.bundles.iter().rev().find(|bundle| bundle.contains(&path))
+    }
+
+    /// File of the asset in the asset root
+    pub fn path(&self, kind: AssetKind, id: &str) -> PathBuf {
+        self.root.join(self.relative_path(kind, id))
+    }
+
+    pub fn exists(&self, kind: AssetKind, id: &str) -> bool {
+        self.bundle(kind, id).is_some() || self.path(kind, id).is_file()
+    }
+
+    /// Where the asset is read from, for messages
+    pub fn source(&self, kind: AssetKind, id: &str) -> String {
+        match self.bundle(kind, id) {
+            Some(bundle) => format!("{}:{}", bundle.name(), self.relative_path(kind, id)),
+            None => self.path(kind, id).display().to_string(),
+        }
+    }
+
+    /// Reads the asset or takes it from the cache
+    pub fn read(&self, kind: AssetKind, id: &str) -> Result<Rc<Vec<u8>>, AssetError> {
+        let key = (kind, String::from(id));
+
+        if let Some(data) = self.cache.borrow().get(&key) {
+            return Ok(data.clone());
+        }
+
+        let data = match self.bundle(kind, id) {
+            Some(bundle) => bundle.get(&self.relative_path(kind, id)).unwrap().to_vec(),
+            None => {
+                let path = self.path(kind, id);
+                fs::read(&path).map_err(|error| AssetError {
+                    kind,
+                    id: String::from(id),
+                    path,
+                    error,
+                })?
+            }
+        };
+
+        let data = Rc::new(data);
+        self.cache.borrow_mut().insert(key, data.clone());
+
+        Ok(data)
+    }
+
+    pub fn read_text(&self, kind: AssetKind, id: &str) -> Result<String, AssetError> {
+        let data = self.read(kind, id)?;
+
+        String::from_utf8(data.to_vec()).map_err(|err| AssetError {
+            kind,
+            id: String::from(id),
+            path: self.path(kind, id),
+            error: io::Error::new(io::ErrorKind::InvalidData, err),
+        })
+    }
+
+    /// Ids of the assets of the kind with the default extension, from the asset root and the bundles
+    pub fn list(&self, kind: AssetKind) -> Vec<String> {
+        let suffix = format!(".{}", kind.extension());
+        let prefix = format!("{}/", kind.dir());
+
+        let files = fs::read_dir(self.root.join(kind.dir()))
+            .map(|entries| entries
+                .filter_map(|entry| entry.ok())
+                .filter_map(|entry| entry.file_name().into_string().ok())
+                .collect::<Vec<String>>())
+            .unwrap_or_default();
+
+        let bundled = self.bundles.iter()
+            .flat_map(|bundle| bundle.paths())
+            .filter_map(|path| path.strip_prefix(prefix.as_str()))
+            .map(String::from);
+
+        let mut ids: Vec<String> = files.into_iter()
+            .chain(bundled)
+            .filter_map(|name| name.strip_suffix(suffix.as_str()).map(String::from))
+            .filter(|id| !id.contains('/'))
+            .collect();
+
+        ids.sort();
+        ids.dedup();
+        ids
+    }
+
+    /// Drops the cached copy, so the asset is read again next time
+    pub fn invalidate(&self, kind: AssetKind, id: &str) {
+        self.cache.borrow_mut().remove(&(kind, String::from(id)));
+    }
+
+    /// Returns a line for each asset which is missing
+    pub fn check(&self, required: &[(AssetKind, &str)]) -> Vec<String> {
+        required.iter()
+            .filter(|(kind, id)| !self.exists(*kind, id))
+            .map(|(kind, id)| format!("{} `{}`: {} not found", kind, id, self.path(*kind, id).display()))
+            .collect()
+    }
+}
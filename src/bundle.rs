@@ -0,0 +1,285 @@
+//! Asset bundles. A bundle packs an asset directory (maps, tilesets, sounds etc.)
+//! into one file, so a mod or a campaign can be shipped as a single file.
+//!
+//! Layout (all numbers are little endian):
+//! ```text
+//! magic "RPGBNDL\0", version u32, entry count u32
+//! entries: path length u16, path (utf-8, `/` separated), offset u64, size u64, checksum u64
+//! data of all entries, offsets are relative to the start of the data
+//! ```
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+const MAGIC: &'static [u8; 8] = b"RPGBNDL\0";
+const VERSION: u32 = 1;
+
+/// Extension of the bundle files
+pub const EXTENSION: &'static str = "bundle";
+
+/// Start value of the 64-bit FNV-1a hash
+pub const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+
+/// Continues the 64-bit FNV-1a `hash` with the data
+pub fn fnv1a(mut hash: u64, data: &[u8]) -> u64 {
+    for byte in data {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+
+    hash
+}
+
+/// FNV-1a hash of the entry data
+pub fn checksum(data: &[u8]) -> u64 {
+    fnv1a(FNV_OFFSET, data)
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+struct Entry {
+    offset: usize,
+    size: usize,
+    checksum: u64,
+}
+
+/// Reads the numbers of the bundle header one by one
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> io::Result<&'a [u8]> {
+        if self.data.len() - self.position < count {
+            return Err(invalid(String::from("unexpected end of the bundle header")));
+        }
+
+        let bytes = &self.data[self.position..self.position + count];
+        self.position += count;
+
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        let mut bytes = [0; 2];
+        bytes.copy_from_slice(self.take(2)?);
+        Ok(u16::from_le_bytes(bytes))
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+}
+
+pub struct Bundle {
+    name: String,
+    data: Vec<u8>,
+    /// Start of the entry data
+    data_start: usize,
+    entries: BTreeMap<String, Entry>,
+}
+
+impl Bundle {
+    /// Reads the whole bundle into memory. Entry data is checked with `verify`.
+    pub fn open(path: &Path) -> io::Result<Bundle> {
+        let data = fs::read(path)?;
+        let name = path.file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| path.display().to_string());
+
+        Bundle::from_bytes(name, data)
+    }
+
+    pub fn from_bytes(name: String, data: Vec<u8>) -> io::Result<Bundle> {
+        let mut reader = Reader { data: &data, position: 0 };
+
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(invalid(format!("{} is not a bundle", name)));
+        }
+
+        let version = reader.u32()?;
+        if version != VERSION {
+            return Err(invalid(format!("{}: unsupported bundle version {}", name, version)));
+        }
+
+        let count = reader.u32()?;
+        let mut entries = BTreeMap::new();
+
+        for _ in 0..count {
+            let length = reader.u16()? as usize;
+            let path = String::from_utf8(reader.take(length)?.to_vec())
+                .map_err(|_| invalid(format!("{}: entry path is not utf-8", name)))?;
+
+            let entry = Entry {
+                offset: reader.u64()? as usize,
+                size: reader.u64()? as usize,
+                checksum: reader.u64()?,
+            };
+
+            entries.insert(path, entry);
+        }
+
+        let data_start = reader.position;
+        let data_size = data.len() - data_start;
+
+        for (path, entry) in entries.iter() {
+            if entry.offset > data_size || data_size - entry.offset < entry.size {
+                return Err(invalid(format!("{}: entry {} is out of the bundle", name, path)));
+            }
+        }
+
+        Ok(Bundle {
+            name,
+            data,
+            data_start,
+            entries,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// `path` is relative to the asset root, e.g. `maps/start.map`
+    pub fn get(&self, path: &str) -> Option<&[u8]> {
+        self.entries.get(path).map(|entry| {
+            let start = self.data_start + entry.offset;
+            &self.data[start..start + entry.size]
+        })
+    }
+
+    pub fn contains(&self, path: &str) -> bool {
+        self.entries.contains_key(path)
+    }
+
+    pub fn paths(&self) -> impl Iterator<Item = &String> {
+        self.entries.keys()
+    }
+
+    /// Returns the entries with a wrong checksum
+    pub fn verify(&self) -> Vec<String> {
+        self.entries.iter()
+            .filter(|(path, entry)| checksum(self.get(path).unwrap()) != entry.checksum)
+            .map(|(path, _)| format!("{}: checksum mismatch", path))
+            .collect()
+    }
+}
+
+/// Files of the directory with their `/` separated paths relative to `root`
+fn collect_files(root: &Path, dir: &Path, files: &mut Vec<(String, Vec<u8>)>) -> io::Result<()> {
+    let mut dir_entries: Vec<_> = fs::read_dir(dir)?.collect::<io::Result<_>>()?;
+    // Same directory always gives the same bundle
+    dir_entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in dir_entries {
+        let path = entry.path();
+
+        if path.is_dir() {
+            collect_files(root, &path, files)?;
+            continue;
+        }
+
+        let relative: Vec<String> = path.strip_prefix(root).unwrap()
+            .components()
+            .map(|part| part.as_os_str().to_string_lossy().into_owned())
+            .collect();
+
+        files.push((relative.join("/"), fs::read(&path)?));
+    }
+
+    Ok(())
+}
+
+/// Packs every file of `dir` into a bundle. Returns the number of packed files.
+pub fn pack(dir: &Path, output: &Path) -> io::Result<usize> {
+    let mut files = vec![];
+    collect_files(dir, dir, &mut files)?;
+
+    let mut header = vec![];
+    header.extend_from_slice(MAGIC);
+    header.extend_from_slice(&VERSION.to_le_bytes());
+    header.extend_from_slice(&(files.len() as u32).to_le_bytes());
+
+    let mut offset = 0;
+    for (path, data) in files.iter() {
+        if path.len() > u16::max_value() as usize {
+            return Err(invalid(format!("path {} is too long", path)));
+        }
+
+        header.extend_from_slice(&(path.len() as u16).to_le_bytes());
+        header.extend_from_slice(path.as_bytes());
+        header.extend_from_slice(&(offset as u64).to_le_bytes());
+        header.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        header.extend_from_slice(&checksum(data).to_le_bytes());
+
+        offset += data.len();
+    }
+
+    let mut file = io::BufWriter::new(fs::File::create(output)?);
+    file.write_all(&header)?;
+    for (_, data) in files.iter() {
+        file.write_all(data)?;
+    }
+    file.flush()?;
+
+    Ok(files.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packed_directory_reads_back() {
+        let base = std::env::temp_dir().join(format!("rust-rpg-test-{}-bundle", std::process::id()));
+        let dir = base.join("assets");
+        fs::create_dir_all(dir.join("maps")).unwrap();
+        fs::write(dir.join("maps").join("start.map"), b"#.#").unwrap();
+        fs::write(dir.join("readme.txt"), b"hello").unwrap();
+
+        let output = base.join("test.bundle");
+        assert_eq!(pack(&dir, &output).unwrap(), 2);
+
+        let bundle = Bundle::open(&output).unwrap();
+        assert_eq!(bundle.name(), "test.bundle");
+        assert_eq!(bundle.paths().cloned().collect::<Vec<String>>(), vec!["maps/start.map", "readme.txt"]);
+        assert_eq!(bundle.get("maps/start.map"), Some(&b"#.#"[..]));
+        assert_eq!(bundle.get("readme.txt"), Some(&b"hello"[..]));
+        assert!(bundle.verify().is_empty());
+
+        // The data of the last entry ends the file
+        let mut data = fs::read(&output).unwrap();
+        *data.last_mut().unwrap() ^= 0x20;
+        let broken = Bundle::from_bytes(String::from("broken"), data).unwrap();
+
+        assert_eq!(broken.verify(), vec!["readme.txt: checksum mismatch"]);
+
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn other_files_are_not_bundles() {
+        assert!(Bundle::from_bytes(String::from("text"), b"not a bundle".to_vec()).is_err());
+        assert!(Bundle::from_bytes(String::from("short"), MAGIC[..4].to_vec()).is_err());
+    }
+
+    #[test]
+    fn checksum_is_fnv1a() {
+        assert_eq!(checksum(b""), 0xcbf29ce484222325);
+        assert_eq!(checksum(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(fnv1a(checksum(b"foo"), b"bar"), checksum(b"foobar"));
+    }
+}
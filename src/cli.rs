@@ -0,0 +1,208 @@
+//! Command line of the game binary
+
+use std::path::PathBuf;
+use log::LevelFilter;
+
+use crate::game::MAX_FPS;
+use crate::render::ColorMode;
+
+pub const USAGE: &'static str = "Usage:
+  rust-rpg [options]          play
+  rust-rpg pack <dir> <file>  pack an asset directory into a bundle
+  rust-rpg verify <file>      check the bundle checksums
+
+Options:
+  --log-file <file>     log file (default: game.log or `file` of log.cfg)
+  --log-level <level>   off, error, warn, info, debug or trace
+                        (default: info or `level` of log.cfg)
+  --skip-splash         start in the main menu
+  --map <id>            start on the map, e.g. `start`
+  --load <file>         start from the saved game
+  --assets <dir>        asset root directory
+  --bundle <file>       mount the bundle over the assets, can be repeated
+  --no-sound            disable the audio output
+  --color <mode>        auto, truecolor, 256, 16 or mono (default: auto)
+  --fps <fps>           frames per second, up to 1000 (default: 30)
+  --seed <seed>         seed of the random numbers (default: a new one,
+                        or the one of the loaded game)
+  --record <file>       record the session to the file
+  --replay <file>       play the recorded session
+  --speed <x>           speed of the replay (default: 1)
+  --headless            replay without the screen and check the end state
+  -h, --help            print this help
+  -V, --version         print the version";
+
+pub enum Command {
+    Play(Options),
+    Pack { dir: PathBuf, output: PathBuf },
+    Verify(PathBuf),
+    Help,
+    Version,
+}
+
+pub struct Options {
+    /// Overrides the logging config
+    pub log_file: Option<PathBuf>,
+    pub log_level: Option<LevelFilter>,
+    pub skip_splash: bool,
+    pub map: Option<String>,
+    pub save: Option<PathBuf>,
+    pub assets: Option<PathBuf>,
+    pub bundles: Vec<PathBuf>,
+    pub no_sound: bool,
+    pub color: ColorMode,
+    pub fps: Option<u64>,
+    pub seed: Option<u64>,
+    pub record: Option<PathBuf>,
+    pub replay: Option<PathBuf>,
+    pub speed: f32,
+    pub headless: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            log_file: None,
+            log_level: None,
+            skip_splash: false,
+            map: None,
+            save: None,
+            assets: None,
+            bundles: vec![],
+            no_sound: false,
+            color: ColorMode::Auto,
+            fps: None,
+            seed: None,
+            record: None,
+            replay: None,
+            speed: 1.0,
+            headless: false,
+        }
+    }
+}
+
+/// Gives the values of the options, both `--name value` and `--name=value` are accepted
+struct Args<'a> {
+    args: std::slice::Iter<'a, String>,
+}
+
+impl<'a> Args<'a> {
+    fn value(&mut self, name: &str, inline: Option<&'a str>) -> Result<&'a str, String> {
+        match inline {
+            Some(value) => Ok(value),
+            None => self.args.next()
+                .map(String::as_str)
+                .ok_or(format!("{} needs a value", name)),
+        }
+    }
+
+    fn parsed<T: std::str::FromStr>(&mut self, name: &str, inline: Option<&'a str>) -> Result<T, String> {
+        let value = self.value(name, inline)?;
+        value.parse().map_err(|_| format!("bad value of {}: {}", name, value))
+    }
+}
+
+/// Parses the arguments without the program name
+pub fn parse(args: &[String]) -> Result<Command, String> {
+    match args.first().map(String::as_str) {
+        Some("pack") => {
+            return match &args[1..] {
+                [dir, output] => Ok(Command::Pack { dir: PathBuf::from(dir), output: PathBuf::from(output) }),
+                _ => Err(String::from("pack needs a directory and a bundle file")),
+            };
+        },
+        Some("verify") => {
+            return match &args[1..] {
+                [file] => Ok(Command::Verify(PathBuf::from(file))),
+                _ => Err(String::from("verify needs a bundle file")),
+            };
+        },
+        _ => {}
+    }
+
+    let mut options = Options::default();
+    let mut args = Args { args: args.iter() };
+
+    while let Some(arg) = args.args.next() {
+        let (name, inline) = match arg.find('=') {
+            Some(index) if arg.starts_with("--") => (&arg[..index], Some(&arg[index + 1..])),
+            _ => (arg.as_str(), None),
+        };
+
+        match name {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-V" | "--version" => return Ok(Command::Version),
+            "--log-file" => options.log_file = Some(PathBuf::from(args.value(name, inline)?)),
+            "--log-level" => options.log_level = Some(args.parsed(name, inline)?),
+            "--skip-splash" => options.skip_splash = true,
+            "--map" => options.map = Some(String::from(args.value(name, inline)?)),
+            "--load" => options.save = Some(PathBuf::from(args.value(name, inline)?)),
+            "--assets" => options.assets = Some(PathBuf::from(args.value(name, inline)?)),
+            "--bundle" => options.bundles.push(PathBuf::from(args.value(name, inline)?)),
+            "--no-sound" => options.no_sound = true,
+            "--color" => options.color = args.parsed(name, inline)?,
+            "--fps" => {
+                let fps: u64 = args.parsed(name, inline)?;
+                if fps == 0 || fps > MAX_FPS {
+                    return Err(format!("--fps must be from 1 to {}", MAX_FPS));
+                }
+
+                options.fps = Some(fps);
+            },
+            "--seed" => options.seed = Some(args.parsed(name, inline)?),
+            "--record" => options.record = Some(PathBuf::from(args.value(name, inline)?)),
+            "--replay" => options.replay = Some(PathBuf::from(args.value(name, inline)?)),
+            "--speed" => {
+                let speed: f32 = args.parsed(name, inline)?;
+                if !(speed > 0.0) {
+                    return Err(String::from("--speed must be positive"));
+                }
+
+                options.speed = speed;
+            },
+            "--headless" => options.headless = true,
+            _ => return Err(format!("unknown argument {}", arg)),
+        }
+    }
+
+    if options.map.is_some() && options.save.is_some() {
+        return Err(String::from("--map and --load can't be used together"));
+    }
+
+    if options.record.is_some() && options.save.is_some() {
+        return Err(String::from("a game started with --load can't be recorded"));
+    }
+
+    // The recording has its own start and seed
+    if options.replay.is_some() && (options.map.is_some() || options.save.is_some() || options.seed.is_some()
+        || options.record.is_some()) {
+        return Err(String::from("--replay can't be used with --map, --load, --seed or --record"));
+    }
+
+    if options.headless && options.replay.is_none() {
+        return Err(String::from("--headless needs --replay"));
+    }
+
+    Ok(Command::Play(options))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fps(value: &str) -> Result<Option<u64>, String> {
+        match parse(&[String::from("--fps"), String::from(value)])? {
+            Command::Play(options) => Ok(options.fps),
+            _ => panic!("--fps is not an option of the play command"),
+        }
+    }
+
+    #[test]
+    fn fps_is_bounded() {
+        assert_eq!(fps("1"), Ok(Some(1)));
+        assert_eq!(fps("1000"), Ok(Some(1000)));
+        assert!(fps("0").is_err());
+        assert!(fps("1001").is_err());
+        assert!(fps("-5").is_err());
+    }
+}
@@ -0,0 +1,138 @@
+//! Simple `key = value` configuration files
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+use lazy_static::lazy_static;
+use log::warn;
+
+const APP_NAME: &'static str = "rust-rpg";
+
+lazy_static! {
+    /// Set by `isolate`, used instead of the directory of the user
+    static ref ISOLATED_DIR: Mutex<Option<PathBuf>> = Mutex::new(None);
+}
+
+/// Directory for the user settings.
+/// `$XDG_CONFIG_HOME/rust-rpg`, `$HOME/.config/rust-rpg` or the current directory.
+pub fn config_dir() -> PathBuf {
+    if let Some(dir) = ISOLATED_DIR.lock().ok().and_then(|dir| dir.clone()) {
+        return dir;
+    }
+
+    if let Some(dir) = std::env::var_os("XDG_CONFIG_HOME") {
+        return PathBuf::from(dir).join(APP_NAME);
+    }
+
+    if let Some(home) = std::env::var_os("HOME") {
+        return PathBuf::from(home).join(".config").join(APP_NAME);
+    }
+
+    PathBuf::from(".")
+}
+
+/// Switches the settings (and everything else in `config_dir`: saves, themes,
+/// mods) to a new empty temporary directory. Nothing of the user is read or
+/// overwritten then, e.g. by a replay. Returns the directory.
+pub fn isolate() -> io::Result<PathBuf> {
+    let dir = std::env::temp_dir().join(format!("{}-{}", APP_NAME, std::process::id()));
+    if dir.exists() {
+        fs::remove_dir_all(&dir)?;
+    }
+
+    fs::create_dir_all(&dir)?;
+
+    if let Ok(mut isolated) = ISOLATED_DIR.lock() {
+        *isolated = Some(dir.clone());
+    }
+
+    Ok(dir)
+}
+
+pub struct Config {
+    path: PathBuf,
+    values: BTreeMap<String, String>,
+}
+
+impl Config {
+    /// Empty config which will be saved to `path`
+    pub fn new(path: &Path) -> Self {
+        Config {
+            path: path.to_path_buf(),
+            values: BTreeMap::new(),
+        }
+    }
+
+    /// Loads the config. A missing or unreadable file gives an empty config.
+    pub fn load(path: &Path) -> Self {
+        let mut config = Config::new(path);
+
+        match fs::read_to_string(path) {
+            Ok(text) => config.parse(&text),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {},
+            Err(err) => warn!("Failed to read config {}: {}", path.display(), err),
+        }
+
+        config
+    }
+
+    /// Parses `key = value` lines. Empty lines and lines starting with `#` are skipped.
+    pub fn parse(&mut self, text: &str) {
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            match line.find('=') {
+                Some(index) => {
+                    let key = line[..index].trim();
+                    let value = line[index + 1..].trim();
+                    self.values.insert(String::from(key), String::from(value));
+                },
+                None => warn!("{}:{}: expected `key = value`", self.path.display(), number + 1),
+            }
+        }
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let mut text = String::new();
+        for (key, value) in &self.values {
+            text.push_str(format!("{} = {}\n", key, value).as_str());
+        }
+
+        fs::write(&self.path, text)
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(|value| value.as_str())
+    }
+
+    /// Parses the value. Logs a warning if it is malformed.
+    pub fn get_parsed<T: FromStr>(&self, key: &str) -> Option<T> {
+        let value = self.get(key)?;
+
+        match value.parse() {
+            Ok(value) => Some(value),
+            Err(_) => {
+                warn!("{}: bad value of {}: {}", self.path.display(), key, value);
+                None
+            }
+        }
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.values.iter().map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    pub fn set<T: ToString>(&mut self, key: &str, value: T) {
+        self.values.insert(String::from(key), value.to_string());
+    }
+}
@@ -0,0 +1,86 @@
+//! Errors of the GAME
+
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::path::PathBuf;
+
+use crate::assets::AssetError;
+
+#[derive(Debug)]
+pub enum Error {
+    /// An asset can't be read
+    Asset(AssetError),
+    /// A map file is malformed. `line` is 1-based.
+    MapParse { map: String, line: Option<usize>, message: String },
+    /// A sound can't be loaded or played
+    Audio { sound: String, message: String },
+    /// The terminal can't be set up or written to
+    Terminal(io::Error),
+    /// A saved game can't be read or written
+    Save { path: PathBuf, message: String },
+    /// A recording can't be read or written, or its replay ended differently
+    Replay { path: PathBuf, message: String },
+    /// The GAME panicked, the message is the crash report
+    Panic(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// Short name of the error kind for the error dialog
+    pub fn title(&self) -> &'static str {
+        match self {
+            Error::Asset(_) => "Asset error",
+            Error::MapParse { .. } => "Broken map",
+            Error::Audio { .. } => "Audio error",
+            Error::Terminal(_) => "Terminal error",
+            Error::Save { .. } => "Save error",
+            Error::Replay { .. } => "Replay error",
+            Error::Panic(_) => "Crash",
+        }
+    }
+
+    /// Key of the translated title, e.g. `error.map`
+    pub fn title_key(&self) -> &'static str {
+        match self {
+            Error::Asset(_) => "error.asset",
+            Error::MapParse { .. } => "error.map",
+            Error::Audio { .. } => "error.audio",
+            Error::Terminal(_) => "error.terminal",
+            Error::Save { .. } => "error.save",
+            Error::Replay { .. } => "error.replay",
+            Error::Panic(_) => "error.crash",
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Asset(err) => write!(f, "{}", err),
+            Error::MapParse { map, line: Some(line), message } => write!(f, "map {}, line {}: {}", map, line, message),
+            Error::MapParse { map, line: None, message } => write!(f, "map {}: {}", map, message),
+            Error::Audio { sound, message } => write!(f, "sound {}: {}", sound, message),
+            Error::Terminal(err) => write!(f, "terminal: {}", err),
+            Error::Save { path, message } => write!(f, "save {}: {}", path.display(), message),
+            Error::Replay { path, message } => write!(f, "recording {}: {}", path.display(), message),
+            Error::Panic(report) => f.write_str(report),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Asset(err) => Some(err),
+            Error::Terminal(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<AssetError> for Error {
+    fn from(err: AssetError) -> Self {
+        Error::Asset(err)
+    }
+}
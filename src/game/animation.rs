@@ -0,0 +1,252 @@
+//! Non-blocking animations. Every animation is advanced by the game loop tick
+//! from `PlayState::update` and only queried during rendering.
+
+use std::time::Duration;
+use termion::color;
+
+use crate::unicode;
+
+pub fn lerp_color(start: color::Rgb, end: color::Rgb, k: f32) -> color::Rgb {
+
+    let r = start.0 as f32 + ((end.0 as f32 - start.0 as f32) * k);
+    let g = start.1 as f32 + ((end.1 as f32 - start.1 as f32) * k);
+    let b = start.2 as f32 + ((end.2 as f32 - start.2 as f32) * k);
+
+    color::Rgb(r as u8, g as u8, b as u8)
+}
+
+/// Values that can be interpolated by a tween
+pub trait Lerp: Copy {
+    fn lerp(start: Self, end: Self, k: f32) -> Self;
+}
+
+impl Lerp for f32 {
+    fn lerp(start: Self, end: Self, k: f32) -> Self {
+        start + (end - start) * k
+    }
+}
+
+impl Lerp for u8 {
+    fn lerp(start: Self, end: Self, k: f32) -> Self {
+        f32::lerp(start as f32, end as f32, k).round() as u8
+    }
+}
+
+impl Lerp for u16 {
+    fn lerp(start: Self, end: Self, k: f32) -> Self {
+        f32::lerp(start as f32, end as f32, k).round() as u16
+    }
+}
+
+/// Screen position (x, y)
+impl Lerp for (u16, u16) {
+    fn lerp(start: Self, end: Self, k: f32) -> Self {
+        (u16::lerp(start.0, end.0, k), u16::lerp(start.1, end.1, k))
+    }
+}
+
+impl Lerp for color::Rgb {
+    fn lerp(start: Self, end: Self, k: f32) -> Self {
+        lerp_color(start, end, k)
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum Easing {
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+}
+
+impl Easing {
+    fn apply(&self, k: f32) -> f32 {
+        match self {
+            Easing::Linear => k,
+            Easing::EaseIn => k * k,
+            Easing::EaseOut => k * (2.0 - k),
+            Easing::EaseInOut => {
+                if k < 0.5 {
+                    2.0 * k * k
+                } else {
+                    -1.0 + (4.0 - 2.0 * k) * k
+                }
+            }
+        }
+    }
+}
+
+pub trait Animation {
+    fn advance(&mut self, dt: Duration);
+    fn is_finished(&self) -> bool;
+    /// Jumps to the end of the animation
+    fn finish(&mut self);
+}
+
+/// Elapsed part of `duration` in range [0; 1]
+fn progress(elapsed: Duration, duration: Duration) -> f32 {
+    if duration.as_nanos() == 0 {
+        return 1.0;
+    }
+
+    (elapsed.as_secs_f32() / duration.as_secs_f32()).min(1.0)
+}
+
+/// Interpolates a value from `start` to `end` during `duration`
+pub struct Tween<T: Lerp> {
+    start: T,
+    end: T,
+    duration: Duration,
+    elapsed: Duration,
+    easing: Easing,
+}
+
+impl<T: Lerp> Tween<T> {
+    pub fn new(start: T, end: T, duration: Duration) -> Self {
+        Tween {
+            start,
+            end,
+            duration,
+            elapsed: Duration::default(),
+            easing: Easing::Linear,
+        }
+    }
+
+    pub fn with_easing(mut self, easing: Easing) -> Self {
+        self.easing = easing;
+        self
+    }
+
+    pub fn progress(&self) -> f32 {
+        progress(self.elapsed, self.duration)
+    }
+
+    pub fn value(&self) -> T {
+        T::lerp(self.start, self.end, self.easing.apply(self.progress()))
+    }
+}
+
+impl<T: Lerp> Animation for Tween<T> {
+    fn advance(&mut self, dt: Duration) {
+        self.elapsed = (self.elapsed + dt).min(self.duration);
+    }
+
+    fn is_finished(&self) -> bool {
+        self.elapsed >= self.duration
+    }
+
+    fn finish(&mut self) {
+        self.elapsed = self.duration;
+    }
+}
+
+/// Finishes after `duration`. Used to delay something without blocking the loop.
+pub struct Timer {
+    duration: Duration,
+    elapsed: Duration,
+}
+
+impl Timer {
+    pub fn new(duration: Duration) -> Self {
+        Timer {
+            duration,
+            elapsed: Duration::default(),
+        }
+    }
+}
+
+impl Animation for Timer {
+    fn advance(&mut self, dt: Duration) {
+        self.elapsed = (self.elapsed + dt).min(self.duration);
+    }
+
+    fn is_finished(&self) -> bool {
+        self.elapsed >= self.duration
+    }
+
+    fn finish(&mut self) {
+        self.elapsed = self.duration;
+    }
+}
+
+/// Toggles visibility every half of the `period`. Never finishes.
+pub struct Blink {
+    period: Duration,
+    elapsed: Duration,
+}
+
+impl Blink {
+    pub fn new(period: Duration) -> Self {
+        Blink {
+            period,
+            elapsed: Duration::default(),
+        }
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.period.as_nanos() == 0 || self.elapsed < self.period / 2
+    }
+}
+
+impl Animation for Blink {
+    fn advance(&mut self, dt: Duration) {
+        if self.period.as_nanos() == 0 {
+            return;
+        }
+
+        let elapsed = (self.elapsed + dt).as_nanos() % self.period.as_nanos();
+        self.elapsed = Duration::from_nanos(elapsed as u64);
+    }
+
+    fn is_finished(&self) -> bool {
+        false
+    }
+
+    fn finish(&mut self) {}
+}
+
+/// Reveals the text one grapheme at a time
+pub struct Typewriter {
+    text: String,
+    chars_per_second: f32,
+    revealed: f32,
+}
+
+impl Typewriter {
+    pub fn new(text: &str, chars_per_second: f32) -> Self {
+        Typewriter {
+            text: String::from(text),
+            chars_per_second,
+            revealed: 0.0,
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn visible_text(&self) -> &str {
+        let length: usize = unicode::graphemes(&self.text)
+            .take(self.revealed as usize)
+            .map(str::len)
+            .sum();
+
+        &self.text[..length]
+    }
+}
+
+impl Animation for Typewriter {
+    fn advance(&mut self, dt: Duration) {
+        if !self.is_finished() {
+            self.revealed += dt.as_secs_f32() * self.chars_per_second;
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        self.revealed as usize >= unicode::graphemes(&self.text).count()
+    }
+
+    fn finish(&mut self) {
+        self.revealed = unicode::graphemes(&self.text).count() as f32;
+    }
+}
@@ -0,0 +1,351 @@
+//! Developer console. A drop-down command line (toggled with `) which runs
+//! the commands registered by the GAME subsystems.
+
+use std::collections::{BTreeMap, VecDeque};
+use termion::event::{Key, MouseButton};
+
+use crate::game::{GlobalState, PlayResult, PlayState};
+use crate::game::input::MouseEvent;
+use crate::render::Render;
+use crate::rng::Random;
+use crate::unicode;
+
+/// Rows of the console including the input line
+const CONSOLE_HEIGHT: u16 = 12;
+const MAX_OUTPUT: usize = 200;
+const MAX_HISTORY: usize = 100;
+/// Output lines scrolled by one step of the mouse wheel
+const SCROLL_STEP: usize = 3;
+
+/// Commands handled by the console itself
+const CONSOLE_COMMANDS: [&'static str; 2] = ["help", "clear"];
+
+pub const TOGGLE_KEY: Key = Key::Char('`');
+
+/// What a command can reach
+pub struct Context<'a> {
+    pub game_state: &'a mut GlobalState,
+    /// The state on the top of the stack
+    pub state: &'a mut dyn PlayState,
+    /// Applied to the state stack after the command
+    pub result: PlayResult,
+}
+
+impl<'a> Context<'a> {
+    /// The top state if it has the type `T`
+    pub fn state<T: PlayState + 'static>(&mut self) -> Option<&mut T> {
+        self.state.as_any_mut().downcast_mut::<T>()
+    }
+}
+
+/// Runs the command with the arguments. Returns the text printed to the console.
+pub type Handler = Box<dyn Fn(&mut Context, &[&str]) -> Result<String, String>>;
+
+pub struct Command {
+    pub usage: &'static str,
+    pub help: &'static str,
+    handler: Handler,
+}
+
+pub struct Registry {
+    commands: BTreeMap<&'static str, Command>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Registry {
+            commands: BTreeMap::new(),
+        }
+    }
+
+    /// Adds the command. `usage` is e.g. `teleport <x> <y>`, its first word is the command name.
+    pub fn register<F>(&mut self, usage: &'static str, help: &'static str, handler: F)
+        where F: Fn(&mut Context, &[&str]) -> Result<String, String> + 'static {
+
+        let name = usage.split_whitespace().next().expect("Empty command usage");
+        self.commands.insert(name, Command {
+            usage,
+            help,
+            handler: Box::new(handler),
+        });
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &&'static str> {
+        self.commands.keys()
+    }
+
+    pub fn execute(&self, context: &mut Context, line: &str) -> Result<String, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+
+        let (name, args) = match words.split_first() {
+            Some(split) => split,
+            None => return Ok(String::new()),
+        };
+
+        match self.commands.get(name) {
+            Some(command) => (command.handler)(context, args)
+                .map_err(|err| format!("{}\nusage: {}", err, command.usage)),
+            None => Err(format!("unknown command {}, try `help`", name)),
+        }
+    }
+}
+
+/// Parses the argument of a command
+pub fn arg<T: std::str::FromStr>(args: &[&str], index: usize, name: &str) -> Result<T, String> {
+    let value = args.get(index).ok_or(format!("{} is missing", name))?;
+    value.parse().map_err(|_| format!("bad {}: {}", name, value))
+}
+
+/// Commands which don't belong to any subsystem
+fn register_builtin(registry: &mut Registry) {
+    registry.register("pop", "pop the current state", |context, _| {
+        context.result = PlayResult::Pop;
+        Ok(String::from("popped"))
+    });
+
+    registry.register("push <state>", "push a state: menu, settings, map or splash", |context, args| {
+        let name: String = arg(args, 0, "state")?;
+        let state: Box<dyn PlayState> = match name.as_str() {
+            "menu" => Box::new(super::menu::PlayState::new(context.game_state)),
+            "settings" => Box::new(super::settings::PlayState::new(context.game_state)),
+            "map" => Box::new(super::map::PlayState::load(context.game_state, super::map::MAP_START)
+                .map_err(|err| err.to_string())?),
+            "splash" => Box::new(super::start_splash::PlayState::new(context.game_state)),
+            _ => return Err(format!("unknown state {}", name)),
+        };
+
+        context.result = PlayResult::Push(state);
+        Ok(format!("pushed {}", name))
+    });
+
+    registry.register("seed [seed]", "show the seed or restart the random streams from a new one", |context, args| {
+        if !args.is_empty() {
+            context.game_state.random = Random::new(arg(args, 0, "seed")?);
+        }
+
+        Ok(format!("seed {}", context.game_state.random.seed()))
+    });
+}
+
+pub struct Console {
+    registry: Registry,
+    visible: bool,
+    input: String,
+    output: VecDeque<String>,
+    history: Vec<String>,
+    /// Position in the history while browsing it with Up/Down
+    history_index: Option<usize>,
+    /// Output lines hidden below the view, scrolled with the mouse wheel
+    scroll: usize,
+}
+
+impl Console {
+    pub fn new() -> Self {
+        let mut registry = Registry::new();
+        register_builtin(&mut registry);
+        super::map::register_commands(&mut registry);
+        super::sound::register_commands(&mut registry);
+
+        Console {
+            registry,
+            visible: false,
+            input: String::new(),
+            output: VecDeque::new(),
+            history: vec![],
+            history_index: None,
+            scroll: 0,
+        }
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+    }
+
+    fn print(&mut self, text: &str) {
+        for line in text.lines() {
+            if self.output.len() == MAX_OUTPUT {
+                self.output.pop_front();
+            }
+
+            self.output.push_back(String::from(line));
+        }
+
+        self.scroll = 0;
+    }
+
+    fn help(&self) -> String {
+        let mut lines = vec![
+            format!("{:<24} {}", "help", "list the commands"),
+            format!("{:<24} {}", "clear", "clear the console"),
+        ];
+
+        lines.extend(self.registry.commands.values()
+            .map(|command| format!("{:<24} {}", command.usage, command.help)));
+
+        lines.join("\n")
+    }
+
+    /// Completes the command name. Several matches are printed and their common prefix is completed.
+    fn complete(&mut self) {
+        if self.input.contains(' ') {
+            return;
+        }
+
+        let mut names = self.registry.names()
+            .map(|name| *name)
+            .chain(CONSOLE_COMMANDS.iter().cloned())
+            .filter(|name| name.starts_with(self.input.as_str()))
+            .collect::<Vec<&str>>();
+        names.sort();
+
+        let (first, last) = match (names.first(), names.last()) {
+            (Some(first), Some(last)) => (*first, *last),
+            _ => return,
+        };
+
+        if names.len() == 1 {
+            self.input = format!("{} ", first);
+            return;
+        }
+
+        // The sorted names have the same common prefix as the first and the last one
+        let common = first.chars().zip(last.chars())
+            .take_while(|(a, b)| a == b)
+            .count();
+
+        self.input = first.chars().take(common).collect();
+        let list = names.join("  ");
+        self.print(&list);
+    }
+
+    fn browse_history(&mut self, older: bool) {
+        if self.history.is_empty() {
+            return;
+        }
+
+        let index = match (self.history_index, older) {
+            (None, true) => Some(self.history.len() - 1),
+            (None, false) => None,
+            (Some(index), true) => Some(index.saturating_sub(1)),
+            (Some(index), false) if index + 1 < self.history.len() => Some(index + 1),
+            (Some(_), false) => None,
+        };
+
+        self.history_index = index;
+        self.input = match index {
+            Some(index) => self.history[index].clone(),
+            None => String::new(),
+        };
+    }
+
+    fn execute(&mut self, game_state: &mut GlobalState, state: &mut dyn PlayState) -> PlayResult {
+        let line = std::mem::replace(&mut self.input, String::new());
+        self.history_index = None;
+
+        if line.trim().is_empty() {
+            return PlayResult::Still;
+        }
+
+        if self.history.last() != Some(&line) {
+            if self.history.len() == MAX_HISTORY {
+                self.history.remove(0);
+            }
+
+            self.history.push(line.clone());
+        }
+
+        self.print(&format!("> {}", line));
+
+        match line.split_whitespace().next() {
+            Some("help") => {
+                let help = self.help();
+                self.print(&help);
+                return PlayResult::Still;
+            },
+            Some("clear") => {
+                self.output.clear();
+                self.scroll = 0;
+                return PlayResult::Still;
+            },
+            _ => {}
+        }
+
+        log::info!("Console command: {}", line);
+
+        let mut context = Context {
+            game_state,
+            state,
+            result: PlayResult::Still,
+        };
+
+        match self.registry.execute(&mut context, &line) {
+            Ok(text) => self.print(&text),
+            Err(err) => self.print(&format!("error: {}", err)),
+        }
+
+        context.result
+    }
+
+    /// Handles the key while the console is open
+    pub fn on_key_pressed(&mut self, game_state: &mut GlobalState, state: &mut dyn PlayState,
+                          key: Key) -> PlayResult {
+        match key {
+            Key::Esc => self.visible = false,
+            key if key == TOGGLE_KEY => self.visible = false,
+            Key::Char('\n') => return self.execute(game_state, state),
+            Key::Char('\t') => self.complete(),
+            Key::Char(c) => self.input.push(c),
+            Key::Backspace => unicode::pop_grapheme(&mut self.input),
+            Key::Up => self.browse_history(true),
+            Key::Down => self.browse_history(false),
+            _ => {}
+        }
+
+        PlayResult::Still
+    }
+
+    /// Scrolls the output with the mouse wheel while the console is open
+    pub fn on_mouse(&mut self, event: MouseEvent) -> PlayResult {
+        match event {
+            MouseEvent::Press(MouseButton::WheelUp, _, _) => {
+                self.scroll = (self.scroll + SCROLL_STEP).min(self.output.len());
+            },
+            MouseEvent::Press(MouseButton::WheelDown, _, _) => {
+                self.scroll = self.scroll.saturating_sub(SCROLL_STEP);
+            },
+            _ => {}
+        }
+
+        PlayResult::Still
+    }
+
+    pub fn render(&self, render: &mut Render) {
+        let width = render.term_size.width as usize;
+        let height = CONSOLE_HEIGHT.min(render.term_size.height);
+        let rows = (height - 1) as usize;
+
+        let background = render.bg(render.theme.panel);
+        let scroll = self.scroll.min(self.output.len().saturating_sub(rows));
+        let skip = self.output.len().saturating_sub(rows + scroll);
+
+        for row in 0..rows {
+            let line = self.output.get(skip + row).map_or("", String::as_str);
+
+            render.set_cursor_position((1, row as u16 + 1));
+            render.draw_raw(format!("{}{}{}", background, render.fg(render.theme.panel_text),
+                                    unicode::fit(line, width)).as_str());
+        }
+
+        // The end of a long input is shown
+        let input = format!("> {}_", self.input);
+        let input = unicode::fit(unicode::tail(&input, width), width);
+
+        render.set_cursor_position((1, height));
+        render.draw_raw(format!("{}{}{}", render.bg(render.theme.border),
+                                render.fg(render.theme.panel_text), input).as_str());
+    }
+}
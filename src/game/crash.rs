@@ -0,0 +1,130 @@
+//! Crash handling. The panic hook writes a crash dump (the panic, the state stack and
+//! the last log lines), the GAME loop catches the panic, restores the terminal and
+//! saves the game to the recovery slot.
+
+use std::fs;
+use std::panic::PanicHookInfo;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use backtrace::Backtrace;
+use lazy_static::lazy_static;
+use log::{error, LevelFilter};
+
+use crate::config;
+use crate::logging::LogBuffer;
+
+/// Log lines written to the crash dump
+const DUMP_LOG_LINES: usize = 50;
+
+/// Name of the save written after a crash
+pub const RECOVERY_SAVE: &'static str = "recovery.sav";
+
+/// What the panic hook knows about the GAME
+struct CrashContext {
+    /// Names of the states, the top one is the last
+    states: Vec<String>,
+    log: Option<LogBuffer>,
+    /// Description of the last panic
+    report: Option<String>,
+    dump: Option<PathBuf>,
+}
+
+lazy_static! {
+    static ref CONTEXT: Mutex<CrashContext> = Mutex::new(CrashContext {
+        states: vec![],
+        log: None,
+        report: None,
+        dump: None,
+    });
+}
+
+pub fn set_states(states: Vec<String>) {
+    if let Ok(mut context) = CONTEXT.lock() {
+        context.states = states;
+    }
+}
+
+pub fn set_log_buffer(buffer: LogBuffer) {
+    if let Ok(mut context) = CONTEXT.lock() {
+        context.log = Some(buffer);
+    }
+}
+
+pub fn crashes_dir() -> PathBuf {
+    config::config_dir().join("crashes")
+}
+
+fn panic_message(info: &PanicHookInfo) -> String {
+    let payload = match (info.payload().downcast_ref::<String>(), info.payload().downcast_ref::<&str>()) {
+        (Some(message), _) => message.clone(),
+        (None, Some(message)) => String::from(*message),
+        (None, None) => String::from("Some critical error occurred!"),
+    };
+
+    match info.location() {
+        Some(location) => format!("{} in {}:{}", payload, location.file(), location.line()),
+        None => payload,
+    }
+}
+
+/// Doesn't print anything since the terminal is still in the raw mode here
+pub fn panic_hook(info: &PanicHookInfo) {
+    let message = panic_message(info);
+    let backtrace = Backtrace::new();
+
+    error!("Panic: {}", message);
+    error!("Backtrace: {:?}", backtrace);
+
+    let mut context = match CONTEXT.lock() {
+        Ok(context) => context,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+
+    let mut dump = format!("Panic: {}\n\nState stack (top last):\n", message);
+    for state in context.states.iter() {
+        dump.push_str(format!("  {}\n", state).as_str());
+    }
+
+    if let Some(log) = &context.log {
+        dump.push_str("\nLast log lines:\n");
+        for line in log.last(LevelFilter::Trace, DUMP_LOG_LINES) {
+            dump.push_str(format!("  {:<5} {}: {}\n", line.level, line.target, line.message).as_str());
+        }
+    }
+
+    dump.push_str(format!("\nBacktrace:\n{:?}\n", backtrace).as_str());
+
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs());
+    let path = crashes_dir().join(format!("crash-{}.txt", seconds));
+
+    let written = fs::create_dir_all(crashes_dir()).and_then(|_| fs::write(&path, dump));
+    match written {
+        Ok(()) => context.dump = Some(path),
+        Err(err) => error!("Failed to write crash dump {}: {}", path.display(), err),
+    }
+
+    let top = context.states.last().cloned().unwrap_or(String::from("no state"));
+    context.report = Some(format!("{} (state {})", message, top));
+}
+
+/// Readable crash report for the player, `recovery` is the recovery save if it was written
+pub fn report(recovery: Option<PathBuf>) -> String {
+    let mut context = match CONTEXT.lock() {
+        Ok(context) => context,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+
+    let mut report = context.report.take().unwrap_or(String::from("unknown panic"));
+
+    if let Some(dump) = context.dump.take() {
+        report.push_str(format!("\nCrash dump: {}", dump.display()).as_str());
+    }
+
+    if let Some(recovery) = recovery {
+        report.push_str(format!("\nThe game was saved, continue with: rust-rpg --load {}",
+                                recovery.display()).as_str());
+    }
+
+    report
+}
@@ -0,0 +1,71 @@
+//! Error dialog. Shown instead of crashing when a state fails.
+
+use crate::game::{GlobalState, PlayResult};
+use crate::error::{Error, Result as GameResult};
+use crate::widgets::label::Label;
+use crate::unicode;
+use crate::render::{Drawable, Position, Coordinate};
+
+use termion::event::Key;
+
+const TEXT_WIDTH: usize = 60;
+
+pub struct PlayState {
+    /// String key of the title
+    title: &'static str,
+    lines: Vec<String>,
+}
+
+impl PlayState {
+    pub fn new(error: &Error) -> Self {
+        PlayState {
+            title: error.title_key(),
+            lines: unicode::wrap(&error.to_string(), TEXT_WIDTH),
+        }
+    }
+}
+
+impl super::PlayState for PlayState {
+    fn render(&mut self, game_state: &mut GlobalState) {
+        game_state.render.clear_color = game_state.render.theme.background;
+        game_state.render.clear_screen();
+
+        let top = (game_state.render.term_size.height as f32 * 0.3) as u16;
+
+        let mut title = Label::new(game_state.strings.get(self.title).as_str());
+        title.set_color(game_state.render.theme.danger);
+        title.set_position(Position {
+            x: Coordinate::Centered,
+            y: Coordinate::Absolute(top),
+        });
+        game_state.render.draw(&title);
+
+        for (i, line) in self.lines.iter().enumerate() {
+            let mut label = Label::new(line.as_str());
+            label.set_position(Position {
+                x: Coordinate::Centered,
+                y: Coordinate::Absolute(top + 2 + i as u16),
+            });
+            game_state.render.draw(&label);
+        }
+
+        let mut hint = Label::new(game_state.strings.get("error.hint").as_str());
+        hint.set_color(game_state.render.theme.hint);
+        hint.set_position(Position {
+            x: Coordinate::Centered,
+            y: Coordinate::FromBorder(2),
+        });
+        game_state.render.draw(&hint);
+    }
+
+    fn to_string(&self) -> String {
+        String::from("ErrorDialog")
+    }
+
+    fn on_key_pressed(&mut self, _game_state: &mut GlobalState, key: Key) -> GameResult<PlayResult> {
+        match key {
+            Key::Char('\n') | Key::Backspace => Ok(PlayResult::Pop),
+            _ => Ok(PlayResult::Still),
+        }
+    }
+}
@@ -1,23 +1,29 @@
 //! Exit splash screen state
 
 use crate::game::{GlobalState, PlayResult};
+use crate::error::Result as GameResult;
+use crate::game::sound::Music;
+use crate::game::animation::{Animation, Easing, Tween, lerp_color};
 use std::time::Duration;
 use crate::widgets::label::Label;
 use crate::render::{Drawable, Position, Coordinate};
-use std::io::Write;
+use termion::color;
+use termion::event::Key;
 
 pub struct PlayState {
-
+    rust_label: Label,
+    rpg_label: Label,
+    author_label: Label,
+    brightness: Tween<u8>,
 }
 
-impl super::PlayState for PlayState {
-    fn play(&mut self, game_state: &mut GlobalState) -> PlayResult {
-        let mut brightness: u8 = 255;
-        let mut inverted: u8 = 0;
+const FADE_DURATION: Duration = Duration::from_millis(5100);
 
-        let mut rust_label = Label::new("R U S T");
-        let mut rpg_label = Label::new("R P G");
-        let mut author_label = Label::new("Made by George Popoff using Rust:3");
+impl PlayState {
+    pub fn new(game_state: &GlobalState) -> Self {
+        let mut rust_label = Label::new(game_state.strings.get("exit.rust").as_str());
+        let mut rpg_label = Label::new(game_state.strings.get("exit.rpg").as_str());
+        let mut author_label = Label::new(game_state.strings.get("exit.author").as_str());
 
         rust_label.set_position(Position {
             x: Coordinate::Centered,
@@ -34,29 +40,55 @@ impl super::PlayState for PlayState {
             y: Coordinate::Absolute(game_state.render.term_size.height - 3)
         });
 
-        for _i in 0..255 {
-            game_state.render.clear_color =
-                Box::new(termion::color::Rgb(brightness, brightness, brightness));
-            game_state.render.clear_screen();
+        PlayState {
+            rust_label,
+            rpg_label,
+            author_label,
+            brightness: Tween::new(255, 0, FADE_DURATION).with_easing(Easing::EaseIn),
+        }
+    }
+}
 
-            rust_label.color = Box::new(termion::color::Rgb(inverted, inverted, inverted));
-            rpg_label.color = Box::new(termion::color::Rgb(inverted, inverted, inverted));
-            author_label.color = Box::new(termion::color::Rgb(inverted, inverted, inverted));
+impl super::PlayState for PlayState {
+    fn update(&mut self, _game_state: &mut GlobalState, dt: Duration) -> GameResult<PlayResult> {
+        self.brightness.advance(dt);
 
-            game_state.render.draw(&rust_label);
-            game_state.render.draw(&rpg_label);
-            game_state.render.draw(&author_label);
+        // The last screen of the GAME
+        if self.brightness.is_finished() {
+            return Ok(PlayResult::Shutdown);
+        }
 
-            brightness = brightness - 1;
-            inverted = inverted + 1;
+        Ok(PlayResult::Still)
+    }
 
-            std::thread::sleep(Duration::from_millis(20));
-        }
+    fn render(&mut self, game_state: &mut GlobalState) {
+        // The theme fades to black while the text fades in
+        let k = self.brightness.value() as f32 / 255.0;
+        let theme = &game_state.render.theme;
+        let background = lerp_color(color::Rgb(0, 0, 0), theme.background, k);
+        let text = lerp_color(theme.title, theme.background, k);
+
+        game_state.render.clear_color = background;
+        game_state.render.clear_screen();
 
-        PlayResult::Pop
+        self.rust_label.set_color(text);
+        self.rpg_label.set_color(text);
+        self.author_label.set_color(text);
+
+        game_state.render.draw(&self.rust_label);
+        game_state.render.draw(&self.rpg_label);
+        game_state.render.draw(&self.author_label);
+    }
+
+    fn music(&self) -> Music {
+        Music::Silence
     }
 
     fn to_string(&self) -> String {
         String::from("ExitSplash")
     }
+
+    fn on_key_pressed(&mut self, _game_state: &mut GlobalState, _key: Key) -> GameResult<PlayResult> {
+        Ok(PlayResult::Pop)
+    }
 }
@@ -1,24 +1,216 @@
-use termion::input::{TermRead, Keys};
-use termion::event::{Event, Key};
-use std::collections::HashMap;
+//! Keyboard and mouse input. Bytes from the terminal are buffered until an event
+//! is complete, since an escape sequence may arrive in several reads.
+//!
+//! Mouse events are read in the SGR encoding (`ESC [ < b ; x ; y M`) which termion
+//! doesn't parse for the motion without a pressed button, so they are parsed here.
+
+use std::io::Read;
+use termion::event::{self, Event, Key, MouseButton};
 use termion::AsyncReader;
-use std::rc::Rc;
+
+/// Turns on the reporting of the mouse motion without a pressed button, on top of `MouseTerminal`
+pub const ENABLE_HOVER: &'static str = "\x1b[?1003h";
+pub const DISABLE_HOVER: &'static str = "\x1b[?1003l";
+
+/// Polls an incomplete sequence is kept before it is dropped, or a lone Esc is taken as the key
+const MAX_PENDING_POLLS: u32 = 10;
+
+/// Coordinates are 1-based terminal cells
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MouseEvent {
+    /// Also the wheel, with `MouseButton::WheelUp` and `MouseButton::WheelDown`
+    Press(MouseButton, u16, u16),
+    Release(u16, u16),
+    /// The mouse moved with a button held
+    Drag(u16, u16),
+    /// The mouse moved without a button
+    Move(u16, u16),
+}
+
+impl MouseEvent {
+    pub fn position(&self) -> (u16, u16) {
+        match *self {
+            MouseEvent::Press(_, x, y) => (x, y),
+            MouseEvent::Release(x, y) => (x, y),
+            MouseEvent::Drag(x, y) => (x, y),
+            MouseEvent::Move(x, y) => (x, y),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Input {
+    Key(Key),
+    Mouse(MouseEvent),
+}
+
+/// Result of parsing the start of the buffer
+enum Parsed {
+    /// The event and the number of bytes it took
+    Event(Option<Input>, usize),
+    /// More bytes are needed
+    Incomplete,
+}
+
+/// Length of the CSI sequence (`ESC [ params final`), None if the final byte hasn't arrived
+fn csi_length(bytes: &[u8]) -> Option<usize> {
+    bytes.iter().skip(2).position(|byte| (0x40..=0x7E).contains(byte)).map(|index| index + 3)
+}
+
+/// Length of the UTF-8 character by its first byte
+fn utf8_length(first: u8) -> usize {
+    match first {
+        0xC0..=0xDF => 2,
+        0xE0..=0xEF => 3,
+        0xF0..=0xF7 => 4,
+        _ => 1,
+    }
+}
+
+/// `ESC [ < b ; x ; y (M|m)`
+fn parse_sgr_mouse(sequence: &[u8]) -> Option<MouseEvent> {
+    let text = std::str::from_utf8(&sequence[3..sequence.len() - 1]).ok()?;
+    let numbers: Vec<u16> = text.split(';').map(|number| number.parse().ok()).collect::<Option<_>>()?;
+
+    let (code, x, y) = match numbers.as_slice() {
+        [code, x, y] => (*code, *x, *y),
+        _ => return None,
+    };
+
+    // Shift, Meta and Control are ignored
+    let code = code & !(4 | 8 | 16);
+    let pressed = *sequence.last()? == b'M';
+
+    let button = match code & 3 {
+        0 => Some(MouseButton::Left),
+        1 => Some(MouseButton::Middle),
+        2 => Some(MouseButton::Right),
+        _ => None,
+    };
+
+    let event = if code & 64 != 0 {
+        match code & 3 {
+            0 => MouseEvent::Press(MouseButton::WheelUp, x, y),
+            1 => MouseEvent::Press(MouseButton::WheelDown, x, y),
+            _ => return None,
+        }
+    } else if code & 32 != 0 {
+        match button {
+            Some(_) => MouseEvent::Drag(x, y),
+            None => MouseEvent::Move(x, y),
+        }
+    } else {
+        match (button, pressed) {
+            (Some(button), true) => MouseEvent::Press(button, x, y),
+            (_, false) | (None, _) => MouseEvent::Release(x, y),
+        }
+    };
+
+    Some(event)
+}
+
+fn convert(event: Event) -> Option<Input> {
+    match event {
+        Event::Key(key) => Some(Input::Key(key)),
+        Event::Mouse(event::MouseEvent::Press(button, x, y)) => Some(Input::Mouse(MouseEvent::Press(button, x, y))),
+        Event::Mouse(event::MouseEvent::Release(x, y)) => Some(Input::Mouse(MouseEvent::Release(x, y))),
+        Event::Mouse(event::MouseEvent::Hold(x, y)) => Some(Input::Mouse(MouseEvent::Drag(x, y))),
+        Event::Unsupported(_) => None,
+    }
+}
+
+fn parse(bytes: &[u8]) -> Parsed {
+    let first = bytes[0];
+
+    let length = match (first, bytes.get(1)) {
+        // A lone Esc may be the start of a sequence split across reads, it is the key
+        // itself only if nothing follows it (see `Controller::poll`)
+        (0x1B, None) => return Parsed::Incomplete,
+        // X10 mouse encoding has three raw bytes after `ESC [ M`
+        (0x1B, Some(b'[')) if bytes.get(2) == Some(&b'M') => 6,
+        (0x1B, Some(b'[')) => match csi_length(bytes) {
+            Some(length) => length,
+            None => return Parsed::Incomplete,
+        },
+        (0x1B, Some(b'O')) if bytes.len() < 3 => return Parsed::Incomplete,
+        (0x1B, Some(b'O')) => 3,
+        (0x1B, Some(next)) => 1 + utf8_length(*next),
+        (first, _) => utf8_length(first),
+    };
+
+    if bytes.len() < length {
+        return Parsed::Incomplete;
+    }
+
+    let sequence = &bytes[..length];
+    if sequence.starts_with(b"\x1b[<") {
+        return Parsed::Event(parse_sgr_mouse(sequence).map(Input::Mouse), length);
+    }
+
+    let mut rest = sequence[1..].iter().map(|byte| Ok(*byte));
+    let event = event::parse_event(first, &mut rest).ok().and_then(convert);
+
+    Parsed::Event(event, length)
+}
 
 pub struct Controller {
-    reader: Keys<AsyncReader>
+    /// Started by the first poll, so a game which never reads the terminal (e.g. a headless replay) needs no terminal
+    reader: Option<AsyncReader>,
+    /// Bytes which aren't a complete event yet
+    pending: Vec<u8>,
+    pending_polls: u32,
 }
 
 impl Controller {
     pub fn new() -> Self {
         Controller{
-            reader: termion::async_stdin().keys(),
+            reader: None,
+            pending: vec![],
+            pending_polls: 0,
         }
     }
 
-    pub fn get_pressed_key(&mut self) -> Option<Key> {
-        return match self.reader.next() {
-            Some(key) => Some(key.unwrap()),
-            None => None,
-        };
+    /// The next key or mouse event, None if there is no complete event yet
+    pub fn poll(&mut self) -> Option<Input> {
+        let reader = self.reader.get_or_insert_with(termion::async_stdin);
+
+        let mut buffer = [0; 256];
+        while let Ok(count) = reader.read(&mut buffer) {
+            if count == 0 {
+                break;
+            }
+
+            self.pending.extend_from_slice(&buffer[..count]);
+        }
+
+        while !self.pending.is_empty() {
+            match parse(&self.pending) {
+                Parsed::Event(event, length) => {
+                    self.pending.drain(..length);
+                    self.pending_polls = 0;
+
+                    if event.is_some() {
+                        return event;
+                    }
+                },
+                Parsed::Incomplete if self.pending_polls < MAX_PENDING_POLLS => {
+                    self.pending_polls += 1;
+                    return None;
+                },
+                Parsed::Incomplete if self.pending == [0x1B] => {
+                    self.pending.clear();
+                    self.pending_polls = 0;
+
+                    return Some(Input::Key(Key::Esc));
+                },
+                Parsed::Incomplete => {
+                    log::warn!("Dropped incomplete input {:?}", self.pending);
+                    self.pending.clear();
+                    self.pending_polls = 0;
+                },
+            }
+        }
+
+        None
     }
-}
\ No newline at end of file
+}
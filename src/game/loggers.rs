@@ -1,9 +1,95 @@
-use crate::render::{Render, Coordinate};
-use log::{Metadata, Record};
-use crate::widgets::label::Label;
-use std::cell::RefCell;
-
-pub trait Logger {
-    fn info(&mut self, message: &str);
-    fn warn(&mut self, message: &str);
-}
\ No newline at end of file
+//! In-game log console. Shows the last log records over the current state.
+
+use crate::render::Render;
+use crate::logging::{LogBuffer, LogLine};
+use crate::theme::Theme;
+use crate::unicode;
+use log::{Level, LevelFilter};
+use termion::color;
+
+/// Part of the screen height taken by the console
+const CONSOLE_HEIGHT_PERCENT: u16 = 40;
+
+/// Levels the console filter cycles through
+const FILTERS: [LevelFilter; 5] = [
+    LevelFilter::Trace,
+    LevelFilter::Debug,
+    LevelFilter::Info,
+    LevelFilter::Warn,
+    LevelFilter::Error,
+];
+
+fn level_color(level: Level, theme: &Theme) -> color::Rgb {
+    match level {
+        Level::Error => theme.danger,
+        Level::Warn => theme.warning,
+        Level::Info => theme.panel_text,
+        Level::Debug => theme.highlight,
+        Level::Trace => theme.hint,
+    }
+}
+
+pub struct LogConsole {
+    buffer: LogBuffer,
+    visible: bool,
+    filter: LevelFilter,
+}
+
+impl LogConsole {
+    pub fn new(buffer: LogBuffer) -> Self {
+        LogConsole {
+            buffer,
+            visible: false,
+            filter: LevelFilter::Info,
+        }
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+    }
+
+    /// Switches to the next more strict level, after Error goes back to Trace
+    pub fn cycle_filter(&mut self) {
+        let index = FILTERS.iter().position(|filter| *filter == self.filter).unwrap_or(0);
+        self.filter = FILTERS[(index + 1) % FILTERS.len()];
+    }
+
+    fn format_line(line: &LogLine, width: usize) -> String {
+        let text = format!("{:<5} {}: {}", line.level, line.target, line.message.replace('\n', " "));
+
+        unicode::fit(&text, width)
+    }
+
+    pub fn render(&self, render: &mut Render) {
+        let width = render.term_size.width;
+        let height = (render.term_size.height * CONSOLE_HEIGHT_PERCENT / 100).max(3);
+        let top = render.term_size.height - height + 1;
+
+        let background = render.bg(render.theme.panel);
+        let title = format!(" Log [{}+]  F2: close  F4: level", self.filter);
+
+        render.set_cursor_position((1, top));
+        render.draw_raw(format!("{}{}{:<width$}", render.bg(render.theme.border),
+                                render.fg(render.theme.panel_text),
+                                title, width = width as usize).as_str());
+
+        let rows = (height - 1) as usize;
+        let lines = self.buffer.last(self.filter, rows);
+
+        // New lines are at the bottom, empty rows are at the top
+        for row in 0..rows {
+            let text = match (row + lines.len()).checked_sub(rows) {
+                Some(index) => format!("{}{}", render.fg(level_color(lines[index].level, &render.theme)),
+                                       LogConsole::format_line(&lines[index], width as usize)),
+                None => " ".repeat(width as usize),
+            };
+
+            render.set_cursor_position((1, top + 1 + row as u16));
+            render.draw_raw(format!("{}{}", background, text).as_str());
+        }
+    }
+}
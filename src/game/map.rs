@@ -1,28 +1,45 @@
 //! Map screen where you can move
 
 use crate::game::{GlobalState, PlayResult};
-use crate::render::{Render, Drawable, Position};
-use crate::game::input::Controller;
+use crate::game::sound::{AmbientSource, Music};
+use crate::render::{Render, Drawable, Position, ColorMode};
+use crate::game::input::{Controller, MouseEvent};
+use crate::game::save::{self, Save, SavedLevel, SavedLink};
+use crate::game::console::{self, Context, Registry};
+use crate::game::pathfinding::{self, DijkstraMap, Grid, Movement, Point};
+use crate::game::PlayState as _;
+use crate::assets::{AssetKind, AssetManager};
+use crate::widgets::text::{self, Style};
+use crate::unicode;
+use crate::i18n::Strings;
+use crate::error::Result as GameResult;
+use crate::rng::{Random, Rng, Stream};
 
-use termion::color::Color;
 use termion::color;
 
 use std::error::Error;
-use std::{fs, io};
 use std::fmt::{Display, Formatter};
-use std::fs::File;
-use std::io::BufRead;
-use log::{error};
-use termion::event::Key;
-use std::collections::HashMap;
+use log::{error, info, warn};
+use termion::event::{Key, MouseButton};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::borrow::{Borrow, BorrowMut};
 use lazy_static::lazy_static;
+use std::time::Duration;
+use std::hash::{Hash, Hasher};
+
+mod dungeon;
+mod levels;
 
 #[derive(Clone)]
 struct Tile {
     bg_color: (u8, u8, u8),
     fg_color: (u8, u8, u8),
-    character: char
+    character: char,
+    /// Shown instead of the colors in the monochrome mode
+    mono_character: char,
+    walkable: bool,
+    /// Cost of walking onto the tile for the pathfinding
+    cost: u32
 }
 
 impl Tile {
@@ -30,22 +47,28 @@ impl Tile {
         Tile {
             bg_color: (0, 200, 0),
             fg_color: (0, 0, 0),
-            character: char
+            character: char,
+            mono_character: char,
+            walkable: true,
+            cost: 1
         }
     }
 
-    fn bg_color(&self) -> color::Bg<color::Rgb> {
-        color::Bg(color::Rgb(self.bg_color.0, self.bg_color.1, self.bg_color.2))
+    fn bg_color(&self) -> color::Rgb {
+        color::Rgb(self.bg_color.0, self.bg_color.1, self.bg_color.2)
     }
 
-    fn fg_color(&self) -> color::Fg<color::Rgb> {
-        color::Fg(color::Rgb(self.fg_color.0, self.fg_color.1, self.fg_color.2))
+    fn fg_color(&self) -> color::Rgb {
+        color::Rgb(self.fg_color.0, self.fg_color.1, self.fg_color.2)
     }
 }
 
 impl Drawable for Tile {
-    fn draw(&self) -> String {
-        unimplemented!()
+    fn draw(&self, render: &Render) -> String {
+        match render.color_mode() {
+            ColorMode::Mono => self.mono_character.to_string(),
+            _ => format!("{}{}{}", render.bg(self.bg_color()), render.fg(self.fg_color()), self.character),
+        }
     }
 
     fn get_width(&self) -> u16 {
@@ -68,24 +91,63 @@ impl Drawable for Tile {
 struct Map {
     tiles: Vec<Vec<Tile>>,
     position: Position,
+    /// Where the player appears on the map
+    start: (u16, u16),
+    /// Map-wide ambient sound
+    ambience: Option<String>,
+    ambient_sources: Vec<AmbientSource>,
+    /// Monsters placed on the map when the player comes to it
+    monsters: Vec<Monster>,
+    loot: Vec<Loot>,
+    stairs: Vec<Stairs>,
+    /// Tiles the player has seen, by rows. None for the maps which are seen whole.
+    explored: Option<Vec<Vec<bool>>>,
+}
+
+/// Stairs tile leading to another map
+struct Stairs {
+    position: Point,
+    /// Id of the map, `gen:<layout>` for a dungeon which gets its seed when it is entered
+    to: String,
 }
 
 #[derive(Debug)]
 struct MapParseError {
-    text: String
+    text: String,
+    /// 1-based line of the map file
+    line: Option<usize>,
 }
 
 impl MapParseError {
     fn new(message: &str) -> MapParseError {
         MapParseError {
-            text: String::from(message)
+            text: String::from(message),
+            line: None,
+        }
+    }
+
+    fn at_line(self, index: usize) -> MapParseError {
+        MapParseError {
+            line: Some(index + 1),
+            ..self
+        }
+    }
+
+    fn into_error(self, map: &str) -> crate::error::Error {
+        crate::error::Error::MapParse {
+            map: String::from(map),
+            line: self.line,
+            message: self.text,
         }
     }
 }
 
 impl Display for MapParseError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        f.write_str(format!("Failed to parse map file: {}", self.text).as_str())
+        match self.line {
+            Some(line) => write!(f, "Failed to parse map file: line {}: {}", line, self.text),
+            None => write!(f, "Failed to parse map file: {}", self.text),
+        }
     }
 }
 
@@ -100,6 +162,29 @@ const HEIGHT: u16 = 40;
 const COLOR_DARK_GREEN: (u8, u8, u8) = (0, 100, 0);
 const COLOR_BLACK: (u8, u8, u8) = (0, 0, 0);
 const COLOR_BROWN: (u8, u8, u8) = (150, 40, 40);
+const COLOR_BLUE: (u8, u8, u8) = (30, 60, 200);
+const COLOR_LIGHT_BLUE: (u8, u8, u8) = (70, 130, 220);
+const COLOR_WOOD: (u8, u8, u8) = (120, 80, 40);
+const COLOR_ORANGE: (u8, u8, u8) = (230, 120, 0);
+const COLOR_WHITE: (u8, u8, u8) = (255, 255, 255);
+const COLOR_STONE: (u8, u8, u8) = (60, 60, 60);
+const COLOR_GRAY: (u8, u8, u8) = (140, 140, 140);
+
+/// Default radius of a positional sound (in tiles)
+const SOUND_RADIUS: f32 = 15.0;
+/// Time between two steps of the player walking to the clicked tile
+const WALK_STEP: Duration = Duration::from_millis(100);
+/// Time between two steps of the monsters
+const MONSTER_STEP: Duration = Duration::from_millis(400);
+/// How far the player sees on the dark maps (in tiles)
+const VIEW_RADIUS: i32 = 7;
+/// Monsters closer than this (in steps) chase the player
+const CHASE_DISTANCE: i32 = 12;
+/// Chance of a monster far from the player to take a random step
+const WANDER_CHANCE: f32 = 0.25;
+/// The player walks like with the arrow keys
+const PLAYER_MOVEMENT: Movement = Movement::FourWay;
+const MONSTER_MOVEMENT: Movement = Movement::EightWay;
 
 lazy_static! {
     static ref DEFAULT_TILE_MAP: HashMap<char, Tile> = {
@@ -108,13 +193,87 @@ lazy_static! {
         map.insert('#', Tile {
             fg_color: COLOR_BLACK,
             bg_color: COLOR_DARK_GREEN,
-            character: ' '
+            character: ' ',
+            mono_character: '.',
+            walkable: true,
+            cost: 1
         });
 
         map.insert('X', Tile {
             fg_color: COLOR_BLACK,
             bg_color: COLOR_BROWN,
-            character: ' '
+            character: ' ',
+            mono_character: '#',
+            walkable: false,
+            cost: 1
+        });
+
+        // Water
+        map.insert('~', Tile {
+            fg_color: COLOR_WHITE,
+            bg_color: COLOR_BLUE,
+            character: '~',
+            mono_character: '~',
+            walkable: false,
+            cost: 1
+        });
+
+        // Shallow water, paths go around it when they can
+        map.insert(',', Tile {
+            fg_color: COLOR_WHITE,
+            bg_color: COLOR_LIGHT_BLUE,
+            character: ' ',
+            mono_character: ',',
+            walkable: true,
+            cost: 3
+        });
+
+        // Bridge
+        map.insert('=', Tile {
+            fg_color: COLOR_BLACK,
+            bg_color: COLOR_WOOD,
+            character: '=',
+            mono_character: '=',
+            walkable: true,
+            cost: 1
+        });
+
+        // Campfire
+        map.insert('*', Tile {
+            fg_color: COLOR_ORANGE,
+            bg_color: COLOR_DARK_GREEN,
+            character: '*',
+            mono_character: '*',
+            walkable: false,
+            cost: 1
+        });
+
+        // Dungeon floor
+        map.insert('.', Tile {
+            fg_color: COLOR_GRAY,
+            bg_color: COLOR_STONE,
+            character: ' ',
+            mono_character: '.',
+            walkable: true,
+            cost: 1
+        });
+
+        map.insert(dungeon::STAIRS_UP, Tile {
+            fg_color: COLOR_WHITE,
+            bg_color: COLOR_STONE,
+            character: dungeon::STAIRS_UP,
+            mono_character: dungeon::STAIRS_UP,
+            walkable: true,
+            cost: 1
+        });
+
+        map.insert(dungeon::STAIRS_DOWN, Tile {
+            fg_color: COLOR_WHITE,
+            bg_color: COLOR_STONE,
+            character: dungeon::STAIRS_DOWN,
+            mono_character: dungeon::STAIRS_DOWN,
+            walkable: true,
+            cost: 1
         });
 
         map
@@ -124,114 +283,981 @@ lazy_static! {
 const DEFAULT_TILE: Tile = Tile {
     bg_color: COLOR_BLACK,
     fg_color: COLOR_BLACK,
-    character: ' '
+    character: ' ',
+    mono_character: ' ',
+    walkable: false,
+    cost: 1
 };
 
+/// The tile shown by the glyph
+fn tile(char: char) -> Tile {
+    match DEFAULT_TILE_MAP.get(&char) {
+        Some(tile) => tile.clone(),
+        // Other glyphs (e.g. box drawing walls) are shown as they are
+        None if char != ' ' => Tile {
+            fg_color: COLOR_WHITE,
+            character: char,
+            mono_character: char,
+            ..DEFAULT_TILE
+        },
+        None => DEFAULT_TILE,
+    }
+}
+
 impl Map {
-    fn from_file(file: &str) -> Result<Map, MapParseError> {
-        let file = match File::open(file) {
-            Ok(file) => file,
-            Err(_) => return Err(MapParseError::new("failed to open map file")),
-        };
+    /// No tiles, for filling the rest of the fields
+    fn empty() -> Map {
+        Map {
+            tiles: vec![],
+            position: Position::from(0, 0),
+            start: (WIDTH / 2, HEIGHT / 2),
+            ambience: None,
+            ambient_sources: vec![],
+            monsters: vec![],
+            loot: vec![],
+            stairs: vec![],
+            explored: None,
+        }
+    }
 
-        let lines: Vec<String> = io::BufReader::new(file).lines()
-            .map(|l| l.expect("Failed to parse line"))
-            .collect();
+    /// The map file of the id or a generated level for the `gen:` ids
+    fn load(assets: &AssetManager, id: &str) -> GameResult<Map> {
+        if let Some(level) = dungeon::parse_id(id) {
+            let (layout, seed, depth) = level.map_err(|err| MapParseError::new(&err).into_error(id))?;
+            return dungeon::generate(layout, seed, depth).map_err(|err| err.into_error(id));
+        }
+
+        let text = assets.read_text(AssetKind::Map, id)?;
+
+        Map::parse(&text).map_err(|err| err.into_error(id))
+    }
+
+    fn parse(text: &str) -> Result<Map, MapParseError> {
+        let lines: Vec<&str> = text.lines().collect();
 
         // HEIGHT lines + 1 MAGIC LINE
         if lines.len() < (HEIGHT + 1) as usize {
             return Err(MapParseError::new("file corrupted (no magic line)"));
         }
 
-        if !lines[0].as_str().eq(MAGIC) {
-            return Err(MapParseError::new("bad magic line"));
+        if !lines[0].eq(MAGIC) {
+            return Err(MapParseError::new("bad magic line").at_line(0));
         }
 
         let mut tiles = vec![];
-        for line in lines.iter().skip(1) {
-            if line.len() != WIDTH as usize {
+        for (index, line) in lines.iter().enumerate().skip(1).take(HEIGHT as usize) {
+            // A tile is one cell, so each glyph is measured by its display width
+            let glyphs: Vec<&str> = unicode::graphemes(line).collect();
+            if let Some(column) = glyphs.iter().position(|glyph| unicode::grapheme_width(glyph) != 1) {
                 return Err(MapParseError::new(
-                    format!("each row should be {} characters ({} found)!", WIDTH, line.len())
-                        .as_str()))
+                    format!("glyph {:?} at column {} is not one cell wide", glyphs[column], column + 1)
+                        .as_str()).at_line(index))
             }
 
-            let mut line_tiles: Vec<Tile> = vec![];
-            for char in line.chars() {
-                if let Some(tile) = DEFAULT_TILE_MAP.get(&char) {
-                    line_tiles.push(tile.clone());
-                } else {
-                    line_tiles.push(DEFAULT_TILE);
-                }
+            if glyphs.len() != WIDTH as usize {
+                return Err(MapParseError::new(
+                    format!("each row should be {} characters ({} found)!", WIDTH, glyphs.len())
+                        .as_str()).at_line(index))
             }
 
+            let line_tiles: Vec<Tile> = glyphs.iter()
+                .map(|glyph| tile(glyph.chars().next().unwrap_or(' ')))
+                .collect();
+
             tiles.push(line_tiles)
         }
 
-        Ok(Map{
+        let mut map = Map{
             tiles,
-            position: Position::from(0, 0)
-        })
+            ..Map::empty()
+        };
+
+        // Directives after the tiles
+        for (index, line) in lines.iter().enumerate().skip(1 + HEIGHT as usize) {
+            map.parse_directive(line).map_err(|err| err.at_line(index))?;
+        }
+
+        Ok(map)
+    }
+
+    /// Parses one of:
+    /// `START x y` - player position,
+    /// `AMBIENCE sound` - map-wide ambient sound,
+    /// `SOUND sound x y [radius]` - positional ambient sound,
+    /// `STAIRS x y map` - the map the stairs on the tile lead to
+    fn parse_directive(&mut self, line: &str) -> Result<(), MapParseError> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+
+        let coordinate = |index: usize| -> Result<u16, MapParseError> {
+            words.get(index)
+                .and_then(|word| word.parse().ok())
+                .ok_or(MapParseError::new(format!("bad coordinate in `{}`", line).as_str()))
+        };
+
+        match words.first() {
+            None => {},
+            Some(&"START") => {
+                self.start = (coordinate(1)?, coordinate(2)?);
+            },
+            Some(&"AMBIENCE") => {
+                let id = words.get(1)
+                    .ok_or(MapParseError::new("no sound in AMBIENCE directive"))?;
+
+                self.ambience = Some(String::from(*id));
+            },
+            Some(&"SOUND") => {
+                let id = words.get(1)
+                    .ok_or(MapParseError::new("no sound in SOUND directive"))?;
+
+                let radius = match words.get(4) {
+                    Some(radius) => radius.parse()
+                        .map_err(|_| MapParseError::new(format!("bad radius in `{}`", line).as_str()))?,
+                    None => SOUND_RADIUS,
+                };
+
+                self.ambient_sources.push(AmbientSource {
+                    id: String::from(*id),
+                    x: coordinate(2)?,
+                    y: coordinate(3)?,
+                    radius,
+                });
+            },
+            Some(&"STAIRS") => {
+                let position = (coordinate(1)?, coordinate(2)?);
+                let to = words.get(3)
+                    .ok_or(MapParseError::new("no map in STAIRS directive"))?;
+
+                if !self.is_stairs(position) {
+                    return Err(MapParseError::new(format!("no stairs at {} {}", position.0, position.1).as_str()));
+                }
+
+                self.stairs.push(Stairs { position, to: String::from(*to) });
+            },
+            Some(directive) => {
+                return Err(MapParseError::new(format!("unknown directive {}", directive).as_str()));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn is_walkable(&self, x: u16, y: u16) -> bool {
+        self.tiles.get(y as usize)
+            .and_then(|row| row.get(x as usize))
+            .map_or(false, |tile| tile.walkable)
+    }
+
+    /// Glyph of the tile, None outside of the map
+    fn glyph(&self, (x, y): Point) -> Option<char> {
+        self.tiles.get(y as usize)
+            .and_then(|row| row.get(x as usize))
+            .map(|tile| tile.character)
+    }
+
+    fn is_stairs(&self, position: Point) -> bool {
+        match self.glyph(position) {
+            Some(glyph) => glyph == dungeon::STAIRS_UP || glyph == dungeon::STAIRS_DOWN,
+            None => false,
+        }
+    }
+
+    /// What changed on the map since it was loaded, for a save
+    fn saved(&self, id: &str) -> SavedLevel {
+        SavedLevel {
+            map: String::from(id),
+            loot: self.loot.iter().map(|loot| (loot.kind.clone(), loot.count, loot.position)).collect(),
+            explored: self.explored.clone(),
+        }
+    }
+
+    /// Puts back what changed on the map in the save
+    fn restore(&mut self, saved: &SavedLevel) {
+        self.loot = saved.loot.iter()
+            .map(|(kind, count, position)| Loot { kind: kind.clone(), count: *count, position: *position })
+            .collect();
+
+        let fits = |explored: &Vec<Vec<bool>>| explored.len() == HEIGHT as usize
+            && explored.iter().all(|row| row.len() >= WIDTH as usize);
+
+        match (&mut self.explored, &saved.explored) {
+            (Some(explored), Some(saved)) if fits(saved) => {
+                for (row, saved) in explored.iter_mut().zip(saved.iter()) {
+                    row.copy_from_slice(&saved[..WIDTH as usize]);
+                }
+            },
+            (Some(_), Some(_)) => warn!("Saved explored tiles don't fit the map"),
+            _ => {}
+        }
+    }
+
+    fn is_explored(&self, (x, y): Point) -> bool {
+        match &self.explored {
+            Some(explored) => explored.get(y as usize).and_then(|row| row.get(x as usize)).cloned().unwrap_or(false),
+            None => true,
+        }
+    }
+
+    /// Tiles in the radius which can be seen from the tile. Walls block the view but are seen themselves.
+    fn visible_from(&self, (x, y): Point, radius: i32) -> HashSet<Point> {
+        let mut visible = HashSet::new();
+
+        for dy in -radius..=radius {
+            for dx in -radius..=radius {
+                let (tx, ty) = (x as i32 + dx, y as i32 + dy);
+                if dx * dx + dy * dy > radius * radius || tx < 0 || ty < 0 || tx >= WIDTH as i32 || ty >= HEIGHT as i32 {
+                    continue;
+                }
+
+                // Every tile on the straight line to the tile has to be open
+                let steps = dx.abs().max(dy.abs());
+                let along = |delta: i32, step: i32| (delta * step + delta.signum() * steps / 2) / steps;
+                let open = (1..steps).all(|step| {
+                    self.is_walkable((x as i32 + along(dx, step)) as u16, (y as i32 + along(dy, step)) as u16)
+                });
+
+                if open {
+                    visible.insert((tx as u16, ty as u16));
+                }
+            }
+        }
+
+        visible
+    }
+}
+
+/// Terrain only, other creatures are not taken into account
+impl Grid for Map {
+    fn width(&self) -> u16 {
+        WIDTH
+    }
+
+    fn height(&self) -> u16 {
+        HEIGHT
+    }
+
+    fn cost(&self, (x, y): Point) -> Option<u32> {
+        self.tiles.get(y as usize)
+            .and_then(|row| row.get(x as usize))
+            .filter(|tile| tile.walkable)
+            .map(|tile| tile.cost)
+    }
+}
+
+/// The tiles as the player walks them: around the monsters, anywhere in god mode
+struct PlayerGrid<'a> {
+    state: &'a PlayState,
+}
+
+impl<'a> Grid for PlayerGrid<'a> {
+    fn width(&self) -> u16 {
+        WIDTH
+    }
+
+    fn height(&self) -> u16 {
+        HEIGHT
+    }
+
+    fn cost(&self, position: Point) -> Option<u32> {
+        if self.state.god_mode {
+            return Some(1);
+        }
+
+        if self.state.monsters.iter().any(|monster| monster.position == position) {
+            return None;
+        }
+
+        self.state.current_map.cost(position)
+    }
+}
+
+/// Items lying on the map, picked up by walking onto them
+struct Loot {
+    kind: String,
+    count: u32,
+    position: (u16, u16),
+}
+
+impl Loot {
+    fn glyph(&self) -> char {
+        match self.kind.as_str() {
+            "gold" => '$',
+            _ => self.kind.chars().next().unwrap_or('?'),
+        }
+    }
+}
+
+/// Monster standing on the map
+struct Monster {
+    kind: String,
+    position: (u16, u16),
+}
+
+impl Monster {
+    fn glyph(&self) -> char {
+        self.kind.chars().next().map_or('M', |c| c.to_ascii_uppercase())
     }
 }
 
+/// Stats the player starts with
+const DEFAULT_STATS: [(&'static str, i32); 4] = [("hp", 10), ("max_hp", 10), ("attack", 1), ("defense", 0)];
+
 pub struct PlayState {
+    /// Id of the current map
+    map_id: String,
     current_map: Map,
+    player: (u16, u16),
+    stats: BTreeMap<String, i32>,
+    /// Item names with their count
+    inventory: BTreeMap<String, u32>,
+    /// The player walks through everything
+    god_mode: bool,
+    monsters: Vec<Monster>,
+    /// Tiles left to walk to the clicked one
+    path: VecDeque<(u16, u16)>,
+    /// Time since the last step on the path
+    walk_time: Duration,
+    /// Distances to the player the monsters follow, with the player position it was made for
+    chase_map: Option<(Point, DijkstraMap)>,
+    /// Time since the last step of the monsters
+    monster_time: Duration,
+    /// Maps the player has left
+    levels: levels::Levels,
+    /// Tiles the player sees now on a dark map
+    visible: HashSet<Point>,
     need_update: bool
 }
 
 impl super::PlayState for PlayState {
-    fn play(&mut self, game_state: &mut GlobalState) -> PlayResult {
+    fn update(&mut self, game_state: &mut GlobalState, dt: Duration) -> GameResult<PlayResult> {
         self.handle_input(&game_state.input);
+        self.walk(game_state, dt);
+        self.move_monsters(dt, game_state.random.stream(Stream::Ai));
 
+        Ok(PlayResult::Still)
+    }
+
+    fn render(&mut self, game_state: &mut GlobalState) {
         if self.need_update {
-            game_state.render.clear_color = Box::new(color::Black);
+            game_state.render.clear_color = color::Rgb(0, 0, 0);
             game_state.render.clear_screen();
-
-            self.render_map(&mut game_state.render);
             self.need_update = false;
         }
 
-        PlayResult::Still
+        // The whole map is redrawn since something (e.g. a transition) could be drawn over it
+        self.render_map(&mut game_state.render, &game_state.strings, game_state.random.seed());
+    }
+
+    fn music(&self) -> Music {
+        Music::Silence
+    }
+
+    fn on_enter(&mut self, game_state: &mut GlobalState) -> GameResult<()> {
+        game_state.sound.enter_ambient_scene(self.current_map.ambience.as_deref(),
+                                             &self.current_map.ambient_sources,
+                                             self.player);
+        self.need_update = true;
+        Ok(())
+    }
+
+    fn on_leave(&mut self, game_state: &mut GlobalState) {
+        game_state.sound.leave_ambient_scene();
+    }
+
+    fn snapshot(&self) -> Option<Save> {
+        Some(self.save())
+    }
+
+    fn hash_state(&self, mut hasher: &mut dyn Hasher) {
+        self.map_id.hash(&mut hasher);
+        self.player.hash(&mut hasher);
+        self.stats.hash(&mut hasher);
+        self.inventory.hash(&mut hasher);
+        self.god_mode.hash(&mut hasher);
+
+        for monster in self.monsters.iter() {
+            (&monster.kind, monster.position).hash(&mut hasher);
+        }
+
+        for loot in self.current_map.loot.iter() {
+            (&loot.kind, loot.count, loot.position).hash(&mut hasher);
+        }
     }
 
     fn to_string(&self) -> String {
         String::from("MapPlayState")
     }
 
-    fn on_key_pressed(&mut self, game_state: &mut GlobalState, key: Key) -> PlayResult {
-        PlayResult::Still
+    fn on_key_pressed(&mut self, game_state: &mut GlobalState, key: Key) -> GameResult<PlayResult> {
+        // The player takes over from the walk to the clicked tile
+        self.path.clear();
+
+        let (x, y) = self.player;
+
+        let target = match key {
+            Key::Left if x > 0 => (x - 1, y),
+            Key::Right => (x + 1, y),
+            Key::Up if y > 0 => (x, y - 1),
+            Key::Down => (x, y + 1),
+            Key::F(5) => {
+                self.quick_save(&game_state.random)?;
+                return Ok(PlayResult::Still);
+            },
+            Key::Char(glyph) if glyph == dungeon::STAIRS_UP || glyph == dungeon::STAIRS_DOWN => {
+                self.take_stairs(game_state, glyph)?;
+                return Ok(PlayResult::Still);
+            },
+            _ => return Ok(PlayResult::Still),
+        };
+
+        if self.can_enter(target) {
+            self.step(game_state, target);
+        }
+
+        Ok(PlayResult::Still)
+    }
+
+    /// Clicking a tile walks the player there
+    fn on_mouse(&mut self, _game_state: &mut GlobalState, event: MouseEvent) -> GameResult<PlayResult> {
+        if let MouseEvent::Press(MouseButton::Left, x, y) = event {
+            // Tiles are drawn from the cell (1, 1)
+            let target = (x.wrapping_sub(1), y.wrapping_sub(1));
+
+            match self.find_path(target) {
+                Some(path) => {
+                    self.path = path;
+                    self.walk_time = Duration::default();
+                },
+                None => info!("No path to {:?}", target),
+            }
+        }
+
+        Ok(PlayResult::Still)
     }
 }
 
-const MAP_FOLDER: &'static str = "assets/maps/";
-const MAP_START: &'static str = "start.map";
+/// The map state on the top of the stack
+fn map_state<'a>(context: &'a mut Context) -> Result<&'a mut PlayState, String> {
+    context.state::<PlayState>().ok_or(String::from("not on a map"))
+}
+
+/// The map state along with the global state
+fn map_state_with_globals<'a>(context: &'a mut Context) -> Result<(&'a mut PlayState, &'a mut GlobalState), String> {
+    match context.state.as_any_mut().downcast_mut::<PlayState>() {
+        Some(state) => Ok((state, &mut *context.game_state)),
+        None => Err(String::from("not on a map")),
+    }
+}
+
+/// Moves the player to the map, keeping the stats and the inventory
+fn change_map(context: &mut Context, id: &str) -> Result<(), String> {
+    let (state, game_state) = map_state_with_globals(context)?;
+    state.go_to(game_state, id).map_err(|err| err.to_string())
+}
+
+pub fn register_commands(registry: &mut Registry) {
+    registry.register("teleport <x> <y>", "move the player to the tile", |context, args| {
+        let target = (console::arg(args, 0, "x")?, console::arg(args, 1, "y")?);
+
+        let (state, game_state) = map_state_with_globals(context)?;
+        if !state.can_enter(target) {
+            return Err(format!("can't stand on {:?}", target));
+        }
+
+        state.player = target;
+        state.look();
+        game_state.sound.set_listener(target);
+
+        Ok(format!("teleported to {:?}", target))
+    });
+
+    registry.register("loadmap <name>", "go to the map keeping the player", |context, args| {
+        let id: String = console::arg(args, 0, "name")?;
+        change_map(context, &id)?;
+
+        Ok(format!("loaded map {}", id))
+    });
+
+    registry.register("generate <rooms|caves|bsp> [seed] [depth]", "go to a generated dungeon level",
+                      |context, args| {
+        let layout: dungeon::Layout = console::arg(args, 0, "layout")?;
+        let seed = match args.len() {
+            1 => context.game_state.random.stream(Stream::Generation).next_u64(),
+            _ => console::arg(args, 1, "seed")?,
+        };
+        let depth = match args.len() {
+            1 | 2 => 1,
+            _ => console::arg(args, 2, "depth")?,
+        };
+
+        let id = dungeon::id(layout, seed, depth);
+        change_map(context, &id)?;
+
+        Ok(format!("generated {}", id))
+    });
+
+    registry.register("levels", "list the levels kept as the player left them", |context, _| {
+        let state = map_state(context)?;
+        let ids: Vec<&str> = state.levels.ids().collect();
+
+        match ids.is_empty() {
+            true => Ok(String::from("no levels kept")),
+            false => Ok(ids.join("\n")),
+        }
+    });
+
+    registry.register("give <item> [count]", "add items to the inventory", |context, args| {
+        let item: String = console::arg(args, 0, "item")?;
+        let count = match args.len() {
+            1 => 1,
+            _ => console::arg(args, 1, "count")?,
+        };
+
+        let state = map_state(context)?;
+        let total = state.inventory.entry(item.clone()).or_insert(0);
+        *total += count;
+
+        Ok(format!("{} x{}", item, total))
+    });
+
+    registry.register("set <stat> <value>", "set the player stat, e.g. hp or attack", |context, args| {
+        let stat: String = console::arg(args, 0, "stat")?;
+        let value: i32 = console::arg(args, 1, "value")?;
+
+        map_state(context)?.stats.insert(stat.clone(), value);
+        Ok(format!("{} = {}", stat, value))
+    });
+
+    registry.register("godmode", "toggle walking through everything", |context, _| {
+        let state = map_state(context)?;
+        state.god_mode = !state.god_mode;
+
+        Ok(format!("god mode {}", if state.god_mode { "on" } else { "off" }))
+    });
+
+    registry.register("reveal", "explore the whole map", |context, _| {
+        let state = map_state(context)?;
+        let explored = match &mut state.current_map.explored {
+            Some(explored) => explored,
+            None => return Ok(String::from("the map has no fog")),
+        };
+
+        for row in explored.iter_mut() {
+            for tile in row.iter_mut() {
+                *tile = true;
+            }
+        }
+
+        Ok(String::from("revealed the map"))
+    });
+
+    registry.register("spawn <monster> [x y]", "put a monster on the map, next to the player by default",
+                      |context, args| {
+        let kind: String = console::arg(args, 0, "monster")?;
+        let state = map_state(context)?;
+
+        let position = match args.len() {
+            1 => state.free_neighbour().ok_or(String::from("no free tile next to the player"))?,
+            _ => (console::arg(args, 1, "x")?, console::arg(args, 2, "y")?),
+        };
+
+        if !state.current_map.is_walkable(position.0, position.1) || state.is_occupied(position) {
+            return Err(format!("can't place a monster on {:?}", position));
+        }
+
+        state.monsters.push(Monster { kind: kind.clone(), position });
+        Ok(format!("spawned {} at {:?}", kind, position))
+    });
+}
+
+pub const MAP_START: &'static str = "start";
+
+/// The id is of a generated level (`gen:<layout>:<seed>[:<depth>]`)
+pub fn is_generated(id: &str) -> bool {
+    dungeon::parse_id(id).is_some()
+}
 
 impl PlayState {
-    pub fn new() -> Self {
-        let start_map = Map::from_file(format!("{}{}", MAP_FOLDER, MAP_START).as_str());
+    pub fn new(game_state: &GlobalState) -> GameResult<Self> {
+        PlayState::load(game_state, MAP_START)
+    }
 
-        let start_map = start_map.unwrap_or_else(|err| {
-            panic!("Failed to parse start map: {}", err);
-        });
+    /// Starts on the map with the given id
+    pub fn load(game_state: &GlobalState, id: &str) -> GameResult<Self> {
+        let mut map = Map::load(&game_state.assets, id)?;
 
-        PlayState {
-            current_map: start_map,
+        let mut state = PlayState {
+            map_id: String::from(id),
+            player: map.start,
+            monsters: std::mem::take(&mut map.monsters),
+            current_map: map,
+            stats: DEFAULT_STATS.iter().map(|(stat, value)| (String::from(*stat), *value)).collect(),
+            inventory: BTreeMap::new(),
+            god_mode: false,
+            path: VecDeque::new(),
+            walk_time: Duration::default(),
+            chase_map: None,
+            monster_time: Duration::default(),
+            levels: levels::Levels::new(),
+            visible: HashSet::new(),
             need_update: true
+        };
+
+        state.look();
+        Ok(state)
+    }
+
+    pub fn from_save(game_state: &GlobalState, save: &Save) -> GameResult<Self> {
+        let mut state = PlayState::load(game_state, &save.map)?;
+
+        for link in save.links.iter() {
+            state.levels.link(&link.map, link.position, &link.to);
         }
+        state.levels.restore_links(&save.map, &mut state.current_map);
+
+        // The levels left are loaded again and get back what changed on them
+        for level in save.levels.iter() {
+            if level.map == save.map {
+                state.current_map.restore(level);
+                continue;
+            }
+
+            match Map::load(&game_state.assets, &level.map) {
+                Ok(mut map) => {
+                    state.levels.restore_links(&level.map, &mut map);
+                    map.restore(level);
+                    state.levels.keep(&level.map, map);
+                },
+                Err(err) => warn!("Failed to load the saved level {}: {}", level.map, err),
+            }
+        }
+
+        if state.current_map.is_walkable(save.player.0, save.player.1) {
+            state.player = save.player;
+        } else {
+            warn!("Saved position {:?} is not walkable, starting at the map start", save.player);
+        }
+        state.look();
+
+        state.stats.extend(save.stats.clone());
+        state.inventory = save.inventory.clone();
+
+        Ok(state)
     }
 
-    fn handle_input(&mut self, input: &Controller) {
+    fn save(&self) -> Save {
+        Save {
+            map: self.map_id.clone(),
+            player: self.player,
+            stats: self.stats.clone(),
+            inventory: self.inventory.clone(),
+            // The random streams belong to the whole game
+            random: None,
+            links: self.levels.links()
+                .map(|(map, position, to)| SavedLink { map: String::from(map), position, to: String::from(to) })
+                .collect(),
+            levels: self.levels.iter()
+                .chain(std::iter::once((self.map_id.as_str(), &self.current_map)))
+                .map(|(id, map)| map.saved(id))
+                .collect(),
+        }
+    }
+
+    fn is_occupied(&self, position: (u16, u16)) -> bool {
+        self.player == position || self.monsters.iter().any(|monster| monster.position == position)
+    }
+
+    /// The player can move to the tile
+    fn can_enter(&self, position: (u16, u16)) -> bool {
+        if position.0 >= WIDTH || position.1 >= HEIGHT {
+            return false;
+        }
+
+        self.god_mode || (self.current_map.is_walkable(position.0, position.1) && !self.is_occupied(position))
+    }
+
+    fn free_neighbour(&self) -> Option<(u16, u16)> {
+        let (x, y) = self.player;
+        let neighbours = [(x + 1, y), (x, y + 1), (x.wrapping_sub(1), y), (x, y.wrapping_sub(1))];
+
+        neighbours.iter().cloned()
+            .find(|(x, y)| self.current_map.is_walkable(*x, *y) && !self.is_occupied((*x, *y)))
+    }
+
+    /// Cheapest path from the player to the target, without the start
+    fn find_path(&self, target: Point) -> Option<VecDeque<Point>> {
+        if target == self.player || !self.can_enter(target) {
+            return None;
+        }
+
+        pathfinding::find_path(&PlayerGrid { state: self }, self.player, target, PLAYER_MOVEMENT)
+            .map(VecDeque::from)
+    }
+
+    /// Leaves the current map for the one with the id. The map left is kept and a kept map
+    /// is entered as it was left. The player arrives on the stairs back or at the start.
+    fn go_to(&mut self, game_state: &mut GlobalState, id: &str) -> GameResult<()> {
+        // Loading the map again would keep a stale copy of it with the levels left
+        if id == self.map_id {
+            info!("Already on the map {}", id);
+            return Ok(());
+        }
+
+        let mut map = match self.levels.take(id) {
+            Some(map) => map,
+            None => Map::load(&game_state.assets, id)?,
+        };
+        self.levels.restore_links(id, &mut map);
 
+        let arrival = map.stairs.iter()
+            .find(|stairs| stairs.to == self.map_id)
+            .map_or(map.start, |stairs| stairs.position);
+
+        self.on_leave(game_state);
+
+        // The monsters go back on the map left, so it is kept with them
+        let mut left = std::mem::replace(&mut self.current_map, map);
+        left.monsters = std::mem::replace(&mut self.monsters, std::mem::take(&mut self.current_map.monsters));
+        let left_id = std::mem::replace(&mut self.map_id, String::from(id));
+        self.levels.keep(&left_id, left);
+
+        self.player = arrival;
+        self.path.clear();
+        self.chase_map = None;
+        self.monster_time = Duration::default();
+        self.look();
+
+        info!("Entered the map {}", id);
+        self.on_enter(game_state)
+    }
+
+    /// Takes the stairs under the player if they are the ones with the glyph
+    fn take_stairs(&mut self, game_state: &mut GlobalState, glyph: char) -> GameResult<()> {
+        if self.current_map.glyph(self.player) != Some(glyph) {
+            return Ok(());
+        }
+
+        let player = self.player;
+        let to = match self.current_map.stairs.iter().find(|stairs| stairs.position == player) {
+            Some(stairs) => stairs.to.clone(),
+            None => {
+                info!("The stairs at {:?} lead nowhere", player);
+                return Ok(());
+            }
+        };
+
+        // A dungeon gets its seed the first time, the stairs lead to the same one then
+        let to = match dungeon::unseeded(&to) {
+            Some(layout) => {
+                let seeded = dungeon::id(layout, game_state.random.stream(Stream::Generation).next_u64(), 1);
+                self.link(player, &seeded);
+                seeded
+            },
+            None => to,
+        };
+
+        let left = self.map_id.clone();
+        self.go_to(game_state, &to)?;
+
+        // The first level of a dungeon leads back up to where the player came from
+        if self.current_map.is_stairs(self.player)
+            && !self.current_map.stairs.iter().any(|stairs| stairs.position == self.player) {
+            self.link(self.player, &left);
+        }
+
+        Ok(())
+    }
+
+    /// Makes the stairs of the current map lead to the map, also after the current map is loaded again
+    fn link(&mut self, position: Point, to: &str) {
+        self.levels.link(&self.map_id, position, to);
+        self.levels.restore_links(&self.map_id, &mut self.current_map);
+    }
+
+    /// Updates what the player sees on a dark map
+    fn look(&mut self) {
+        if self.current_map.explored.is_none() {
+            return;
+        }
+
+        self.visible = self.current_map.visible_from(self.player, VIEW_RADIUS);
+        if let Some(explored) = &mut self.current_map.explored {
+            for (x, y) in self.visible.iter() {
+                explored[*y as usize][*x as usize] = true;
+            }
+        }
+    }
+
+    /// The player sees the tile now
+    fn can_see(&self, position: Point) -> bool {
+        self.current_map.explored.is_none() || self.visible.contains(&position)
+    }
+
+    /// Moves the player to the tile and picks up the loot there
+    fn step(&mut self, game_state: &mut GlobalState, position: Point) {
+        self.player = position;
+        self.look();
+        game_state.sound.set_listener(self.player);
+
+        while let Some(index) = self.current_map.loot.iter().position(|loot| loot.position == position) {
+            let loot = self.current_map.loot.swap_remove(index);
+            *self.inventory.entry(loot.kind.clone()).or_insert(0) += loot.count;
+
+            info!("Picked up {} x{}", loot.kind, loot.count);
+        }
+    }
+
+    /// Takes the next steps of the path. A monster in the way stops the walk.
+    fn walk(&mut self, game_state: &mut GlobalState, dt: Duration) {
+        if self.path.is_empty() {
+            return;
+        }
+
+        self.walk_time += dt;
+        while self.walk_time >= WALK_STEP {
+            self.walk_time -= WALK_STEP;
+
+            match self.path.pop_front() {
+                Some(next) if self.can_enter(next) => self.step(game_state, next),
+                _ => {
+                    self.path.clear();
+                    break;
+                }
+            }
+        }
     }
 
-    fn render_map(&mut self, render: &mut Render) {
-        for (i, row) in self.current_map.tiles.iter().enumerate() {
-            render.set_cursor_position((1, (i + 1) as u16));
+    /// Monsters near the player step towards them, or run away from a player in god mode.
+    /// The others wander around.
+    fn move_monsters(&mut self, dt: Duration, rng: &mut Rng) {
+        if self.monsters.is_empty() {
+            return;
+        }
+
+        self.monster_time += dt;
+        if self.monster_time < MONSTER_STEP {
+            return;
+        }
+        self.monster_time -= MONSTER_STEP;
+
+        // One map serves every monster until the player moves
+        if self.chase_map.as_ref().map_or(true, |(target, _)| *target != self.player) {
+            let map = DijkstraMap::new(&self.current_map, &[self.player], MONSTER_MOVEMENT);
+            self.chase_map = Some((self.player, map));
+        }
+
+        let chase_map = match &self.chase_map {
+            Some((_, map)) => map,
+            None => return,
+        };
+
+        let flee_map = match self.god_mode {
+            true => Some(chase_map.flee(&self.current_map)),
+            false => None,
+        };
+
+        for index in 0..self.monsters.len() {
+            let position = self.monsters[index].position;
+            let near = chase_map.value(position)
+                .map_or(false, |value| value <= CHASE_DISTANCE * pathfinding::ORTHOGONAL_STEP as i32);
+            let step = match near {
+                true => flee_map.as_ref().unwrap_or(chase_map)
+                    .downhill(&self.current_map, position, |tile| !self.is_occupied(tile)),
+                false if rng.chance(WANDER_CHANCE) => {
+                    let (x, y) = position;
+                    let neighbours = [(x + 1, y), (x, y + 1), (x.wrapping_sub(1), y), (x, y.wrapping_sub(1))];
 
-            for tile in row {
-                render.draw_raw(
-                    format!("{}{}", tile.bg_color(), tile.character).as_str());
+                    rng.choose(&neighbours).cloned()
+                        .filter(|(x, y)| self.current_map.is_walkable(*x, *y) && !self.is_occupied((*x, *y)))
+                },
+                false => None,
+            };
+
+            if let Some(step) = step {
+                self.monsters[index].position = step;
             }
         }
     }
+
+    fn quick_save(&self, random: &Random) -> GameResult<()> {
+        let path = save::saves_dir().join(save::QUICK_SAVE);
+        let mut save = self.save();
+        save.random = Some(random.clone());
+        save.store(&path)?;
+
+        info!("Game saved to {}", path.display());
+        Ok(())
+    }
+
+    fn handle_input(&mut self, input: &Controller) {
+
+    }
+
+    fn render_map(&mut self, render: &mut Render, strings: &Strings, seed: u64) {
+        for (y, row) in self.current_map.tiles.iter().enumerate() {
+            render.set_cursor_position((1, (y + 1) as u16));
+
+            // Unexplored tiles of a dark map are left black
+            let row: String = row.iter().enumerate()
+                .map(|(x, tile)| match self.current_map.is_explored((x as u16, y as u16)) {
+                    true => tile.draw(render),
+                    false => DEFAULT_TILE.draw(render),
+                })
+                .collect();
+            render.draw_raw(row.as_str());
+        }
+
+        for loot in self.current_map.loot.iter().filter(|loot| self.current_map.is_explored(loot.position)) {
+            self.render_on_tile(render, loot.position, loot.glyph(), color::Rgb(255, 220, 0));
+        }
+
+        for monster in self.monsters.iter().filter(|monster| self.can_see(monster.position)) {
+            self.render_on_tile(render, monster.position, monster.glyph(), color::Rgb(255, 60, 60));
+        }
+
+        self.render_on_tile(render, self.player, '@', color::Rgb(255, 255, 255));
+        self.render_status(render, strings, seed);
+    }
+
+    /// Draws the character over the tile keeping its background
+    fn render_on_tile(&self, render: &mut Render, (x, y): (u16, u16), character: char, fg: color::Rgb) {
+        if let Some(tile) = self.current_map.tiles.get(y as usize).and_then(|row| row.get(x as usize)) {
+            render.set_cursor_position((x + 1, y + 1));
+            render.draw_raw(format!("{}{}{}", render.bg(tile.bg_color()), render.fg(fg), character).as_str());
+        }
+    }
+
+    /// Status line under the map, the depth in a dungeon and the seed of the game are on the right
+    fn render_status(&self, render: &mut Render, strings: &Strings, seed: u64) {
+        let stat = |name: &str| self.stats.get(name).cloned().unwrap_or(0);
+        let items: u32 = self.inventory.values().sum();
+
+        // Low health is shown in red
+        let hp_color = match stat("hp") * 3 <= stat("max_hp") {
+            true => "red",
+            false => "green",
+        };
+
+        let god_mode = match self.god_mode {
+            true => format!("  [yellow][[{}][/]", strings.get("status.god")),
+            false => String::new(),
+        };
+
+        let status = format!("[b]{}[/b] [{}]{}/{}[/]  [b]{}[/b] {}  [b]{}[/b] {}  {}{}",
+                             strings.get("status.hp"), hp_color, stat("hp"), stat("max_hp"),
+                             strings.get("status.attack"), stat("attack"),
+                             strings.get("status.defense"), stat("defense"),
+                             strings.plural("status.items", items as i64, &[]), god_mode);
+        let depth = match dungeon::parse_id(&self.map_id) {
+            Some(Ok((_, _, depth))) => format!("[b]{}[/b]  ", strings.format("status.depth", &[("depth", &depth)])),
+            _ => String::new(),
+        };
+        let seed = format!("{}[dim]{}[/]", depth, strings.format("status.seed", &[("seed", &seed)]));
+        let padding = (WIDTH as usize)
+            .saturating_sub(unicode::width(&text::plain(&status)) + unicode::width(&text::plain(&seed)));
+        let status = format!("{}{}{}", status, " ".repeat(padding.max(1)), seed);
+
+        let style = Style::new().fg(render.theme.panel_text).bg(render.theme.panel);
+        render.set_cursor_position((1, HEIGHT + 1));
+        render.draw_raw(text::draw_spans(&text::parse(&status), &style, render).as_str());
+    }
 }
\ No newline at end of file
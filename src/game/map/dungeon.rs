@@ -0,0 +1,533 @@
+//! Procedural dungeon levels. A level is made from its layout, seed and depth, which
+//! are kept in the map id (`gen:caves:1234:2`), so the same id always gives the same
+//! level and a saved game comes back to it.
+//!
+//! Layouts: `rooms` (rooms joined by corridors), `caves` (cellular automaton) and
+//! `bsp` (binary space partition with a room in every leaf). Only the largest
+//! connected area is kept, the player starts on the up stairs and the down stairs
+//! are the farthest tile from them. The down stairs lead to the next depth of the
+//! same dungeon, the up stairs to the depth above.
+
+use std::str::FromStr;
+
+use crate::game::pathfinding::{DijkstraMap, Grid, Movement, Point, ORTHOGONAL_STEP};
+use crate::rng::Rng;
+use super::{Loot, Map, Monster, MapParseError, Stairs, HEIGHT, WIDTH, tile};
+
+pub const ID_PREFIX: &'static str = "gen";
+
+const FLOOR: char = '.';
+const WALL: char = 'X';
+pub const STAIRS_UP: char = '<';
+pub const STAIRS_DOWN: char = '>';
+
+const MAX_ROOMS: usize = 14;
+const ROOM_TRIES: usize = 200;
+const ROOM_WIDTH: (i32, i32) = (4, 14);
+const ROOM_HEIGHT: (i32, i32) = (3, 8);
+
+/// Walls at the start of the cave automaton
+const CAVE_FILL: f32 = 0.45;
+const CAVE_STEPS: usize = 5;
+
+/// Smallest BSP leaf, a room with its walls has to fit
+const MIN_LEAF: u16 = 8;
+
+/// Monsters don't appear closer to the start than this (in steps)
+const SAFE_DISTANCE: i32 = 8;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Layout {
+    Rooms,
+    Caves,
+    Bsp,
+}
+
+impl Layout {
+    pub const ALL: [Layout; 3] = [Layout::Rooms, Layout::Caves, Layout::Bsp];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Layout::Rooms => "rooms",
+            Layout::Caves => "caves",
+            Layout::Bsp => "bsp",
+        }
+    }
+}
+
+impl FromStr for Layout {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        Layout::ALL.iter().cloned()
+            .find(|layout| layout.name() == text)
+            .ok_or(format!("unknown layout {} (rooms, caves or bsp)", text))
+    }
+}
+
+/// What can appear on a level, with the relative chance and the first depth it appears at
+struct Spawn {
+    kind: &'static str,
+    weight: u32,
+    min_depth: u32,
+}
+
+const MONSTER_TABLE: [Spawn; 5] = [
+    Spawn { kind: "rat", weight: 10, min_depth: 1 },
+    Spawn { kind: "goblin", weight: 8, min_depth: 1 },
+    Spawn { kind: "skeleton", weight: 5, min_depth: 2 },
+    Spawn { kind: "orc", weight: 4, min_depth: 3 },
+    Spawn { kind: "troll", weight: 2, min_depth: 5 },
+];
+
+const LOOT_TABLE: [Spawn; 4] = [
+    Spawn { kind: "gold", weight: 10, min_depth: 1 },
+    Spawn { kind: "potion", weight: 6, min_depth: 1 },
+    Spawn { kind: "scroll", weight: 3, min_depth: 2 },
+    Spawn { kind: "sword", weight: 1, min_depth: 3 },
+];
+
+fn roll_spawn(rng: &mut Rng, table: &[Spawn], depth: u32) -> Option<&'static str> {
+    rng.weighted(table, |spawn| if spawn.min_depth <= depth { spawn.weight } else { 0 })
+        .map(|spawn| spawn.kind)
+}
+
+/// Id of the generated level
+pub fn id(layout: Layout, seed: u64, depth: u32) -> String {
+    format!("{}:{}:{}:{}", ID_PREFIX, layout.name(), seed, depth)
+}
+
+/// Layout, seed and depth of a generated level id, None if it is not one.
+/// The depth can be left out and is 1 then.
+pub fn parse_id(id: &str) -> Option<Result<(Layout, u64, u32), String>> {
+    let mut parts = id.split(':');
+    if parts.next() != Some(ID_PREFIX) {
+        return None;
+    }
+
+    let mut parse = || -> Result<(Layout, u64, u32), String> {
+        let layout = parts.next().ok_or(String::from("no layout"))?.parse()?;
+        let seed = parts.next().ok_or(String::from("no seed"))?
+            .parse().map_err(|_| String::from("bad seed"))?;
+        let depth = match parts.next() {
+            Some(depth) => depth.parse().map_err(|_| String::from("bad depth"))?,
+            None => 1,
+        };
+
+        Ok((layout, seed, depth))
+    };
+
+    Some(parse())
+}
+
+/// Layout of a dungeon link without a seed (`gen:<layout>`), which gets its seed
+/// when the player first enters it
+pub fn unseeded(id: &str) -> Option<Layout> {
+    match id.split(':').collect::<Vec<_>>().as_slice() {
+        [ID_PREFIX, layout] => layout.parse().ok(),
+        _ => None,
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Rect {
+    x: u16,
+    y: u16,
+    width: u16,
+    height: u16,
+}
+
+impl Rect {
+    fn center(&self) -> Point {
+        (self.x + self.width / 2, self.y + self.height / 2)
+    }
+
+    /// Overlaps the other one or touches it
+    fn touches(&self, other: &Rect) -> bool {
+        self.x <= other.x + other.width && other.x <= self.x + self.width
+            && self.y <= other.y + other.height && other.y <= self.y + self.height
+    }
+}
+
+/// Floor and walls while the level is made
+struct Cells {
+    floor: Vec<bool>,
+}
+
+impl Grid for Cells {
+    fn width(&self) -> u16 {
+        WIDTH
+    }
+
+    fn height(&self) -> u16 {
+        HEIGHT
+    }
+
+    fn cost(&self, position: Point) -> Option<u32> {
+        match self.is_floor(position) {
+            true => Some(1),
+            false => None,
+        }
+    }
+}
+
+impl Cells {
+    fn new() -> Self {
+        Cells { floor: vec![false; WIDTH as usize * HEIGHT as usize] }
+    }
+
+    fn is_floor(&self, (x, y): Point) -> bool {
+        x < WIDTH && y < HEIGHT && self.floor[y as usize * WIDTH as usize + x as usize]
+    }
+
+    /// The border of the map is always wall
+    fn set(&mut self, (x, y): Point, floor: bool) {
+        if x > 0 && y > 0 && x < WIDTH - 1 && y < HEIGHT - 1 {
+            self.floor[y as usize * WIDTH as usize + x as usize] = floor;
+        }
+    }
+
+    fn floor_tiles(&self) -> Vec<Point> {
+        (0..HEIGHT).flat_map(|y| (0..WIDTH).map(move |x| (x, y)))
+            .filter(|position| self.is_floor(*position))
+            .collect()
+    }
+
+    fn carve_room(&mut self, room: &Rect) {
+        for y in room.y..room.y + room.height {
+            for x in room.x..room.x + room.width {
+                self.set((x, y), true);
+            }
+        }
+    }
+
+    /// L-shaped corridor, the bend is picked at random
+    fn carve_corridor(&mut self, rng: &mut Rng, (x1, y1): Point, (x2, y2): Point) {
+        let bend = match rng.chance(0.5) {
+            true => (x2, y1),
+            false => (x1, y2),
+        };
+
+        for (from, to) in [((x1, y1), bend), (bend, (x2, y2))].iter() {
+            let (mut x, mut y) = *from;
+            self.set((x, y), true);
+
+            while (x, y) != *to {
+                if x != to.0 {
+                    x = if x < to.0 { x + 1 } else { x - 1 };
+                } else {
+                    y = if y < to.1 { y + 1 } else { y - 1 };
+                }
+
+                self.set((x, y), true);
+            }
+        }
+    }
+
+    fn wall_neighbours(&self, (x, y): Point) -> usize {
+        let mut walls = 0;
+
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                let (nx, ny) = (x as i32 + dx, y as i32 + dy);
+                if (dx, dy) != (0, 0) && !(nx >= 0 && ny >= 0 && self.is_floor((nx as u16, ny as u16))) {
+                    walls += 1;
+                }
+            }
+        }
+
+        walls
+    }
+
+    /// Fills every floor tile which can't be reached from the largest area. Returns a tile of the area.
+    fn keep_largest_area(&mut self) -> Option<Point> {
+        let mut unvisited = self.floor_tiles();
+        let mut largest: Option<(usize, Point)> = None;
+
+        while let Some(start) = unvisited.pop() {
+            let distances = DijkstraMap::new(self, &[start], Movement::FourWay);
+            let before = unvisited.len();
+            unvisited.retain(|tile| distances.value(*tile).is_none());
+
+            let size = before - unvisited.len() + 1;
+            if largest.map_or(true, |(largest, _)| size > largest) {
+                largest = Some((size, start));
+            }
+        }
+
+        let (_, start) = largest?;
+        let distances = DijkstraMap::new(self, &[start], Movement::FourWay);
+
+        for tile in self.floor_tiles() {
+            if distances.value(tile).is_none() {
+                self.set(tile, false);
+            }
+        }
+
+        Some(start)
+    }
+}
+
+fn rooms(rng: &mut Rng, cells: &mut Cells) {
+    let mut rooms: Vec<Rect> = vec![];
+
+    for _ in 0..ROOM_TRIES {
+        if rooms.len() == MAX_ROOMS {
+            break;
+        }
+
+        let width = rng.range(ROOM_WIDTH.0, ROOM_WIDTH.1) as u16;
+        let height = rng.range(ROOM_HEIGHT.0, ROOM_HEIGHT.1) as u16;
+        let room = Rect {
+            x: rng.range(1, (WIDTH - width - 1) as i32) as u16,
+            y: rng.range(1, (HEIGHT - height - 1) as i32) as u16,
+            width,
+            height,
+        };
+
+        if rooms.iter().any(|other| room.touches(other)) {
+            continue;
+        }
+
+        cells.carve_room(&room);
+        if let Some(previous) = rooms.last() {
+            cells.carve_corridor(rng, previous.center(), room.center());
+        }
+
+        rooms.push(room);
+    }
+}
+
+fn caves(rng: &mut Rng, cells: &mut Cells) {
+    for y in 0..HEIGHT {
+        for x in 0..WIDTH {
+            let floor = !rng.chance(CAVE_FILL);
+            cells.set((x, y), floor);
+        }
+    }
+
+    // A tile becomes wall when most of its neighbours are walls
+    for _ in 0..CAVE_STEPS {
+        let walls: Vec<usize> = (0..HEIGHT).flat_map(|y| (0..WIDTH).map(move |x| (x, y)))
+            .map(|position| cells.wall_neighbours(position))
+            .collect();
+
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                let walls = walls[y as usize * WIDTH as usize + x as usize];
+                let floor = match cells.is_floor((x, y)) {
+                    true => walls < 5,
+                    false => walls < 4,
+                };
+
+                cells.set((x, y), floor);
+            }
+        }
+    }
+}
+
+/// Splits the area in two until the parts are small and puts a room in each of them.
+/// Returns the center of a room in the area, the rooms of the two parts are joined.
+fn bsp(rng: &mut Rng, cells: &mut Cells, area: Rect) -> Point {
+    let can_split_x = area.width >= MIN_LEAF * 2;
+    let can_split_y = area.height >= MIN_LEAF * 2;
+
+    let split_x = match (can_split_x, can_split_y) {
+        (false, false) => {
+            let width = rng.range(3, area.width as i32 - 1) as u16;
+            let height = rng.range(3, area.height as i32 - 1) as u16;
+            let room = Rect {
+                x: area.x + rng.range(1, (area.width - width) as i32) as u16,
+                y: area.y + rng.range(1, (area.height - height) as i32) as u16,
+                width,
+                height,
+            };
+
+            cells.carve_room(&room);
+            return room.center();
+        },
+        (true, false) => true,
+        (false, true) => false,
+        // Tiles are twice as high as wide on the screen
+        (true, true) => area.width > area.height * 2 || (area.width * 2 >= area.height && rng.chance(0.5)),
+    };
+
+    let (first, second) = match split_x {
+        true => {
+            let at = rng.range(MIN_LEAF as i32, (area.width - MIN_LEAF) as i32 + 1) as u16;
+            (Rect { width: at, ..area }, Rect { x: area.x + at, width: area.width - at, ..area })
+        },
+        false => {
+            let at = rng.range(MIN_LEAF as i32, (area.height - MIN_LEAF) as i32 + 1) as u16;
+            (Rect { height: at, ..area }, Rect { y: area.y + at, height: area.height - at, ..area })
+        },
+    };
+
+    let first = bsp(rng, cells, first);
+    let second = bsp(rng, cells, second);
+    cells.carve_corridor(rng, first, second);
+
+    match rng.chance(0.5) {
+        true => first,
+        false => second,
+    }
+}
+
+/// Makes the level. Errors if the layout left no floor, which shouldn't happen.
+pub fn generate(layout: Layout, seed: u64, depth: u32) -> Result<Map, MapParseError> {
+    // Every depth of the dungeon is a different level, the first one is made from the seed itself
+    let mut rng = Rng::new(seed ^ (depth.saturating_sub(1) as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+    let mut cells = Cells::new();
+
+    match layout {
+        Layout::Rooms => rooms(&mut rng, &mut cells),
+        Layout::Caves => caves(&mut rng, &mut cells),
+        Layout::Bsp => {
+            bsp(&mut rng, &mut cells, Rect { x: 0, y: 0, width: WIDTH, height: HEIGHT });
+        },
+    }
+
+    cells.keep_largest_area().ok_or(MapParseError::new("generated level has no floor"))?;
+
+    let floor = cells.floor_tiles();
+    let start = *rng.choose(&floor).ok_or(MapParseError::new("generated level has no floor"))?;
+
+    let distances = DijkstraMap::new(&cells, &[start], Movement::FourWay);
+    let stairs_down = floor.iter().cloned()
+        .max_by_key(|tile| distances.value(*tile).unwrap_or(0))
+        .unwrap_or(start);
+
+    let mut tiles: Vec<Vec<_>> = (0..HEIGHT)
+        .map(|y| (0..WIDTH).map(|x| tile(if cells.is_floor((x, y)) { FLOOR } else { WALL })).collect())
+        .collect();
+    tiles[start.1 as usize][start.0 as usize] = tile(STAIRS_UP);
+    tiles[stairs_down.1 as usize][stairs_down.0 as usize] = tile(STAIRS_DOWN);
+
+    // Monsters and loot don't stand on each other, on the stairs or next to the start
+    let mut free: Vec<Point> = floor.into_iter()
+        .filter(|tile| *tile != stairs_down)
+        .filter(|tile| distances.value(*tile).map_or(false, |distance| distance >= SAFE_DISTANCE * ORTHOGONAL_STEP as i32))
+        .collect();
+    let mut take_free = |rng: &mut Rng| match free.len() {
+        0 => None,
+        len => Some(free.swap_remove(rng.range(0, len as i32) as usize)),
+    };
+
+    let mut monsters = vec![];
+    for _ in 0..3 + depth as i32 + rng.range(0, 4) {
+        if let (Some(kind), Some(position)) = (roll_spawn(&mut rng, &MONSTER_TABLE, depth), take_free(&mut rng)) {
+            monsters.push(Monster { kind: String::from(kind), position });
+        }
+    }
+
+    let mut loot = vec![];
+    for _ in 0..2 + rng.range(0, 3) {
+        if let (Some(kind), Some(position)) = (roll_spawn(&mut rng, &LOOT_TABLE, depth), take_free(&mut rng)) {
+            let count = match kind {
+                "gold" => rng.range(5, 20) as u32 * depth,
+                _ => 1,
+            };
+
+            loot.push(Loot { kind: String::from(kind), count, position });
+        }
+    }
+
+    let mut stairs = vec![Stairs { position: stairs_down, to: id(layout, seed, depth + 1) }];
+    if depth > 1 {
+        stairs.push(Stairs { position: start, to: id(layout, seed, depth - 1) });
+    }
+
+    Ok(Map {
+        tiles,
+        start,
+        monsters,
+        loot,
+        stairs,
+        // Dungeons are dark, the player only knows what they have seen
+        explored: Some(vec![vec![false; WIDTH as usize]; HEIGHT as usize]),
+        ..Map::empty()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEEDS: [u64; 4] = [0, 1, 42, 0xDEAD_BEEF];
+
+    /// Everything a level is made of, to compare two of them
+    fn describe(map: &Map) -> String {
+        let tiles: String = (0..HEIGHT)
+            .flat_map(|y| (0..WIDTH).map(move |x| (x, y)))
+            .map(|position| match (map.cost(position), map.glyph(position)) {
+                (None, _) => WALL,
+                (Some(_), Some(' ')) | (Some(_), None) => FLOOR,
+                (Some(_), Some(glyph)) => glyph,
+            })
+            .collect();
+        let monsters: Vec<_> = map.monsters.iter().map(|monster| (&monster.kind, monster.position)).collect();
+        let loot: Vec<_> = map.loot.iter().map(|loot| (&loot.kind, loot.count, loot.position)).collect();
+        let stairs: Vec<_> = map.stairs.iter().map(|stairs| (stairs.position, &stairs.to)).collect();
+
+        format!("{}\n{:?}\n{:?}\n{:?}\n{:?}", tiles, map.start, monsters, loot, stairs)
+    }
+
+    #[test]
+    fn same_seed_gives_same_level() {
+        for layout in Layout::ALL.iter() {
+            for seed in SEEDS.iter() {
+                for depth in 1..=3 {
+                    let first = generate(*layout, *seed, depth).unwrap();
+                    let second = generate(*layout, *seed, depth).unwrap();
+
+                    assert_eq!(describe(&first), describe(&second), "{}", id(*layout, *seed, depth));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn depths_are_different_levels() {
+        for layout in Layout::ALL.iter() {
+            let first = generate(*layout, 7, 1).unwrap();
+            let second = generate(*layout, 7, 2).unwrap();
+
+            assert_ne!(describe(&first), describe(&second), "{}", layout.name());
+        }
+    }
+
+    #[test]
+    fn every_floor_tile_is_reachable_from_the_start() {
+        for layout in Layout::ALL.iter() {
+            for seed in SEEDS.iter() {
+                let map = generate(*layout, *seed, 1).unwrap();
+                let distances = DijkstraMap::new(&map, &[map.start], Movement::FourWay);
+
+                for y in 0..HEIGHT {
+                    for x in 0..WIDTH {
+                        if map.cost((x, y)).is_some() {
+                            assert!(distances.value((x, y)).is_some(), "{} {:?}", id(*layout, *seed, 1), (x, y));
+                        }
+                    }
+                }
+
+                for position in map.stairs.iter().map(|stairs| stairs.position)
+                    .chain(map.monsters.iter().map(|monster| monster.position))
+                    .chain(map.loot.iter().map(|loot| loot.position)) {
+                    assert!(distances.value(position).is_some(), "{} {:?}", id(*layout, *seed, 1), position);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn stairs_link_the_depths() {
+        let map = generate(Layout::Rooms, 5, 2).unwrap();
+        let links: Vec<&str> = map.stairs.iter().map(|stairs| stairs.to.as_str()).collect();
+
+        assert!(links.contains(&id(Layout::Rooms, 5, 3).as_str()));
+        assert!(links.contains(&id(Layout::Rooms, 5, 1).as_str()));
+        assert_eq!(parse_id("gen:bsp:9"), Some(Ok((Layout::Bsp, 9, 1))));
+        assert_eq!(unseeded("gen:caves"), Some(Layout::Caves));
+    }
+}
@@ -0,0 +1,86 @@
+//! Levels the player has left. A level is kept as it was left (monsters killed,
+//! loot taken, tiles explored), so coming back finds it the same. Only the most
+//! recently left levels are kept, an older one is loaded again from scratch.
+//!
+//! Stairs links made while playing (a dungeon seeded on the first visit, the way
+//! back up from its first level) are kept apart from the levels and never
+//! forgotten, a level loaded again gets them back.
+
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::game::pathfinding::Point;
+use super::{Map, Stairs};
+
+/// Levels kept besides the current one
+const KEPT_LEVELS: usize = 8;
+
+pub struct Levels {
+    /// Map ids with their maps, the one left the longest ago first
+    levels: VecDeque<(String, Map)>,
+    /// Where the stairs of the maps lead, by map id and the position of the stairs
+    links: BTreeMap<String, BTreeMap<Point, String>>,
+}
+
+impl Levels {
+    pub fn new() -> Self {
+        Levels {
+            levels: VecDeque::new(),
+            links: BTreeMap::new(),
+        }
+    }
+
+    /// Takes the level out, None if it isn't kept
+    pub fn take(&mut self, id: &str) -> Option<Map> {
+        let index = self.levels.iter().position(|(level, _)| level == id)?;
+        self.levels.remove(index).map(|(_, map)| map)
+    }
+
+    /// Keeps the level the player leaves, forgetting the one left the longest ago if there are too many
+    pub fn keep(&mut self, id: &str, map: Map) {
+        self.take(id);
+
+        if self.levels.len() == KEPT_LEVELS {
+            if let Some((forgotten, _)) = self.levels.pop_front() {
+                log::info!("Forgot the level {}", forgotten);
+            }
+        }
+
+        self.levels.push_back((String::from(id), map));
+    }
+
+    /// Ids of the kept levels, the one left the longest ago first
+    pub fn ids(&self) -> impl Iterator<Item = &str> {
+        self.levels.iter().map(|(id, _)| id.as_str())
+    }
+
+    /// The kept levels with their ids, the one left the longest ago first
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Map)> {
+        self.levels.iter().map(|(id, map)| (id.as_str(), map))
+    }
+
+    /// Makes the stairs of the map lead to `to` from now on
+    pub fn link(&mut self, id: &str, position: Point, to: &str) {
+        self.links.entry(String::from(id)).or_default().insert(position, String::from(to));
+    }
+
+    /// All links as the map id, the position of the stairs and where they lead
+    pub fn links(&self) -> impl Iterator<Item = (&str, Point, &str)> {
+        self.links.iter()
+            .flat_map(|(id, links)| links.iter().map(move |(position, to)| (id.as_str(), *position, to.as_str())))
+    }
+
+    /// Points the stairs of the map the way they were linked
+    pub fn restore_links(&self, id: &str, map: &mut Map) {
+        let links = match self.links.get(id) {
+            Some(links) => links,
+            None => return,
+        };
+
+        for (position, to) in links.iter() {
+            match map.stairs.iter_mut().find(|stairs| stairs.position == *position) {
+                Some(stairs) => stairs.to = to.clone(),
+                None => map.stairs.push(Stairs { position: *position, to: to.clone() }),
+            }
+        }
+    }
+}
@@ -1,35 +1,56 @@
 use crate::game::{GlobalState, PlayResult};
+use crate::error::Result as GameResult;
 use crate::widgets::label::Label;
 use crate::render::{Drawable, Position, Coordinate};
 
 use termion::color;
-use termion::event::Key;
+use termion::event::{Key, MouseButton};
 use std::time::Duration;
+use std::hash::Hasher;
 
 use super::map;
+use super::sound::{Channel, Music};
+use super::settings;
 use super::exit_splash;
+use super::animation::{Animation, Easing, Tween};
+use super::transition::Effect;
+use super::input::MouseEvent;
 
 /// Menu GAME state implementation
 
 pub struct PlayState {
     buttons: [Label; 3],
+    button_slides: Vec<Tween<u16>>,
     selected_index: i8,
-    bg_music_started: bool
 }
 
 const BUTTON_NEW_GAME: u8 = 0;
 const BUTTON_SETTINGS: u8 = 1;
 const BUTTON_EXIT: u8 = 2;
 
-const BUTTON_SOUND_EXIT: &'static str = "assets/sound/button2.wav";
-const BUTTON_SOUND: &'static str = "assets/sound/button.wav";
+/// String keys of the buttons
+const BUTTON_TEXTS: [&'static str; 3] = ["menu.new_game", "menu.settings", "menu.exit"];
+
+const BUTTON_SOUND_EXIT: &'static str = "ui.button_exit";
+const BUTTON_SOUND: &'static str = "ui.button";
+
+const BUTTON_SLIDE_DURATION: Duration = Duration::from_millis(400);
+const EXIT_FADE_DURATION: Duration = Duration::from_millis(800);
+const NEW_GAME_DISSOLVE_DURATION: Duration = Duration::from_millis(400);
+
+/// A broken sound must not keep the player from using the menu, so it is only logged
+fn play_sound(game_state: &mut GlobalState, id: &str) {
+    if let Err(err) = game_state.sound.play(Channel::Ui, id) {
+        log::warn!("Failed to play {}: {}", id, err);
+    }
+}
 
 impl PlayState {
     pub fn new(state: &GlobalState) -> Self {
 
-        let new_game = Label::new("New GAME");
-        let settings = Label::new("Settings");
-        let exit = Label::new("Exit");
+        let new_game = Label::new(state.strings.get(BUTTON_TEXTS[0]).as_str());
+        let settings = Label::new(state.strings.get(BUTTON_TEXTS[1]).as_str());
+        let exit = Label::new(state.strings.get(BUTTON_TEXTS[2]).as_str());
 
         let mut this = PlayState {
             buttons: [
@@ -37,37 +58,55 @@ impl PlayState {
                 settings,
                 exit
             ],
+            button_slides: vec![],
             selected_index: 0,
-            bg_music_started: false
         };
 
-        for (i, button) in this.buttons.iter_mut().enumerate() {
+        // Buttons slide in from the bottom of the screen
+        for i in 0..this.buttons.len() {
             let y = state.render.term_size.height as f32 * 0.2
                 + (i * 5) as f32;
 
-            button.set_position(Position{
-                x: Coordinate::Centered,
-                y: Coordinate::Absolute(y as u16)
-            });
+            let slide = Tween::new(state.render.term_size.height, y as u16, BUTTON_SLIDE_DURATION)
+                .with_easing(Easing::EaseOut);
+
+            this.button_slides.push(slide);
         }
 
+        this.update_button_positions();
+
         this.buttons[this.selected_index as usize].set_selected(true);
 
         this
     }
 
-    fn on_button_pressed(&mut self, button: u8, game_state: &mut GlobalState) -> PlayResult {
-        return match button {
+    fn on_button_pressed(&mut self, button: u8, game_state: &mut GlobalState) -> GameResult<PlayResult> {
+        Ok(match button {
             BUTTON_EXIT => {
-                game_state.sound.play(BUTTON_SOUND_EXIT).unwrap();
-                std::thread::sleep(Duration::from_millis(800));
-                return PlayResult::Switch(Box::new(exit_splash::PlayState{}));
+                play_sound(game_state, BUTTON_SOUND_EXIT);
+
+                // Exit splash starts from the background of the theme
+                PlayResult::Switch(Box::new(exit_splash::PlayState::new(game_state)))
+                    .with_transition(Effect::fade(game_state.render.theme.background, EXIT_FADE_DURATION))
             },
             BUTTON_NEW_GAME => {
-                return PlayResult::Push(Box::new(map::PlayState::new()))
-            }
+                PlayResult::Push(Box::new(map::PlayState::new(game_state)?))
+                    .with_transition(Effect::dissolve(color::Rgb(0, 0, 0), NEW_GAME_DISSOLVE_DURATION))
+            },
+            BUTTON_SETTINGS => {
+                PlayResult::Push(Box::new(settings::PlayState::new(game_state)))
+            },
             _ => PlayResult::Still
-        };
+        })
+    }
+
+    fn update_button_positions(&mut self) {
+        for (button, slide) in self.buttons.iter_mut().zip(&self.button_slides) {
+            button.set_position(Position{
+                x: Coordinate::Centered,
+                y: Coordinate::Absolute(slide.value())
+            });
+        }
     }
 
     fn adjust_selected_index(&mut self) {
@@ -78,54 +117,89 @@ impl PlayState {
          }
     }
 
-    fn render(&mut self, game_state: &mut GlobalState) {
+    /// Index of the button under the mouse
+    fn button_at(&self, game_state: &GlobalState, position: (u16, u16)) -> Option<usize> {
+        self.buttons.iter().position(|button| game_state.render.contains(button, position))
+    }
 
-        for button in &self.buttons {
-            game_state.render.draw(button);
+    /// Moves the selection to the button, with the sound if it changed
+    fn select(&mut self, index: i8, game_state: &mut GlobalState) {
+        let previous = self.selected_index;
+
+        self.buttons[self.selected_index as usize].set_selected(false);
+        self.selected_index = index;
+        self.adjust_selected_index();
+        self.buttons[self.selected_index as usize].set_selected(true);
+
+        if self.selected_index != previous {
+            play_sound(game_state, BUTTON_SOUND);
         }
     }
+
 }
 
-const BG_MUSIC: &'static str = "assets/sound/menu_bg.wav";
+const BG_MUSIC: &'static str = "music.menu";
 
 impl super::PlayState for PlayState {
 
-    fn play(&mut self, game_state: &mut GlobalState) -> PlayResult {
-        if !self.bg_music_started {
-            game_state.sound.play(BG_MUSIC).unwrap();
-            self.bg_music_started = true;
+    fn update(&mut self, _game_state: &mut GlobalState, dt: Duration) -> GameResult<PlayResult> {
+        for slide in &mut self.button_slides {
+            slide.advance(dt);
         }
 
-        game_state.render.clear_color = Box::new(color::Rgb(255, 255, 255));
+        self.update_button_positions();
+
+        Ok(PlayResult::Still)
+    }
+
+    fn render(&mut self, game_state: &mut GlobalState) {
+        game_state.render.clear_color = game_state.render.theme.background;
         game_state.render.clear_screen();
 
-        self.render(game_state);
+        // The language could be changed in the settings
+        for (button, key) in self.buttons.iter_mut().zip(BUTTON_TEXTS.iter()) {
+            button.text = game_state.strings.get(key);
+            game_state.render.draw(button);
+        }
+    }
 
-        PlayResult::Still
+    fn music(&self) -> Music {
+        Music::Track(BG_MUSIC)
+    }
+
+    fn hash_state(&self, hasher: &mut dyn Hasher) {
+        hasher.write_i8(self.selected_index);
     }
 
     fn to_string(&self) -> String {
         String::from("MenuState")
     }
 
-    fn on_key_pressed(&mut self, game_state: &mut GlobalState, key: Key) -> PlayResult {
+    fn on_key_pressed(&mut self, game_state: &mut GlobalState, key: Key) -> GameResult<PlayResult> {
+        // Don't make the player wait for the buttons to slide in
+        for slide in &mut self.button_slides {
+            slide.finish();
+        }
+
         self.buttons[self.selected_index as usize].set_selected(false);
 
+        let mut moved = false;
         match key {
             Key::Down => {
                 self.selected_index = self.selected_index + 1;
-                game_state.sound.play(BUTTON_SOUND).unwrap();
+                moved = true;
             }
             Key::Up => {
                 self.selected_index = self.selected_index - 1;
-                game_state.sound.play(BUTTON_SOUND).unwrap();
+                moved = true;
             }
             Key::Esc => {
-                return PlayResult::Push(Box::new(map::PlayState::new()));
+                return Ok(PlayResult::Push(Box::new(map::PlayState::new(game_state)?)));
             }
             Key::Char(char) => {
                 // Enter on exit label
                 if (char as u8) == 10 {
+                    self.buttons[self.selected_index as usize].set_selected(true);
                     return self.on_button_pressed(self.selected_index as u8, game_state);
                 }
             }
@@ -135,6 +209,32 @@ impl super::PlayState for PlayState {
         self.adjust_selected_index();
 
         self.buttons[self.selected_index as usize].set_selected(true);
-        PlayResult::Still
+
+        if moved {
+            play_sound(game_state, BUTTON_SOUND);
+        }
+
+        Ok(PlayResult::Still)
+    }
+
+    fn on_mouse(&mut self, game_state: &mut GlobalState, event: MouseEvent) -> GameResult<PlayResult> {
+        match event {
+            MouseEvent::Move(x, y) => {
+                if let Some(index) = self.button_at(game_state, (x, y)) {
+                    self.select(index as i8, game_state);
+                }
+            },
+            MouseEvent::Press(MouseButton::Left, x, y) => {
+                if let Some(index) = self.button_at(game_state, (x, y)) {
+                    self.select(index as i8, game_state);
+                    return self.on_button_pressed(index as u8, game_state);
+                }
+            },
+            MouseEvent::Press(MouseButton::WheelDown, _, _) => self.select(self.selected_index + 1, game_state),
+            MouseEvent::Press(MouseButton::WheelUp, _, _) => self.select(self.selected_index - 1, game_state),
+            _ => {}
+        }
+
+        Ok(PlayResult::Still)
     }
 }
\ No newline at end of file
@@ -0,0 +1,91 @@
+//! Frame time and FPS statistics of the game loop
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// How many last frames are used to compute the statistics
+const WINDOW_SIZE: usize = 60;
+
+pub struct FrameMetrics {
+    frame_starts: VecDeque<Instant>,
+    frame_times: VecDeque<Duration>,
+    ticks: u64,
+    frames: u64,
+    dropped_ticks: u64,
+}
+
+impl FrameMetrics {
+    pub fn new() -> Self {
+        FrameMetrics {
+            frame_starts: VecDeque::with_capacity(WINDOW_SIZE),
+            frame_times: VecDeque::with_capacity(WINDOW_SIZE),
+            ticks: 0,
+            frames: 0,
+            dropped_ticks: 0,
+        }
+    }
+
+    pub fn begin_frame(&mut self, now: Instant) {
+        if self.frame_starts.len() == WINDOW_SIZE {
+            self.frame_starts.pop_front();
+        }
+
+        self.frame_starts.push_back(now);
+    }
+
+    /// Records how long the frame took, not counting the time spent sleeping
+    pub fn end_frame(&mut self, frame_time: Duration) {
+        if self.frame_times.len() == WINDOW_SIZE {
+            self.frame_times.pop_front();
+        }
+
+        self.frame_times.push_back(frame_time);
+        self.frames += 1;
+    }
+
+    pub fn record_tick(&mut self) {
+        self.ticks += 1;
+    }
+
+    /// Ticks skipped because the loop fell too far behind
+    pub fn record_dropped_ticks(&mut self, count: u64) {
+        self.dropped_ticks += count;
+    }
+
+    pub fn fps(&self) -> f32 {
+        let (first, last) = match (self.frame_starts.front(), self.frame_starts.back()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return 0.0,
+        };
+
+        let elapsed = last.duration_since(*first).as_secs_f32();
+        if elapsed <= 0.0 {
+            return 0.0;
+        }
+
+        (self.frame_starts.len() - 1) as f32 / elapsed
+    }
+
+    pub fn average_frame_time(&self) -> Duration {
+        if self.frame_times.is_empty() {
+            return Duration::default();
+        }
+
+        self.frame_times.iter().sum::<Duration>() / self.frame_times.len() as u32
+    }
+
+    pub fn max_frame_time(&self) -> Duration {
+        self.frame_times.iter().max().cloned().unwrap_or_default()
+    }
+
+    /// One line summary for the debug overlay
+    pub fn summary(&self) -> String {
+        format!("FPS {:.1} | frame {:.1}ms (max {:.1}ms) | frames {} | ticks {} | dropped {}",
+                self.fps(),
+                self.average_frame_time().as_secs_f32() * 1000.0,
+                self.max_frame_time().as_secs_f32() * 1000.0,
+                self.frames,
+                self.ticks,
+                self.dropped_ticks)
+    }
+}
@@ -1,19 +1,29 @@
-use std::error::Error;
 use std::fmt::Debug;
 
 use crate::render;
+use crate::config::{self, Config};
+use crate::assets::{AssetKind, AssetManager};
+use crate::logging::LogBuffer;
+use crate::theme::{self, Theme};
+use crate::i18n::{self, Strings};
+use crate::error::{Error, Result as GameResult};
+use std::rc::Rc;
 
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::io::Write;
 use termion::raw::IntoRawMode;
 use termion::screen;
-use log::{info, error};
+use termion::input::MouseTerminal;
+use input::{Input, MouseEvent};
+use log::{info, warn, error};
 
 use crate::widgets::label::Label;
-use crate::render::Coordinate;
-use std::panic::PanicInfo;
-use std::any::TypeId;
-use backtrace::Backtrace;
+use crate::rng::{self, Random};
+use crate::render::{Coordinate, Drawable};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::any::Any;
+use std::hash::{Hash, Hasher};
 use termion::event::Key;
 
 mod menu;
@@ -22,7 +32,21 @@ mod start_splash;
 mod input;
 mod sound;
 mod map;
+mod pathfinding;
 mod loggers;
+mod animation;
+mod metrics;
+mod transition;
+mod settings;
+mod save;
+mod console;
+mod crash;
+mod error_dialog;
+mod replay;
+
+pub use save::Save;
+pub use replay::Recording;
+pub use map::is_generated as is_generated_map;
 
 /// Here is a state system implemented
 /// Inspired by Veloren project
@@ -37,145 +61,740 @@ enum PlayResult {
     Push(Box<dyn PlayState>),
     /// Replace the current state with another
     Switch(Box<dyn PlayState>),
+    /// Apply the result with a transition effect between the outgoing and incoming states
+    Transition(transition::Effect, Box<PlayResult>),
     /// Do nothing
     Still,
 }
 
+impl PlayResult {
+    fn with_transition(self, effect: transition::Effect) -> PlayResult {
+        PlayResult::Transition(effect, Box::new(self))
+    }
+}
+
+/// Lets the console commands get the concrete type of a state
+trait AsAny {
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T: Any> AsAny for T {
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
 /// Represents some GAME state (e.g. menu, battle and so on)
-trait PlayState {
-    fn play(&mut self, game_state: &mut GlobalState) -> PlayResult;
+trait PlayState: AsAny {
+    /// Advances the state by one fixed tick. `dt` is always the same tick duration.
+    fn update(&mut self, _game_state: &mut GlobalState, _dt: Duration) -> GameResult<PlayResult> {
+        Ok(PlayResult::Still)
+    }
+
+    /// Draws the state. Called once per frame after all pending ticks are processed.
+    fn render(&mut self, _game_state: &mut GlobalState) {}
+
+    /// Called when the state gets on the top of the stack
+    fn on_enter(&mut self, _game_state: &mut GlobalState) -> GameResult<()> {
+        Ok(())
+    }
+
+    /// Called when the state stops being the top of the stack (popped, replaced or covered)
+    fn on_leave(&mut self, _game_state: &mut GlobalState) {}
+
+    /// Background music of the state. Applied when the state is on the top of the stack.
+    fn music(&self) -> sound::Music {
+        sound::Music::Keep
+    }
+
+    /// The game to save when this state is the one being played, e.g. after a crash
+    fn snapshot(&self) -> Option<Save> {
+        None
+    }
+
+    /// Feeds what the state is made of to the hasher, for comparing the end of a replay
+    fn hash_state(&self, _hasher: &mut dyn Hasher) {}
+
     fn to_string(&self) -> String;
-    fn on_key_pressed(&mut self, game_state: &mut GlobalState, key: Key) -> PlayResult {
-        PlayResult::Still
+    fn on_key_pressed(&mut self, _game_state: &mut GlobalState, _key: Key) -> GameResult<PlayResult> {
+        Ok(PlayResult::Still)
+    }
+
+    /// Clicks, wheel and motion of the mouse
+    fn on_mouse(&mut self, _game_state: &mut GlobalState, _event: MouseEvent) -> GameResult<PlayResult> {
+        Ok(PlayResult::Still)
     }
 }
 
 pub struct GlobalState {
+    assets: Rc<AssetManager>,
     render: render::Render,
     input: input::Controller,
     sound: sound::Manager,
+    /// User settings saved between runs
+    settings: Config,
+    /// Color mode chosen in the settings, the one used can be overridden from the command line
+    color_mode: render::ColorMode,
+    /// Texts in the current language
+    strings: Strings,
+    /// Every random number of the game comes from here
+    random: Random,
+}
+
+const SETTINGS_FILE: &'static str = "settings.cfg";
+const COLOR_MODE_KEY: &'static str = "video.color";
+const THEME_KEY: &'static str = "ui.theme";
+const LANGUAGE_KEY: &'static str = "ui.language";
+
+/// Strings of the language, English or no strings at all if they can't be loaded
+fn load_strings(assets: &AssetManager, language: &str) -> Strings {
+    Strings::load(assets, language)
+        .or_else(|err| {
+            warn!("Failed to load language {}: {}", language, err);
+            Strings::load(assets, i18n::DEFAULT_LANGUAGE)
+        })
+        .unwrap_or_else(|err| {
+            warn!("Failed to load language {}: {}", i18n::DEFAULT_LANGUAGE, err);
+            Strings::empty()
+        })
 }
 
 impl GlobalState {
-    pub fn new() -> GlobalState {
+    pub fn new(assets: Rc<AssetManager>, sound_enabled: bool) -> GlobalState {
+        let settings = Config::load(config::config_dir().join(SETTINGS_FILE).as_path());
+
+        let mut sound = match sound_enabled {
+            true => sound::Manager::new(assets.clone()),
+            false => sound::Manager::silent(assets.clone()),
+        };
+        sound.load_settings(&settings);
+
+        let color_mode = settings.get_parsed(COLOR_MODE_KEY).unwrap_or(render::ColorMode::Auto);
+        let mut render = render::Render::new();
+        render.set_color_mode(color_mode);
+
+        let theme_name = settings.get(THEME_KEY).unwrap_or(theme::DEFAULT_THEME);
+        render.theme = Theme::load(theme_name).unwrap_or_else(|err| {
+            warn!("Failed to load theme {}: {}", theme_name, err);
+            Theme::default()
+        });
+
+        let language = settings.get(LANGUAGE_KEY).map(String::from)
+            .or_else(|| i18n::system_language().filter(|language| assets.exists(AssetKind::Lang, language)))
+            .unwrap_or(String::from(i18n::DEFAULT_LANGUAGE));
+        let strings = load_strings(&assets, &language);
 
         GlobalState {
-            render: render::Render::new(),
+            assets,
+            render,
             input: input::Controller::new(),
-            sound: sound::Manager::new(),
+            sound,
+            settings,
+            color_mode,
+            strings,
+            random: Random::new(rng::random_seed()),
         }
     }
 
+    /// The language, theme and color mode the session was recorded with
+    fn use_recorded_settings(&mut self, recording: &Recording) {
+        self.color_mode = recording.color_mode;
+        self.render.set_color_mode(recording.color_mode);
 
+        self.render.theme = Theme::load(&recording.theme).unwrap_or_else(|err| {
+            warn!("Failed to load theme {}: {}", recording.theme, err);
+            Theme::default()
+        });
+
+        self.strings = load_strings(&self.assets, &recording.language);
+    }
+
+    /// Writes the current settings of all subsystems to the settings file
+    fn save_settings(&mut self) {
+        self.sound.store_settings(&mut self.settings);
+        self.settings.set(COLOR_MODE_KEY, self.color_mode);
+        self.settings.set(THEME_KEY, &self.render.theme.name);
+        self.settings.set(LANGUAGE_KEY, self.strings.language());
+
+        if let Err(err) = self.settings.save() {
+            warn!("Failed to save settings: {}", err);
+        }
+    }
+}
+
+/// Assets the GAME can't start without
+pub const REQUIRED_ASSETS: [(AssetKind, &'static str); 3] = [
+    (AssetKind::Map, map::MAP_START),
+    (AssetKind::Sound, sound::MANIFEST),
+    (AssetKind::Lang, i18n::DEFAULT_LANGUAGE),
+];
+
+/// The first state of the GAME
+pub enum Start {
+    Splash,
+    Menu,
+    /// Map id
+    Map(String),
+    Save(Save),
 }
 
 pub struct Game {
+    assets: Rc<AssetManager>,
+    start: Start,
+    sound_enabled: bool,
+    color_mode: render::ColorMode,
+    /// Seed of the game, a new one for every run if not set
+    seed: Option<u64>,
     states: Vec<Box<dyn PlayState>>,
     fps: u64,
-    mpf: u64
+    tick: Duration,
+    metrics: metrics::FrameMetrics,
+    show_metrics: bool,
+    log_console: Option<loggers::LogConsole>,
+    console: console::Console,
+    transition: Option<transition::Transition>,
+    /// Ticks run since the start, the clock of the recordings
+    ticks: u64,
+    /// File to record the session to
+    record_path: Option<PathBuf>,
+    recorder: Option<replay::Recorder>,
+    replay: Option<replay::Player>,
 }
 
+/// How many ticks can be processed in one frame before the loop gives up catching up
+const MAX_CATCH_UP_TICKS: u32 = 5;
+
+/// Above this the tick would be shorter than a millisecond
+pub const MAX_FPS: u64 = 1000;
+
 impl Game {
-    pub fn new() -> Self {
+    pub fn new(assets: AssetManager) -> Self {
         let states : Vec<Box<dyn PlayState>> = vec![];
-        let fps = 10;
-        let mpf = 1000 / fps;
+        let fps = 30;
+        let tick = Duration::from_millis(1000 / fps);
 
-        Game { states, fps, mpf }
+        Game {
+            assets: Rc::new(assets),
+            start: Start::Splash,
+            sound_enabled: true,
+            color_mode: render::ColorMode::Auto,
+            seed: None,
+            states,
+            fps,
+            tick,
+            metrics: metrics::FrameMetrics::new(),
+            show_metrics: false,
+            log_console: None,
+            console: console::Console::new(),
+            transition: None,
+            ticks: 0,
+            record_path: None,
+            recorder: None,
+            replay: None,
+        }
+    }
+
+    pub fn set_fps(&mut self, fps: u64) {
+        self.fps = fps.clamp(1, MAX_FPS);
+        self.tick = Duration::from_millis(1000 / self.fps);
+    }
+
+    /// Enables the log console (F2)
+    pub fn set_log_buffer(&mut self, buffer: LogBuffer) {
+        crash::set_log_buffer(buffer.clone());
+        self.log_console = Some(loggers::LogConsole::new(buffer));
+    }
+
+    pub fn set_start(&mut self, start: Start) {
+        self.start = start;
+    }
+
+    pub fn set_sound_enabled(&mut self, enabled: bool) {
+        self.sound_enabled = enabled;
+    }
+
+    pub fn set_color_mode(&mut self, mode: render::ColorMode) {
+        self.color_mode = mode;
+    }
+
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = Some(seed);
+    }
+
+    /// Records the session to the file
+    pub fn set_record(&mut self, path: PathBuf) {
+        self.record_path = Some(path);
+    }
+
+    /// Plays the recording instead of the player's input. `speed` multiplies the speed of the playback,
+    /// a headless replay draws nothing and runs as fast as it can.
+    pub fn set_replay(&mut self, path: PathBuf, mut recording: Recording, speed: f32, headless: bool) {
+        self.start = std::mem::replace(&mut recording.start, Start::Splash);
+        self.seed = Some(recording.seed);
+        if headless {
+            self.sound_enabled = false;
+        }
+
+        self.replay = Some(replay::Player::new(&path, recording, speed, headless));
+    }
+
+    fn is_headless(&self) -> bool {
+        self.replay.as_ref().map_or(false, replay::Player::is_headless)
+    }
+
+    fn start_state(&self, game_state: &GlobalState) -> GameResult<Box<dyn PlayState>> {
+        Ok(match &self.start {
+            Start::Splash => Box::new(start_splash::PlayState::new(game_state)),
+            Start::Menu => Box::new(menu::PlayState::new(game_state)),
+            Start::Map(id) => Box::new(map::PlayState::load(game_state, id)?),
+            Start::Save(save) => Box::new(map::PlayState::from_save(game_state, save)?),
+        })
     }
 
     /// Starts the GAME
-    pub fn run(&mut self) -> Result<(), String> {
-        let mut stdout = std::io::stdout().into_raw_mode();
+    pub fn run(&mut self) -> GameResult<()> {
+        let mut stdout: Box<dyn Write> = match self.is_headless() {
+            true => Box::new(std::io::sink()),
+            false => {
+                let stdout = std::io::stdout().into_raw_mode().map_err(Error::Terminal)?;
+                Box::new(MouseTerminal::from(screen::AlternateScreen::from(stdout)))
+            },
+        };
+        write!(stdout, "{}", input::ENABLE_HOVER).map_err(Error::Terminal)?;
+        let mut global_state = GlobalState::new(self.assets.clone(), self.sound_enabled);
+        if let Some(player) = &self.replay {
+            global_state.use_recorded_settings(player.recording());
+        }
+
+        if self.color_mode != render::ColorMode::Auto {
+            global_state.render.set_color_mode(self.color_mode);
+        }
+        info!("Color mode: {}", global_state.render.color_mode());
+
+        // A loaded game goes on with its own random streams unless another seed is asked for
+        match (&self.start, self.seed) {
+            (_, Some(seed)) => global_state.random = Random::new(seed),
+            (Start::Save(Save { random: Some(random), .. }), None) => global_state.random = random.clone(),
+            _ => {}
+        }
+        info!("Seed: {}", global_state.random.seed());
+
+        // The layout of the screens, and so what the recorded clicks hit, depends on the size
+        if let Some(player) = &self.replay {
+            let (width, height) = player.recording().size;
+            let size = render::TermSize { width, height };
 
-        if stdout.is_err() {
-            return Err(format!("Failed to set terminal into raw mode"));
+            match player.is_headless() {
+                true => global_state.render.set_headless(size),
+                false => global_state.render.term_size = size,
+            }
+        }
+
+        if let Some(path) = &self.record_path {
+            self.recorder = replay::Recorder::new(path, &global_state, &self.start);
+
+            if self.recorder.is_none() {
+                warn!("A game started from a save can't be recorded");
+            }
         }
 
-        let mut stdout = screen::AlternateScreen::from(stdout.unwrap());
-        let mut global_state = GlobalState::new();
+        let start_state = self.start_state(&global_state)?;
+        self.apply(&mut global_state, PlayResult::Push(start_state));
+
+        std::panic::set_hook(Box::new(crash::panic_hook));
 
-        self.states.push(Box::new(start_splash::PlayState::new()));
+        let result = panic::catch_unwind(AssertUnwindSafe(|| self.main_loop(&mut global_state, &mut stdout)));
+        // Panics after the GAME are reported by the default hook again
+        let _ = panic::take_hook();
+        let _ = write!(stdout, "{}", input::DISABLE_HOVER);
 
-        std::panic::set_hook(Box::new(panic_handler));
+        match result {
+            Ok(result) => {
+                let hash = self.state_hash(&global_state);
+                result?;
+
+                if let Some(recorder) = self.recorder.take() {
+                    recorder.finish(self.ticks, Some(hash))?;
+                }
+
+                match &self.replay {
+                    Some(player) => player.check(hash),
+                    None => Ok(()),
+                }
+            },
+            Err(_) => {
+                let recovery = self.save_recovery(&global_state);
+
+                // The replay of the recording runs into the same crash
+                if let Some(recorder) = self.recorder.take() {
+                    if let Err(err) = recorder.finish(self.ticks, None) {
+                        error!("Failed to write the recording: {}", err);
+                    }
+                }
+
+                // Leave the raw mode and the alternate screen before the report is printed
+                drop(global_state);
+                drop(stdout);
+                let _ = std::io::stdout().flush();
+
+                Err(Error::Panic(crash::report(recovery)))
+            }
+        }
+    }
+
+    /// Runs the frames until the GAME is stopped
+    fn main_loop(&mut self, global_state: &mut GlobalState, stdout: &mut impl Write) -> GameResult<()> {
+        let mut previous = Instant::now();
+        let mut lag = Duration::default();
 
         let mut playing = true;
         while playing {
-            let mut current_state = match self.states.last_mut() {
-                None => {
-                    playing = false;
-                    continue;
-                },
-                Some(state) => state
+            let frame_start = Instant::now();
+            lag += match &mut self.replay {
+                Some(player) => player.game_time(frame_start - previous, self.tick),
+                None => frame_start - previous,
             };
+            previous = frame_start;
+            self.metrics.begin_frame(frame_start);
 
-            info!("Current state: {}", current_state.to_string());
+            // A headless replay has no terminal to read
+            let mut polled = vec![];
+            if !self.is_headless() {
+                while let Some(input) = global_state.input.poll() {
+                    polled.push(input);
+                }
+            }
 
-            let mut result = PlayResult::Still;
-            if let Some(key) = global_state.input.get_pressed_key() {
-                result = current_state.on_key_pressed(&mut global_state, key);
+            // In a replay the keys control the playback, the recorded input is handled tick by tick below
+            for input in polled {
+                playing = match &mut self.replay {
+                    Some(player) => player.control(input),
+                    None => {
+                        if let Some(recorder) = &mut self.recorder {
+                            recorder.record(self.ticks, input);
+                        }
 
-                match key {
-                    Key::Esc => {
-                        info!("Esc pressed. Exit game from state {}", current_state.to_string());
-                        break;
+                        self.handle_input(global_state, input)
                     },
-                    _ => {}
+                };
+
+                if !playing {
+                    break;
                 }
             }
 
-            if let PlayResult::Still = result {
-                result = current_state.play(&mut global_state);
-            }
+            let max_ticks = self.replay.as_ref().map_or(MAX_CATCH_UP_TICKS, |player| player.catch_up_ticks(MAX_CATCH_UP_TICKS));
+
+            let mut ticks = 0;
+            while playing && lag >= self.tick {
+                // The recorded input comes right before the tick it came before in the session
+                let due = match &mut self.replay {
+                    Some(player) => player.due(self.ticks),
+                    None => vec![],
+                };
+
+                for input in due {
+                    playing = self.handle_input(global_state, input);
+                    if !playing {
+                        break;
+                    }
+                }
 
-            match result {
-                PlayResult::Shutdown => {
-                    info!("Shutdown state");
+                if !playing || self.replay.as_ref().map_or(false, |player| player.is_finished(self.ticks)) {
                     playing = false;
-                },
-                PlayResult::Pop => {
-                    info!("Pop state");
-                    self.states.pop().expect("Empty state in queue!");
-                },
-                PlayResult::Push(state) => {
-                    info!("Push state: {}", state.to_string());
-                    self.states.push(state);
-                },
-                PlayResult::Switch(state) => {
-                    info!("Switch to state: from {} to {}", current_state.to_string(), state.to_string());
-                    self.states.pop().expect("Empty state in queue!");
-                    self.states.push(state);
+                    break;
+                }
+
+                if ticks == max_ticks {
+                    let dropped = (lag.as_nanos() / self.tick.as_nanos()) as u64;
+                    warn!("Game loop is {} ticks behind, skipping them", dropped);
+
+                    self.metrics.record_dropped_ticks(dropped);
+                    lag = Duration::default();
+                    break;
+                }
+
+                lag -= self.tick;
+                ticks += 1;
+                self.ticks += 1;
+                self.metrics.record_tick();
+                global_state.sound.update(self.tick);
+
+                if let Some(transition) = &mut self.transition {
+                    let result = transition.advance(self.tick);
+                    if transition.is_finished() {
+                        self.transition = None;
+                    }
+
+                    if let Some(result) = result {
+                        playing = self.apply(global_state, result);
+                    }
+
                     continue;
+                }
+
+                let current_state = match self.states.last_mut() {
+                    None => {
+                        playing = false;
+                        break;
+                    },
+                    Some(state) => state
+                };
+
+                playing = match current_state.update(global_state, self.tick) {
+                    Ok(result) => self.apply(global_state, result),
+                    Err(err) => self.show_error(global_state, err, true),
+                };
+            }
+
+            if !playing {
+                break;
+            }
+
+            if self.is_headless() {
+                continue;
+            }
+
+            match self.states.last_mut() {
+                None => break,
+                Some(state) => {
+                    global_state.sound.set_music(state.music());
+                    state.render(global_state);
                 },
-                PlayResult::Still => {}
             }
 
-            stdout.flush().unwrap();
-            std::thread::sleep(Duration::from_millis(self.mpf));
+            if let Some(transition) = &self.transition {
+                transition.render(&mut global_state.render);
+            }
+
+            if let Some(console) = self.log_console.as_ref().filter(|console| console.is_visible()) {
+                console.render(&mut global_state.render);
+            }
+
+            if self.console.is_visible() {
+                self.console.render(&mut global_state.render);
+            }
+
+            if self.show_metrics {
+                self.render_metrics(global_state);
+            }
+
+            if let Some(player) = &self.replay {
+                let mut label = Label::new(player.status(self.ticks).as_str());
+                label.set_color(global_state.render.theme.warning);
+                label.set_position(render::Position {
+                    x: Coordinate::FromBorder(label.get_width()),
+                    y: Coordinate::Absolute(1),
+                });
+
+                global_state.render.draw(&label);
+            }
+
+            stdout.flush().map_err(Error::Terminal)?;
+
+            let frame_time = frame_start.elapsed();
+            self.metrics.end_frame(frame_time);
+
+            // Sleep until the next tick is due, a replay just keeps the frame rate
+            let wait = match &self.replay {
+                Some(_) => self.tick.checked_sub(frame_time),
+                None => self.tick.checked_sub(lag + frame_time),
+            };
+
+            if let Some(wait) = wait {
+                std::thread::sleep(wait);
+            }
         }
 
         info!("Shutdown the GAME");
         Ok(())
     }
-}
 
-fn panic_handler(info: &PanicInfo) {
-    error!("Panic payload is {:?}", info.payload().type_id());
+    /// Handles the key or mouse event. Returns false if the GAME should be stopped.
+    fn handle_input(&mut self, global_state: &mut GlobalState, input: Input) -> bool {
+        let current_state = match self.states.last_mut() {
+            None => return true,
+            Some(state) => state
+        };
 
-    let backtrace = Backtrace::new();
-    if let Some(s) = info.payload().downcast_ref::<String>() {
-        if let Some(location) = info.location() {
-            error!("Panic: {} in {}", s, location);
-        } else {
-            error!("Panic: {}", s);
+        let key = match input {
+            Input::Key(key) => key,
+            Input::Mouse(event) => {
+                // The console takes the mouse while it is open, states don't get it during a transition
+                let result = match (self.console.is_visible(), &self.transition) {
+                    (true, _) => Ok(self.console.on_mouse(event)),
+                    (false, None) => current_state.on_mouse(global_state, event),
+                    (false, Some(_)) => Ok(PlayResult::Still),
+                };
+
+                return match result {
+                    Ok(result) => self.apply(global_state, result),
+                    Err(err) => self.show_error(global_state, err, false),
+                };
+            }
+        };
+
+        if self.console.is_visible() {
+            let result = self.console.on_key_pressed(global_state, current_state.as_mut(), key);
+            if !self.console.is_visible() {
+                global_state.render.clear_screen();
+            }
+
+            return self.apply(global_state, result);
+        }
+
+        if key == console::TOGGLE_KEY {
+            self.console.toggle();
+            return true;
+        }
+
+        // States don't get the input while a transition is in progress
+        let result = match self.transition {
+            None => current_state.on_key_pressed(global_state, key),
+            Some(_) => Ok(PlayResult::Still),
+        };
+
+        match key {
+            Key::Esc => {
+                info!("Esc pressed. Exit game from state {}", current_state.to_string());
+                return self.apply(global_state, PlayResult::Shutdown);
+            },
+            Key::F(2) => {
+                if let Some(console) = &mut self.log_console {
+                    console.toggle();
+
+                    // Not every state redraws the whole screen
+                    if !console.is_visible() {
+                        global_state.render.clear_screen();
+                    }
+                }
+            },
+            Key::F(3) => {
+                self.show_metrics = !self.show_metrics;
+            },
+            Key::F(4) => {
+                if let Some(console) = self.log_console.as_mut().filter(|console| console.is_visible()) {
+                    console.cycle_filter();
+                }
+            },
+            _ => {}
+        }
+
+        match result {
+            Ok(result) => self.apply(global_state, result),
+            Err(err) => self.show_error(global_state, err, false),
+        }
+    }
+
+    /// Hash of everything the input and the random numbers change, same for the same session
+    fn state_hash(&self, game_state: &GlobalState) -> u64 {
+        let mut hasher = replay::StateHasher::new();
+        self.ticks.hash(&mut hasher);
+        game_state.random.states().hash(&mut hasher);
+
+        for state in self.states.iter() {
+            state.to_string().hash(&mut hasher);
+            state.hash_state(&mut hasher);
+        }
+
+        hasher.finish()
+    }
+
+    /// Applies the result of a state to the state stack.
+    /// Returns false if the GAME should be stopped.
+    fn apply(&mut self, game_state: &mut GlobalState, result: PlayResult) -> bool {
+        match result {
+            PlayResult::Shutdown => {
+                info!("Shutdown state");
+                return false;
+            },
+            PlayResult::Pop => {
+                info!("Pop state");
+                let mut state = self.states.pop().expect("Empty state in queue!");
+                state.on_leave(game_state);
+
+                self.enter_top(game_state);
+            },
+            PlayResult::Push(state) => {
+                info!("Push state: {}", state.to_string());
+                if let Some(current_state) = self.states.last_mut() {
+                    current_state.on_leave(game_state);
+                }
+
+                self.states.push(state);
+                self.enter_top(game_state);
+            },
+            PlayResult::Switch(state) => {
+                let mut current_state = self.states.pop().expect("Empty state in queue!");
+                info!("Switch to state: from {} to {}", current_state.to_string(), state.to_string());
+                current_state.on_leave(game_state);
+
+                self.states.push(state);
+                self.enter_top(game_state);
+            },
+            PlayResult::Transition(effect, result) => {
+                // Only one transition at a time, the nested one is applied immediately
+                if self.transition.is_some() {
+                    return self.apply(game_state, *result);
+                }
+
+                self.transition = Some(transition::Transition::new(effect, *result));
+            },
+            PlayResult::Still => {}
         }
-    } else {
-        if let Some(location) = info.location() {
-            error!("Some critical error occurred in {}:{}!", location.file(), location.line());
-        } else {
-            error!("Some critical error occurred!");
+
+        crash::set_states(self.states.iter().map(|state| state.to_string()).collect());
+        !self.states.is_empty()
+    }
+
+    /// Calls `on_enter` of the top state. The state which fails to enter is replaced with the error dialog.
+    fn enter_top(&mut self, game_state: &mut GlobalState) {
+        let result = match self.states.last_mut() {
+            Some(state) => state.on_enter(game_state),
+            None => return,
+        };
+
+        if let Err(err) = result {
+            self.show_error(game_state, err, true);
         }
     }
 
-    error!("Backtrace: {:?}", backtrace);
-}
\ No newline at end of file
+    /// Shows the error dialog instead of crashing. `replace` drops the state which failed,
+    /// otherwise the dialog is shown over it.
+    fn show_error(&mut self, game_state: &mut GlobalState, error: Error, replace: bool) -> bool {
+        error!("{}: {}", error.title(), error);
+
+        let dialog = Box::new(error_dialog::PlayState::new(&error));
+        match replace {
+            true => self.apply(game_state, PlayResult::Switch(dialog)),
+            false => self.apply(game_state, PlayResult::Push(dialog)),
+        }
+    }
+
+    /// Saves the top-most state which can be saved to the recovery slot
+    fn save_recovery(&self, game_state: &GlobalState) -> Option<PathBuf> {
+        let snapshot = panic::catch_unwind(AssertUnwindSafe(|| {
+            self.states.iter().rev().find_map(|state| state.snapshot())
+        }));
+
+        let mut save = snapshot.ok().flatten()?;
+        save.random = Some(game_state.random.clone());
+        let path = save::saves_dir().join(crash::RECOVERY_SAVE);
+
+        match save.store(&path) {
+            Ok(()) => Some(path),
+            Err(err) => {
+                error!("Failed to write the recovery save {}: {}", path.display(), err);
+                None
+            }
+        }
+    }
+
+    fn render_metrics(&self, game_state: &mut GlobalState) {
+        let mut label = Label::new(self.metrics.summary().as_str());
+        label.set_color(game_state.render.theme.warning);
+        label.set_position(render::Position::from(1, 1));
+
+        game_state.render.draw(&label);
+    }
+}
@@ -0,0 +1,378 @@
+//! Pathfinding on tile grids. `find_path` is A* between two tiles, `DijkstraMap`
+//! holds the distance of every tile to the nearest goal, so many movers (e.g.
+//! monsters chasing the player) can share one search. A Dijkstra map can also be
+//! turned into a flee map which leads away from the goals.
+//!
+//! Costs are in tenths of a step: an orthogonal step costs 10 times the cost of
+//! the entered tile, a diagonal one 14 times.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+pub type Point = (u16, u16);
+
+/// Cost of an orthogonal step onto a tile of cost 1
+pub const ORTHOGONAL_STEP: u32 = 10;
+const DIAGONAL_STEP: u32 = 14;
+
+/// How far the flee map pushes away from the goals. Above 1 the movers prefer
+/// running past the goals into open areas over being cornered.
+const FLEE_FACTOR: f32 = -1.2;
+
+const ORTHOGONAL: [(i32, i32); 4] = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+const DIAGONAL: [(i32, i32); 4] = [(1, 1), (-1, 1), (-1, -1), (1, -1)];
+
+/// Tiles which can be searched
+pub trait Grid {
+    fn width(&self) -> u16;
+    fn height(&self) -> u16;
+
+    /// Cost of entering the tile (at least 1), None if it can't be entered
+    fn cost(&self, position: Point) -> Option<u32>;
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Movement {
+    FourWay,
+    /// Diagonal steps can't cut the corners of blocked tiles
+    EightWay,
+}
+
+impl Movement {
+    /// Neighbours of the tile inside the grid with the cost of the step multiplier
+    fn neighbours<G: Grid>(self, grid: &G, (x, y): Point) -> Vec<(Point, u32)> {
+        let offset = |(dx, dy): (i32, i32)| {
+            let (x, y) = (x as i32 + dx, y as i32 + dy);
+
+            match x >= 0 && y >= 0 && x < grid.width() as i32 && y < grid.height() as i32 {
+                true => Some((x as u16, y as u16)),
+                false => None,
+            }
+        };
+
+        let mut neighbours: Vec<(Point, u32)> = ORTHOGONAL.iter()
+            .filter_map(|step| offset(*step))
+            .map(|point| (point, ORTHOGONAL_STEP))
+            .collect();
+
+        if self == Movement::EightWay {
+            for (dx, dy) in DIAGONAL.iter() {
+                let corners_open = offset((*dx, 0)).and_then(|corner| grid.cost(corner)).is_some()
+                    && offset((0, *dy)).and_then(|corner| grid.cost(corner)).is_some();
+
+                if let Some(point) = offset((*dx, *dy)).filter(|_| corners_open) {
+                    neighbours.push((point, DIAGONAL_STEP));
+                }
+            }
+        }
+
+        neighbours
+    }
+
+    /// Lower bound of the cost between the tiles, for A*
+    fn estimate(self, (x1, y1): Point, (x2, y2): Point) -> u32 {
+        let dx = (x1 as i32 - x2 as i32).abs() as u32;
+        let dy = (y1 as i32 - y2 as i32).abs() as u32;
+
+        match self {
+            Movement::FourWay => (dx + dy) * ORTHOGONAL_STEP,
+            Movement::EightWay => dx.max(dy) * ORTHOGONAL_STEP + dx.min(dy) * (DIAGONAL_STEP - ORTHOGONAL_STEP),
+        }
+    }
+}
+
+fn index<G: Grid>(grid: &G, (x, y): Point) -> usize {
+    y as usize * grid.width() as usize + x as usize
+}
+
+fn point<G: Grid>(grid: &G, index: usize) -> Point {
+    ((index % grid.width() as usize) as u16, (index / grid.width() as usize) as u16)
+}
+
+/// Cheapest path from `from` to `to` (A*), without the start. None if `to` can't be reached.
+pub fn find_path<G: Grid>(grid: &G, from: Point, to: Point, movement: Movement) -> Option<Vec<Point>> {
+    if from.0 >= grid.width() || from.1 >= grid.height() || grid.cost(to).is_none() {
+        return None;
+    }
+
+    let size = grid.width() as usize * grid.height() as usize;
+    let mut costs = vec![u32::max_value(); size];
+    let mut came_from = vec![usize::max_value(); size];
+    let mut open = BinaryHeap::new();
+
+    let start = index(grid, from);
+    costs[start] = 0;
+    open.push(Reverse((movement.estimate(from, to), start)));
+
+    while let Some(Reverse((priority, current))) = open.pop() {
+        let position = point(grid, current);
+
+        // The tile was reached cheaper after this entry was pushed
+        if priority > costs[current] + movement.estimate(position, to) {
+            continue;
+        }
+
+        if position == to {
+            let mut path = vec![];
+            let mut step = current;
+
+            while step != start {
+                path.push(point(grid, step));
+                step = came_from[step];
+            }
+
+            path.reverse();
+            return Some(path);
+        }
+
+        for (neighbour, step) in movement.neighbours(grid, position) {
+            let tile_cost = match grid.cost(neighbour) {
+                Some(cost) => cost,
+                None => continue,
+            };
+
+            let next = index(grid, neighbour);
+            let cost = costs[current] + tile_cost * step;
+
+            if cost < costs[next] {
+                costs[next] = cost;
+                came_from[next] = current;
+                open.push(Reverse((cost + movement.estimate(neighbour, to), next)));
+            }
+        }
+    }
+
+    None
+}
+
+/// Distance of every tile to the nearest goal
+pub struct DijkstraMap {
+    width: u16,
+    height: u16,
+    movement: Movement,
+    /// None for the tiles which can't reach a goal
+    values: Vec<Option<i32>>,
+}
+
+impl DijkstraMap {
+    pub fn new<G: Grid>(grid: &G, goals: &[Point], movement: Movement) -> Self {
+        let mut map = DijkstraMap {
+            width: grid.width(),
+            height: grid.height(),
+            movement,
+            values: vec![None; grid.width() as usize * grid.height() as usize],
+        };
+
+        for goal in goals.iter().filter(|(x, y)| *x < grid.width() && *y < grid.height()) {
+            map.values[index(grid, *goal)] = Some(0);
+        }
+
+        map.relax(grid);
+        map
+    }
+
+    /// Map leading away from the goals of this map. Following it downhill a mover
+    /// runs from the goals but still goes around them to escape a dead end.
+    pub fn flee<G: Grid>(&self, grid: &G) -> Self {
+        let mut map = DijkstraMap {
+            width: self.width,
+            height: self.height,
+            movement: self.movement,
+            values: self.values.iter()
+                .map(|value| value.map(|value| (value as f32 * FLEE_FACTOR) as i32))
+                .collect(),
+        };
+
+        map.relax(grid);
+        map
+    }
+
+    /// Lowers the values until no tile can be reached cheaper from a neighbour
+    fn relax<G: Grid>(&mut self, grid: &G) {
+        let mut open: BinaryHeap<Reverse<(i32, usize)>> = self.values.iter().enumerate()
+            .filter_map(|(index, value)| value.map(|value| Reverse((value, index))))
+            .collect();
+
+        while let Some(Reverse((value, current))) = open.pop() {
+            if self.values[current] != Some(value) {
+                continue;
+            }
+
+            // A mover on the neighbour steps onto this tile, so it is this tile's cost which is paid
+            let position = point(grid, current);
+            let tile_cost = grid.cost(position).unwrap_or(1);
+
+            for (neighbour, step) in self.movement.neighbours(grid, position) {
+                if grid.cost(neighbour).is_none() {
+                    continue;
+                }
+
+                let next = index(grid, neighbour);
+                let cost = value + (tile_cost * step) as i32;
+
+                if self.values[next].map_or(true, |old| cost < old) {
+                    self.values[next] = Some(cost);
+                    open.push(Reverse((cost, next)));
+                }
+            }
+        }
+    }
+
+    /// The value of the tile, None if no goal can be reached from it
+    pub fn value(&self, (x, y): Point) -> Option<i32> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+
+        self.values[y as usize * self.width as usize + x as usize]
+    }
+
+    /// The neighbour with the lowest value, None at the bottom. `can_enter` filters
+    /// the tiles taken right now (e.g. by other movers).
+    pub fn downhill<G, F>(&self, grid: &G, from: Point, can_enter: F) -> Option<Point>
+        where G: Grid, F: Fn(Point) -> bool {
+
+        let current = self.value(from)?;
+
+        self.movement.neighbours(grid, from).into_iter()
+            .map(|(neighbour, _)| neighbour)
+            .filter(|neighbour| can_enter(*neighbour))
+            .filter_map(|neighbour| self.value(neighbour).map(|value| (value, neighbour)))
+            .filter(|(value, _)| *value < current)
+            .min()
+            .map(|(_, neighbour)| neighbour)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `#` is a wall, a digit is the cost of the tile, anything else costs 1
+    struct TestGrid {
+        rows: Vec<Vec<char>>,
+    }
+
+    impl TestGrid {
+        fn new(rows: &[&str]) -> Self {
+            TestGrid { rows: rows.iter().map(|row| row.chars().collect()).collect() }
+        }
+    }
+
+    impl Grid for TestGrid {
+        fn width(&self) -> u16 {
+            self.rows[0].len() as u16
+        }
+
+        fn height(&self) -> u16 {
+            self.rows.len() as u16
+        }
+
+        fn cost(&self, (x, y): Point) -> Option<u32> {
+            match self.rows[y as usize][x as usize] {
+                '#' => None,
+                tile => Some(tile.to_digit(10).unwrap_or(1)),
+            }
+        }
+    }
+
+    /// Cost of walking the path from the start
+    fn path_cost(grid: &TestGrid, from: Point, path: &[Point]) -> i32 {
+        let mut previous = from;
+        let mut total = 0;
+
+        for point in path {
+            let step = match previous.0 != point.0 && previous.1 != point.1 {
+                true => DIAGONAL_STEP,
+                false => ORTHOGONAL_STEP,
+            };
+
+            total += (grid.cost(*point).unwrap() * step) as i32;
+            previous = *point;
+        }
+
+        total
+    }
+
+    const GRID: [&str; 6] = [
+        "........",
+        ".####.#.",
+        ".#..3.#.",
+        ".#.##.#.",
+        ".9....#.",
+        "...##...",
+    ];
+
+    #[test]
+    fn a_star_costs_match_dijkstra_map() {
+        let grid = TestGrid::new(&GRID);
+        let goal = (3, 2);
+
+        for movement in [Movement::FourWay, Movement::EightWay].iter() {
+            let map = DijkstraMap::new(&grid, &[goal], *movement);
+
+            for y in 0..grid.height() {
+                for x in 0..grid.width() {
+                    let from = (x, y);
+                    if grid.cost(from).is_none() {
+                        continue;
+                    }
+
+                    let path = find_path(&grid, from, goal, *movement).expect("no path");
+                    assert_eq!(path.last().cloned().unwrap_or(from), goal);
+                    assert_eq!(Some(path_cost(&grid, from, &path)), map.value(from), "{:?} {:?}", movement, from);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn diagonal_steps_do_not_cut_corners() {
+        let grid = TestGrid::new(&[
+            ".#",
+            "..",
+        ]);
+
+        let path = find_path(&grid, (0, 0), (1, 1), Movement::EightWay).unwrap();
+        assert_eq!(path, vec![(0, 1), (1, 1)]);
+
+        let map = DijkstraMap::new(&grid, &[(1, 1)], Movement::EightWay);
+        assert_eq!(map.value((0, 0)), Some(2 * ORTHOGONAL_STEP as i32));
+
+        let open = TestGrid::new(&[
+            "..",
+            "..",
+        ]);
+        assert_eq!(find_path(&open, (0, 0), (1, 1), Movement::EightWay), Some(vec![(1, 1)]));
+    }
+
+    #[test]
+    fn costly_tiles_are_avoided() {
+        let grid = TestGrid::new(&[
+            ".4.",
+            "...",
+        ]);
+
+        // Around the tile of cost 4 are 4 steps of cost 1, through it only 2 steps but 5 in cost
+        let path = find_path(&grid, (0, 0), (2, 0), Movement::FourWay).unwrap();
+        assert_eq!(path, vec![(0, 1), (1, 1), (2, 1), (2, 0)]);
+
+        let ford = TestGrid::new(&[
+            ".2.",
+            "...",
+        ]);
+        let path = find_path(&ford, (0, 0), (2, 0), Movement::FourWay).unwrap();
+        assert_eq!(path, vec![(1, 0), (2, 0)]);
+    }
+
+    #[test]
+    fn walled_off_tiles_have_no_path() {
+        let grid = TestGrid::new(&[
+            ".#.",
+            "##.",
+        ]);
+
+        assert_eq!(find_path(&grid, (0, 0), (2, 1), Movement::EightWay), None);
+        assert_eq!(DijkstraMap::new(&grid, &[(2, 1)], Movement::EightWay).value((0, 0)), None);
+    }
+}
@@ -0,0 +1,601 @@
+//! Recording and replay of a game session. A recording is the seed, the terminal
+//! size, the first state and every input event with the tick it came before.
+//! Ticks are fixed steps, so feeding the events at the same ticks plays the
+//! session again exactly, whatever the speed of the machine.
+//!
+//! ```text
+//! RECORDING 1
+//! SEED 7
+//! SIZE 80 24
+//! LANGUAGE en
+//! THEME light
+//! COLOR auto
+//! START map start
+//! TICKS 412
+//! HASH 9f3c0b4e1d2a7788
+//! 35 key char 10
+//! 120 mouse press left 16 19
+//! ```
+//!
+//! `HASH` is the hash of the state at the end of the session. A headless replay
+//! compares it with the hash of its own end.
+//!
+//! The language, theme and color mode are kept since they change the text on the
+//! screens, and so what the recorded clicks hit. The rest of the settings of the
+//! player is left out, a replay runs with its own empty settings.
+
+use std::fs;
+use std::hash::Hasher;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use termion::event::{Key, MouseButton};
+use log::info;
+
+use crate::bundle;
+use crate::error::{Error, Result};
+use crate::i18n;
+use crate::render::ColorMode;
+use crate::theme;
+use super::{GlobalState, Start};
+use super::input::{Input, MouseEvent};
+
+const MAGIC: &'static str = "RECORDING 1";
+
+const MIN_SPEED: f32 = 0.25;
+const MAX_SPEED: f32 = 16.0;
+
+/// FNV-1a. Unlike the hasher of the std it is the same in every build, so hashes
+/// can be kept in recordings.
+pub struct StateHasher {
+    hash: u64,
+}
+
+impl StateHasher {
+    pub fn new() -> Self {
+        StateHasher { hash: bundle::FNV_OFFSET }
+    }
+}
+
+impl Hasher for StateHasher {
+    fn finish(&self) -> u64 {
+        self.hash
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.hash = bundle::fnv1a(self.hash, bytes);
+    }
+}
+
+pub struct Recording {
+    pub seed: u64,
+    /// Terminal size, the layout of the screens and so the mouse targets depend on it
+    pub size: (u16, u16),
+    pub language: String,
+    pub theme: String,
+    /// The setting, `auto` is resolved by the terminal of the replay
+    pub color_mode: ColorMode,
+    pub start: Start,
+    /// Length of the session
+    pub ticks: u64,
+    /// Hash of the state at the end, None if the game crashed
+    pub hash: Option<u64>,
+    /// Input events with the number of ticks run before them
+    pub events: Vec<(u64, Input)>,
+}
+
+fn button_name(button: MouseButton) -> &'static str {
+    match button {
+        MouseButton::Left => "left",
+        MouseButton::Right => "right",
+        MouseButton::Middle => "middle",
+        MouseButton::WheelUp => "wheelup",
+        MouseButton::WheelDown => "wheeldown",
+    }
+}
+
+fn encode_key(key: Key) -> Option<String> {
+    let text = match key {
+        Key::Char(c) => format!("char {}", c as u32),
+        Key::Alt(c) => format!("alt {}", c as u32),
+        Key::Ctrl(c) => format!("ctrl {}", c as u32),
+        Key::F(n) => format!("f {}", n),
+        Key::Backspace => String::from("backspace"),
+        Key::Left => String::from("left"),
+        Key::Right => String::from("right"),
+        Key::Up => String::from("up"),
+        Key::Down => String::from("down"),
+        Key::Home => String::from("home"),
+        Key::End => String::from("end"),
+        Key::PageUp => String::from("pageup"),
+        Key::PageDown => String::from("pagedown"),
+        Key::BackTab => String::from("backtab"),
+        Key::Delete => String::from("delete"),
+        Key::Insert => String::from("insert"),
+        Key::Null => String::from("null"),
+        Key::Esc => String::from("esc"),
+        _ => return None,
+    };
+
+    Some(text)
+}
+
+fn encode(input: &Input) -> Option<String> {
+    let text = match *input {
+        Input::Key(key) => format!("key {}", encode_key(key)?),
+        Input::Mouse(MouseEvent::Press(button, x, y)) => format!("mouse press {} {} {}", button_name(button), x, y),
+        Input::Mouse(MouseEvent::Release(x, y)) => format!("mouse release {} {}", x, y),
+        Input::Mouse(MouseEvent::Drag(x, y)) => format!("mouse drag {} {}", x, y),
+        Input::Mouse(MouseEvent::Move(x, y)) => format!("mouse move {} {}", x, y),
+    };
+
+    Some(text)
+}
+
+fn decode(words: &[&str]) -> std::result::Result<Input, String> {
+    let number = |index: usize| -> std::result::Result<u32, String> {
+        words.get(index)
+            .and_then(|word| word.parse().ok())
+            .ok_or(format!("bad number in `{}`", words.join(" ")))
+    };
+    let char = |index: usize| number(index)
+        .and_then(|code| std::char::from_u32(code).ok_or(format!("bad character {}", code)));
+    let position = |index: usize| -> std::result::Result<(u16, u16), String> {
+        Ok((number(index)? as u16, number(index + 1)? as u16))
+    };
+
+    let input = match words {
+        ["key", "char", ..] => Input::Key(Key::Char(char(2)?)),
+        ["key", "alt", ..] => Input::Key(Key::Alt(char(2)?)),
+        ["key", "ctrl", ..] => Input::Key(Key::Ctrl(char(2)?)),
+        ["key", "f", ..] => Input::Key(Key::F(number(2)? as u8)),
+        ["key", name] => Input::Key(match *name {
+            "backspace" => Key::Backspace,
+            "left" => Key::Left,
+            "right" => Key::Right,
+            "up" => Key::Up,
+            "down" => Key::Down,
+            "home" => Key::Home,
+            "end" => Key::End,
+            "pageup" => Key::PageUp,
+            "pagedown" => Key::PageDown,
+            "backtab" => Key::BackTab,
+            "delete" => Key::Delete,
+            "insert" => Key::Insert,
+            "null" => Key::Null,
+            "esc" => Key::Esc,
+            name => return Err(format!("unknown key {}", name)),
+        }),
+        ["mouse", "press", button, ..] => {
+            let button = match *button {
+                "left" => MouseButton::Left,
+                "right" => MouseButton::Right,
+                "middle" => MouseButton::Middle,
+                "wheelup" => MouseButton::WheelUp,
+                "wheeldown" => MouseButton::WheelDown,
+                button => return Err(format!("unknown mouse button {}", button)),
+            };
+            let (x, y) = position(3)?;
+
+            Input::Mouse(MouseEvent::Press(button, x, y))
+        },
+        ["mouse", "release", ..] => position(2).map(|(x, y)| Input::Mouse(MouseEvent::Release(x, y)))?,
+        ["mouse", "drag", ..] => position(2).map(|(x, y)| Input::Mouse(MouseEvent::Drag(x, y)))?,
+        ["mouse", "move", ..] => position(2).map(|(x, y)| Input::Mouse(MouseEvent::Move(x, y)))?,
+        _ => return Err(format!("unknown event `{}`", words.join(" "))),
+    };
+
+    Ok(input)
+}
+
+fn encode_start(start: &Start) -> Option<String> {
+    match start {
+        Start::Splash => Some(String::from("splash")),
+        Start::Menu => Some(String::from("menu")),
+        Start::Map(id) => Some(format!("map {}", id)),
+        // The save file could change, so a session from it can't be played again
+        Start::Save(_) => None,
+    }
+}
+
+fn decode_start(words: &[&str]) -> std::result::Result<Start, String> {
+    match words {
+        ["splash"] => Ok(Start::Splash),
+        ["menu"] => Ok(Start::Menu),
+        ["map", id] => Ok(Start::Map(String::from(*id))),
+        _ => Err(format!("bad start `{}`", words.join(" "))),
+    }
+}
+
+impl Recording {
+    pub fn load(path: &Path) -> Result<Recording> {
+        let error = |message: String| Error::Replay { path: path.to_path_buf(), message };
+
+        let text = fs::read_to_string(path).map_err(|err| error(err.to_string()))?;
+        let mut lines = text.lines().enumerate();
+
+        if lines.next().map(|(_, line)| line) != Some(MAGIC) {
+            return Err(error(String::from("not a recording")));
+        }
+
+        // Settings left out are the defaults
+        let mut recording = Recording {
+            seed: 0,
+            size: (0, 0),
+            language: String::from(i18n::DEFAULT_LANGUAGE),
+            theme: String::from(theme::DEFAULT_THEME),
+            color_mode: ColorMode::Auto,
+            start: Start::Splash,
+            ticks: 0,
+            hash: None,
+            events: vec![],
+        };
+        let mut seed = None;
+        let mut ticks = None;
+
+        for (index, line) in lines {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let number = |index: usize, radix: u32| -> std::result::Result<u64, String> {
+                words.get(index)
+                    .and_then(|word| u64::from_str_radix(word, radix).ok())
+                    .ok_or(format!("bad number in `{}`", line))
+            };
+
+            let parsed: std::result::Result<(), String> = match words.as_slice() {
+                [] => Ok(()),
+                ["SEED", ..] => number(1, 10).map(|value| seed = Some(value)),
+                ["SIZE", ..] => number(1, 10)
+                    .and_then(|width| Ok((width as u16, number(2, 10)? as u16)))
+                    .map(|size| recording.size = size),
+                ["LANGUAGE", language] => {
+                    recording.language = String::from(*language);
+                    Ok(())
+                },
+                ["THEME", theme] => {
+                    recording.theme = String::from(*theme);
+                    Ok(())
+                },
+                ["COLOR", mode] => mode.parse().map(|mode| recording.color_mode = mode),
+                ["START", start @ ..] => decode_start(start).map(|start| recording.start = start),
+                ["TICKS", ..] => number(1, 10).map(|value| ticks = Some(value)),
+                ["HASH", ..] => number(1, 16).map(|hash| recording.hash = Some(hash)),
+                [tick, event @ ..] => tick.parse()
+                    .map_err(|_| format!("bad tick in `{}`", line))
+                    .and_then(|tick| Ok(recording.events.push((tick, decode(event)?)))),
+            };
+
+            parsed.map_err(|message| error(format!("line {}: {}", index + 1, message)))?;
+        }
+
+        recording.seed = seed.ok_or(error(String::from("no SEED")))?;
+        // The game was killed before the recording was finished, it is played until the last event
+        recording.ticks = ticks.unwrap_or(recording.events.last().map_or(0, |(tick, _)| *tick));
+        if recording.size.0 == 0 || recording.size.1 == 0 {
+            return Err(error(String::from("no SIZE")));
+        }
+
+        Ok(recording)
+    }
+
+    pub fn store(&self, path: &Path) -> Result<()> {
+        let mut lines = vec![
+            String::from(MAGIC),
+            format!("SEED {}", self.seed),
+            format!("SIZE {} {}", self.size.0, self.size.1),
+            format!("LANGUAGE {}", self.language),
+            format!("THEME {}", self.theme),
+            format!("COLOR {}", self.color_mode),
+        ];
+
+        lines.extend(encode_start(&self.start).map(|start| format!("START {}", start)));
+        lines.push(format!("TICKS {}", self.ticks));
+        lines.extend(self.hash.map(|hash| format!("HASH {:016x}", hash)));
+        lines.extend(self.events.iter()
+            .filter_map(|(tick, input)| encode(input).map(|event| format!("{} {}", tick, event))));
+
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).map_err(|err| Error::Replay { path: path.to_path_buf(), message: err.to_string() })?;
+        }
+
+        fs::write(path, lines.join("\n") + "\n")
+            .map_err(|err| Error::Replay { path: path.to_path_buf(), message: err.to_string() })
+    }
+}
+
+/// Records the session being played
+pub struct Recorder {
+    path: PathBuf,
+    recording: Recording,
+}
+
+impl Recorder {
+    /// None if the session can't be recorded because of its start
+    pub fn new(path: &Path, game_state: &GlobalState, start: &Start) -> Option<Self> {
+        let start = match start {
+            Start::Splash => Start::Splash,
+            Start::Menu => Start::Menu,
+            Start::Map(id) => Start::Map(id.clone()),
+            Start::Save(_) => return None,
+        };
+
+        Some(Recorder {
+            path: path.to_path_buf(),
+            recording: Recording {
+                seed: game_state.random.seed(),
+                size: (game_state.render.term_size.width, game_state.render.term_size.height),
+                language: String::from(game_state.strings.language()),
+                theme: game_state.render.theme.name.clone(),
+                color_mode: game_state.color_mode,
+                start,
+                ticks: 0,
+                hash: None,
+                events: vec![],
+            },
+        })
+    }
+
+    pub fn record(&mut self, tick: u64, input: Input) {
+        self.recording.events.push((tick, input));
+    }
+
+    /// Writes the recording. `hash` is None after a crash, the replay then just runs into it again.
+    pub fn finish(mut self, ticks: u64, hash: Option<u64>) -> Result<()> {
+        self.recording.ticks = ticks;
+        self.recording.hash = hash;
+        self.recording.store(&self.path)?;
+
+        info!("Session recorded to {} ({} events, {} ticks)", self.path.display(), self.recording.events.len(), ticks);
+        Ok(())
+    }
+}
+
+/// Feeds the events of a recording to the game
+pub struct Player {
+    path: PathBuf,
+    recording: Recording,
+    /// Index of the next event
+    next: usize,
+    speed: f32,
+    paused: bool,
+    /// Run one tick while paused
+    step: bool,
+    /// Stopped by the viewer before the end
+    stopped: bool,
+    headless: bool,
+}
+
+impl Player {
+    pub fn new(path: &Path, recording: Recording, speed: f32, headless: bool) -> Self {
+        Player {
+            path: path.to_path_buf(),
+            recording,
+            next: 0,
+            speed: speed.max(MIN_SPEED).min(MAX_SPEED),
+            paused: false,
+            step: false,
+            stopped: false,
+            headless,
+        }
+    }
+
+    pub fn recording(&self) -> &Recording {
+        &self.recording
+    }
+
+    pub fn is_headless(&self) -> bool {
+        self.headless
+    }
+
+    /// The events recorded before the tick
+    pub fn due(&mut self, tick: u64) -> Vec<Input> {
+        let count = self.recording.events[self.next..].iter()
+            .take_while(|(event_tick, _)| *event_tick <= tick)
+            .count();
+
+        let events = self.recording.events[self.next..self.next + count].iter()
+            .map(|(_, input)| *input)
+            .collect();
+        self.next += count;
+
+        events
+    }
+
+    /// Playback keys: Space pauses, `.` steps one tick while paused, `+` and `-` change the speed.
+    /// Returns false to stop the replay.
+    pub fn control(&mut self, input: Input) -> bool {
+        match input {
+            Input::Key(Key::Esc) | Input::Key(Key::Char('q')) => {
+                info!("Replay stopped after {} of {} events", self.next, self.recording.events.len());
+                self.stopped = true;
+                return false;
+            },
+            Input::Key(Key::Char(' ')) => self.paused = !self.paused,
+            Input::Key(Key::Char('.')) => self.step = true,
+            Input::Key(Key::Char('+')) => self.speed = (self.speed * 2.0).min(MAX_SPEED),
+            Input::Key(Key::Char('-')) => self.speed = (self.speed / 2.0).max(MIN_SPEED),
+            _ => {}
+        }
+
+        true
+    }
+
+    /// Game time to run for the real time passed: scaled by the speed, nothing while paused
+    /// but one tick for a step. A headless replay runs one tick per frame, as fast as it can.
+    pub fn game_time(&mut self, elapsed: Duration, tick: Duration) -> Duration {
+        let step = std::mem::replace(&mut self.step, false);
+
+        match (self.headless, self.paused, step) {
+            (true, _, _) | (false, true, true) => tick,
+            (false, true, false) => Duration::default(),
+            (false, false, _) => elapsed.mul_f32(self.speed),
+        }
+    }
+
+    /// Ticks one frame can run to catch up, more for a faster playback
+    pub fn catch_up_ticks(&self, ticks: u32) -> u32 {
+        ticks * self.speed.ceil().max(1.0) as u32
+    }
+
+    /// The recorded session is over
+    pub fn is_finished(&self, tick: u64) -> bool {
+        tick >= self.recording.ticks
+    }
+
+    /// Playback line shown over the game, e.g. `replay 2x 120/400`
+    pub fn status(&self, tick: u64) -> String {
+        let speed = match self.paused {
+            true => String::from("paused"),
+            false => format!("{}x", self.speed),
+        };
+
+        format!("replay {} {}/{}", speed, tick, self.recording.ticks)
+    }
+
+    /// Compares the hash of the end with the recorded one
+    pub fn check(&self, hash: u64) -> Result<()> {
+        if self.stopped {
+            return Ok(());
+        }
+
+        match self.recording.hash {
+            Some(expected) if expected != hash => Err(Error::Replay {
+                path: self.path.clone(),
+                message: format!("final state hash {:016x} differs from the recorded {:016x}", hash, expected),
+            }),
+            Some(_) => {
+                info!("Replay of {} ended with the recorded state {:016x}", self.path.display(), hash);
+                Ok(())
+            },
+            None => {
+                info!("Replay of {} ended with the state {:016x}, nothing to compare with", self.path.display(), hash);
+                Ok(())
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_file(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("rust-rpg-test-{}-{}", std::process::id(), name))
+    }
+
+    fn every_input() -> Vec<Input> {
+        let keys = vec![
+            Key::Char('a'), Key::Char(' '), Key::Char('\n'), Key::Char('ж'), Key::Char('日'),
+            Key::Alt('x'), Key::Ctrl('c'), Key::F(1), Key::F(12),
+            Key::Backspace, Key::Left, Key::Right, Key::Up, Key::Down, Key::Home, Key::End,
+            Key::PageUp, Key::PageDown, Key::BackTab, Key::Delete, Key::Insert, Key::Null, Key::Esc,
+        ];
+        let buttons = vec![
+            MouseButton::Left, MouseButton::Right, MouseButton::Middle, MouseButton::WheelUp, MouseButton::WheelDown,
+        ];
+
+        keys.into_iter().map(Input::Key)
+            .chain(buttons.into_iter().map(|button| Input::Mouse(MouseEvent::Press(button, 3, 4))))
+            .chain(vec![
+                Input::Mouse(MouseEvent::Release(1, 1)),
+                Input::Mouse(MouseEvent::Drag(80, 24)),
+                Input::Mouse(MouseEvent::Move(300, 200)),
+            ])
+            .collect()
+    }
+
+    #[test]
+    fn store_and_load_give_the_same_recording() {
+        let recording = Recording {
+            seed: u64::MAX,
+            size: (120, 40),
+            language: String::from("ja"),
+            theme: String::from("dark"),
+            color_mode: ColorMode::Ansi16,
+            start: Start::Map(String::from("gen:caves:12:2")),
+            ticks: 5000,
+            hash: Some(0x0123_4567_89AB_CDEF),
+            events: every_input().into_iter().enumerate().map(|(index, input)| (index as u64 * 7, input)).collect(),
+        };
+
+        let path = temp_file("round-trip.rec");
+        recording.store(&path).unwrap();
+        let loaded = Recording::load(&path).unwrap();
+        let _ = fs::remove_file(&path);
+
+        assert_eq!(loaded.seed, recording.seed);
+        assert_eq!(loaded.size, recording.size);
+        assert_eq!(loaded.language, recording.language);
+        assert_eq!(loaded.theme, recording.theme);
+        assert_eq!(loaded.color_mode, recording.color_mode);
+        assert_eq!(encode_start(&loaded.start), encode_start(&recording.start));
+        assert_eq!(loaded.ticks, recording.ticks);
+        assert_eq!(loaded.hash, recording.hash);
+        assert_eq!(loaded.events, recording.events);
+    }
+
+    #[test]
+    fn every_input_is_encoded() {
+        for input in every_input() {
+            let text = encode(&input).expect("not encoded");
+            let words: Vec<&str> = text.split_whitespace().collect();
+
+            assert_eq!(decode(&words), Ok(input), "{}", text);
+        }
+    }
+
+    #[test]
+    fn missing_settings_are_the_defaults() {
+        let path = temp_file("defaults.rec");
+        fs::write(&path, "RECORDING 1\nSEED 3\nSIZE 80 24\nSTART menu\n10 key esc\n").unwrap();
+        let loaded = Recording::load(&path).unwrap();
+        let _ = fs::remove_file(&path);
+
+        assert_eq!(loaded.language, i18n::DEFAULT_LANGUAGE);
+        assert_eq!(loaded.theme, theme::DEFAULT_THEME);
+        assert_eq!(loaded.color_mode, ColorMode::Auto);
+        // Not finished, it is played until the last event
+        assert_eq!(loaded.ticks, 10);
+        assert_eq!(loaded.hash, None);
+    }
+
+    #[test]
+    fn broken_recordings_are_errors() {
+        let broken = [
+            "not a recording\n",
+            "RECORDING 1\nSIZE 80 24\n",
+            "RECORDING 1\nSEED 3\n",
+            "RECORDING 1\nSEED 3\nSIZE 80 24\n5 key nothing\n",
+            "RECORDING 1\nSEED 3\nSIZE 80 24\nfive key esc\n",
+            "RECORDING 1\nSEED 3\nSIZE 80 24\nCOLOR rainbow\n",
+        ];
+
+        let path = temp_file("broken.rec");
+        for text in broken.iter() {
+            fs::write(&path, text).unwrap();
+            assert!(Recording::load(&path).is_err(), "{:?}", text);
+        }
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn events_are_due_at_their_ticks() {
+        let recording = Recording {
+            seed: 1,
+            size: (80, 24),
+            language: String::from("en"),
+            theme: String::from("light"),
+            color_mode: ColorMode::Auto,
+            start: Start::Menu,
+            ticks: 20,
+            hash: None,
+            events: vec![(0, Input::Key(Key::Up)), (5, Input::Key(Key::Down)), (5, Input::Key(Key::Esc))],
+        };
+        let mut player = Player::new(Path::new("test.rec"), recording, 1.0, true);
+
+        assert_eq!(player.due(0), vec![Input::Key(Key::Up)]);
+        assert_eq!(player.due(4), vec![]);
+        assert_eq!(player.due(5), vec![Input::Key(Key::Down), Input::Key(Key::Esc)]);
+        assert!(!player.is_finished(19));
+        assert!(player.is_finished(20));
+    }
+}
@@ -0,0 +1,270 @@
+//! Saved games. A save is a `key = value` file like the settings.
+//!
+//! Besides the player, a save keeps the stairs links made while playing and what
+//! changed on the levels the player has been to (loot taken, tiles explored):
+//!
+//! ```text
+//! link.0 = start 70 30 gen:caves:1234:1
+//! level.0.map = gen:caves:1234:1
+//! level.0.loot = gold 12 5 7, potion 1 20 3
+//! level.0.explored = 0000ff80…,00ff…
+//! ```
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use log::warn;
+
+use crate::config::{self, Config};
+use crate::error::{Error, Result};
+use crate::rng::Random;
+
+/// Name of the save written by the quick save key
+pub const QUICK_SAVE: &'static str = "quick.sav";
+
+const STAT_PREFIX: &'static str = "stat.";
+const ITEM_PREFIX: &'static str = "item.";
+const RNG_PREFIX: &'static str = "rng.";
+const LINK_PREFIX: &'static str = "link.";
+const LEVEL_PREFIX: &'static str = "level.";
+
+pub fn saves_dir() -> PathBuf {
+    config::config_dir().join("saves")
+}
+
+/// Stairs which lead somewhere else than the map file or the generator says
+pub struct SavedLink {
+    pub map: String,
+    pub position: (u16, u16),
+    pub to: String,
+}
+
+/// Kind, count and position of an item lying on a level
+pub type SavedLoot = (String, u32, (u16, u16));
+
+/// What changed on a level the player has been to
+pub struct SavedLevel {
+    pub map: String,
+    /// The loot left
+    pub loot: Vec<SavedLoot>,
+    /// Explored tiles by rows, None for the maps which are seen whole
+    pub explored: Option<Vec<Vec<bool>>>,
+}
+
+pub struct Save {
+    /// Map id
+    pub map: String,
+    pub player: (u16, u16),
+    pub stats: BTreeMap<String, i32>,
+    /// Item names with their count
+    pub inventory: BTreeMap<String, u32>,
+    /// The seed and the random streams, None in saves made before they were stored
+    pub random: Option<Random>,
+    pub links: Vec<SavedLink>,
+    /// The level left the longest ago first, the current one last
+    pub levels: Vec<SavedLevel>,
+}
+
+/// Values of the keys with the prefix, keyed without the prefix
+fn with_prefix<T: std::str::FromStr>(config: &Config, prefix: &str) -> BTreeMap<String, T> {
+    config.entries()
+        .filter(|(key, _)| key.starts_with(prefix))
+        .filter_map(|(key, _)| config.get_parsed(key).map(|value| (String::from(&key[prefix.len()..]), value)))
+        .collect()
+}
+
+/// `start 70 30 gen:caves:1234:1`
+fn parse_link(value: &str) -> Option<SavedLink> {
+    match value.split_whitespace().collect::<Vec<_>>().as_slice() {
+        [map, x, y, to] => Some(SavedLink {
+            map: String::from(*map),
+            position: (x.parse().ok()?, y.parse().ok()?),
+            to: String::from(*to),
+        }),
+        _ => None,
+    }
+}
+
+/// `gold 12 5 7, potion 1 20 3`
+fn parse_loot(value: &str) -> Option<Vec<SavedLoot>> {
+    value.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(|item| match item.split_whitespace().collect::<Vec<_>>().as_slice() {
+            [kind, count, x, y] => Some((String::from(*kind), count.parse().ok()?, (x.parse().ok()?, y.parse().ok()?))),
+            _ => None,
+        })
+        .collect()
+}
+
+fn format_loot(loot: &[SavedLoot]) -> String {
+    loot.iter()
+        .map(|(kind, count, (x, y))| format!("{} {} {} {}", kind, count, x, y))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Rows separated by commas, every hex digit is 4 tiles of the row
+fn parse_explored(value: &str) -> Option<Vec<Vec<bool>>> {
+    value.split(',')
+        .map(|row| row.chars()
+            .map(|digit| digit.to_digit(16).map(|bits| (0..4).rev().map(move |bit| bits & (1 << bit) != 0)))
+            .collect::<Option<Vec<_>>>()
+            .map(|digits| digits.into_iter().flatten().collect()))
+        .collect()
+}
+
+fn format_explored(explored: &[Vec<bool>]) -> String {
+    explored.iter()
+        .map(|row| row.chunks(4)
+            .map(|tiles| tiles.iter().enumerate()
+                .filter(|(_, explored)| **explored)
+                .fold(0, |bits, (index, _)| bits | (8 >> index)))
+            .map(|bits| std::char::from_digit(bits, 16).unwrap_or('0'))
+            .collect::<String>())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// The levels of `level.<index>.<field>` keys in the order of the index
+fn parse_levels(config: &Config) -> Vec<SavedLevel> {
+    let mut levels: BTreeMap<usize, SavedLevel> = BTreeMap::new();
+
+    for (key, value) in config.entries().filter(|(key, _)| key.starts_with(LEVEL_PREFIX)) {
+        let mut parts = key[LEVEL_PREFIX.len()..].splitn(2, '.');
+        let (index, field) = match (parts.next().and_then(|index| index.parse().ok()), parts.next()) {
+            (Some(index), Some(field)) => (index, field),
+            _ => continue,
+        };
+
+        let level = levels.entry(index).or_insert(SavedLevel { map: String::new(), loot: vec![], explored: None });
+        match field {
+            "map" => level.map = String::from(value),
+            "loot" => match parse_loot(value) {
+                Some(loot) => level.loot = loot,
+                None => warn!("Bad loot in the save: {}", value),
+            },
+            "explored" => match parse_explored(value) {
+                Some(explored) => level.explored = Some(explored),
+                None => warn!("Bad explored tiles in the save: {}", value),
+            },
+            _ => {}
+        }
+    }
+
+    levels.into_values().filter(|level| !level.map.is_empty()).collect()
+}
+
+impl Save {
+    pub fn load(path: &Path) -> Result<Save> {
+        if !path.is_file() {
+            return Err(Error::Save { path: path.to_path_buf(), message: String::from("file does not exist") });
+        }
+
+        let config = Config::load(path);
+        let field = |key: &str| Error::Save { path: path.to_path_buf(), message: format!("no valid {}", key) };
+
+        Ok(Save {
+            map: String::from(config.get("map").ok_or_else(|| field("map"))?),
+            player: (config.get_parsed("player.x").ok_or_else(|| field("player.x"))?,
+                     config.get_parsed("player.y").ok_or_else(|| field("player.y"))?),
+            stats: with_prefix(&config, STAT_PREFIX),
+            inventory: with_prefix(&config, ITEM_PREFIX),
+            random: config.get_parsed("seed").map(|seed| Random::restore(seed, &with_prefix(&config, RNG_PREFIX))),
+            links: with_prefix::<String>(&config, LINK_PREFIX).values()
+                .filter_map(|value| {
+                    let link = parse_link(value);
+                    if link.is_none() {
+                        warn!("Bad stairs link in the save: {}", value);
+                    }
+
+                    link
+                })
+                .collect(),
+            levels: parse_levels(&config),
+        })
+    }
+
+    pub fn store(&self, path: &Path) -> Result<()> {
+        let mut config = Config::new(path);
+        config.set("map", &self.map);
+        config.set("player.x", self.player.0);
+        config.set("player.y", self.player.1);
+
+        for (stat, value) in self.stats.iter() {
+            config.set(&format!("{}{}", STAT_PREFIX, stat), value);
+        }
+
+        for (item, count) in self.inventory.iter() {
+            config.set(&format!("{}{}", ITEM_PREFIX, item), count);
+        }
+
+        if let Some(random) = &self.random {
+            config.set("seed", random.seed());
+
+            for (stream, state) in random.states() {
+                config.set(&format!("{}{}", RNG_PREFIX, stream), state);
+            }
+        }
+
+        for (index, link) in self.links.iter().enumerate() {
+            let (x, y) = link.position;
+            config.set(&format!("{}{}", LINK_PREFIX, index), format!("{} {} {} {}", link.map, x, y, link.to));
+        }
+
+        for (index, level) in self.levels.iter().enumerate() {
+            config.set(&format!("{}{}.map", LEVEL_PREFIX, index), &level.map);
+            config.set(&format!("{}{}.loot", LEVEL_PREFIX, index), format_loot(&level.loot));
+
+            if let Some(explored) = &level.explored {
+                config.set(&format!("{}{}.explored", LEVEL_PREFIX, index), format_explored(explored));
+            }
+        }
+
+        config.save().map_err(|err| Error::Save { path: path.to_path_buf(), message: err.to_string() })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn links_and_levels_are_stored() {
+        let explored: Vec<Vec<bool>> = (0..3).map(|y| (0..8).map(|x| (x + y) % 3 == 0).collect()).collect();
+        let save = Save {
+            map: String::from("gen:caves:7:2"),
+            player: (4, 5),
+            stats: BTreeMap::new(),
+            inventory: BTreeMap::new(),
+            random: None,
+            links: vec![
+                SavedLink { map: String::from("start"), position: (70, 30), to: String::from("gen:caves:7:1") },
+                SavedLink { map: String::from("gen:caves:7:1"), position: (3, 9), to: String::from("start") },
+            ],
+            levels: vec![
+                SavedLevel { map: String::from("start"), loot: vec![], explored: None },
+                SavedLevel {
+                    map: String::from("gen:caves:7:2"),
+                    loot: vec![(String::from("gold"), 12, (5, 7)), (String::from("potion"), 1, (20, 3))],
+                    explored: Some(explored.clone()),
+                },
+            ],
+        };
+
+        let path = std::env::temp_dir().join(format!("rust-rpg-test-{}.sav", std::process::id()));
+        save.store(&path).unwrap();
+        let loaded = Save::load(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        let links: Vec<_> = loaded.links.iter().map(|link| (link.map.as_str(), link.position, link.to.as_str())).collect();
+        assert_eq!(links, vec![("start", (70, 30), "gen:caves:7:1"), ("gen:caves:7:1", (3, 9), "start")]);
+
+        assert_eq!(loaded.levels.len(), 2);
+        assert_eq!(loaded.levels[0].map, "start");
+        assert!(loaded.levels[0].loot.is_empty());
+        assert_eq!(loaded.levels[0].explored, None);
+        assert_eq!(loaded.levels[1].map, "gen:caves:7:2");
+        assert_eq!(loaded.levels[1].loot, save.levels[1].loot);
+        assert_eq!(loaded.levels[1].explored, Some(explored));
+    }
+}
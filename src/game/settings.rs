@@ -0,0 +1,252 @@
+//! Settings screen. Changes are applied immediately and saved when leaving the screen.
+
+use crate::game::{GlobalState, PlayResult};
+use crate::error::Result as GameResult;
+use crate::game::sound::Channel;
+use crate::widgets::label::Label;
+use crate::render::{Drawable, Position, Coordinate, ColorMode};
+use crate::theme::Theme;
+use crate::assets::AssetKind;
+use crate::i18n::Strings;
+use crate::unicode;
+
+use termion::event::{Key, MouseButton};
+use std::hash::Hasher;
+
+use super::input::MouseEvent;
+
+/// One line of the settings screen
+enum Item {
+    Volume(Channel),
+    Colors,
+    Theme,
+    Language,
+}
+
+const VOLUME_STEP: f32 = 0.1;
+const VOLUME_BAR_WIDTH: usize = 10;
+/// Cells for the item titles, so the values are aligned in every language
+const TITLE_WIDTH: usize = 18;
+
+const ADJUST_SOUND: &'static str = "ui.button";
+
+pub struct PlayState {
+    items: Vec<Item>,
+    selected_index: usize,
+}
+
+fn item_title(item: &Item, game_state: &GlobalState) -> String {
+    let key = match item {
+        Item::Volume(channel) => format!("settings.channel.{}", channel.name()),
+        Item::Colors => String::from("settings.colors"),
+        Item::Theme => String::from("settings.theme"),
+        Item::Language => String::from("settings.language"),
+    };
+
+    unicode::fit(&game_state.strings.get(&key), TITLE_WIDTH)
+}
+
+impl PlayState {
+    pub fn new(_game_state: &GlobalState) -> Self {
+        let mut items: Vec<Item> = Channel::ALL.iter().map(|channel| Item::Volume(*channel)).collect();
+        items.push(Item::Colors);
+        items.push(Item::Theme);
+        items.push(Item::Language);
+
+        PlayState {
+            items,
+            selected_index: 0,
+        }
+    }
+
+    fn item_text(&self, item: &Item, game_state: &GlobalState) -> String {
+        let title = item_title(item, game_state);
+
+        match item {
+            Item::Volume(channel) => {
+                let settings = game_state.sound.channel(*channel);
+                let filled = (settings.volume * VOLUME_BAR_WIDTH as f32).round() as usize;
+                let muted = match settings.muted {
+                    true => game_state.strings.get("settings.muted"),
+                    false => String::new(),
+                };
+
+                format!("{} [{}{}] {:>3}% {}",
+                        title,
+                        "#".repeat(filled),
+                        "-".repeat(VOLUME_BAR_WIDTH - filled),
+                        (settings.volume * 100.0).round(),
+                        unicode::fit(&muted, 12))
+            },
+            Item::Colors => format!("{} < {:<9} > {:>7}", title, game_state.color_mode.name(),
+                                    game_state.render.color_mode().name()),
+            Item::Theme => format!("{} < {:<19} >", title, game_state.render.theme.name),
+            Item::Language => format!("{} < {} >", title,
+                                      unicode::fit(&game_state.strings.get("language.name"), 19)),
+        }
+    }
+
+    /// Changes the selected item. `direction` is -1 or 1.
+    fn adjust(&mut self, game_state: &mut GlobalState, direction: f32) {
+        match self.items[self.selected_index] {
+            Item::Volume(channel) => {
+                let volume = game_state.sound.channel(channel).volume + VOLUME_STEP * direction;
+                game_state.sound.set_channel_volume(channel, volume);
+
+                // Let the player hear the new volume
+                if let Err(err) = game_state.sound.play(channel, ADJUST_SOUND) {
+                    log::warn!("Failed to play {}: {}", ADJUST_SOUND, err);
+                }
+            },
+            Item::Colors => {
+                let count = ColorMode::ALL.len() as i32;
+                let index = ColorMode::ALL.iter().position(|mode| *mode == game_state.color_mode).unwrap_or(0);
+                let mode = ColorMode::ALL[(index as i32 + direction as i32 + count) as usize % count as usize];
+
+                game_state.color_mode = mode;
+                game_state.render.set_color_mode(mode);
+            },
+            Item::Theme => {
+                let themes = Theme::available();
+                let count = themes.len() as i32;
+                let index = themes.iter().position(|name| *name == game_state.render.theme.name).unwrap_or(0);
+                let name = &themes[(index as i32 + direction as i32 + count) as usize % count as usize];
+
+                match Theme::load(name) {
+                    Ok(theme) => game_state.render.theme = theme,
+                    Err(err) => log::warn!("Failed to load theme {}: {}", name, err),
+                }
+            },
+            Item::Language => {
+                let languages = game_state.assets.list(AssetKind::Lang);
+                if languages.is_empty() {
+                    return;
+                }
+
+                let count = languages.len() as i32;
+                let index = languages.iter().position(|language| language == game_state.strings.language())
+                    .unwrap_or(0);
+                let language = &languages[(index as i32 + direction as i32 + count) as usize % count as usize];
+
+                match Strings::load(&game_state.assets, language) {
+                    Ok(strings) => game_state.strings = strings,
+                    Err(err) => log::warn!("Failed to load language {}: {}", language, err),
+                }
+            }
+        }
+    }
+
+    /// Row of the first item
+    fn top(game_state: &GlobalState) -> u16 {
+        (game_state.render.term_size.height as f32 * 0.2) as u16
+    }
+
+    fn item_label(&self, index: usize, game_state: &GlobalState) -> Label {
+        let mut label = Label::new(self.item_text(&self.items[index], game_state).as_str());
+        label.set_selected(index == self.selected_index);
+        label.set_position(Position {
+            x: Coordinate::Centered,
+            y: Coordinate::Absolute(PlayState::top(game_state) + 3 + (index * 2) as u16),
+        });
+
+        label
+    }
+
+    /// Index of the item under the mouse
+    fn item_at(&self, game_state: &GlobalState, position: (u16, u16)) -> Option<usize> {
+        (0..self.items.len()).find(|index| game_state.render.contains(&self.item_label(*index, game_state), position))
+    }
+
+    fn toggle(&mut self, game_state: &mut GlobalState) {
+        match self.items[self.selected_index] {
+            Item::Volume(channel) => {
+                let muted = game_state.sound.channel(channel).muted;
+                game_state.sound.set_channel_muted(channel, !muted);
+            },
+            Item::Colors | Item::Theme | Item::Language => {}
+        }
+    }
+}
+
+impl super::PlayState for PlayState {
+    fn render(&mut self, game_state: &mut GlobalState) {
+        game_state.render.clear_color = game_state.render.theme.background;
+        game_state.render.clear_screen();
+
+        let top = PlayState::top(game_state);
+
+        let mut title = Label::new(game_state.strings.get("settings.title").as_str());
+        title.set_color(game_state.render.theme.title);
+        title.set_position(Position {
+            x: Coordinate::Centered,
+            y: Coordinate::Absolute(top),
+        });
+        game_state.render.draw(&title);
+
+        for i in 0..self.items.len() {
+            let label = self.item_label(i, game_state);
+            game_state.render.draw(&label);
+        }
+
+        let mut hint = Label::new(game_state.strings.get("settings.hint").as_str());
+        hint.set_color(game_state.render.theme.hint);
+        hint.set_position(Position {
+            x: Coordinate::Centered,
+            y: Coordinate::FromBorder(2),
+        });
+        game_state.render.draw(&hint);
+    }
+
+    fn hash_state(&self, hasher: &mut dyn Hasher) {
+        hasher.write_usize(self.selected_index);
+    }
+
+    fn to_string(&self) -> String {
+        String::from("SettingsState")
+    }
+
+    fn on_key_pressed(&mut self, game_state: &mut GlobalState, key: Key) -> GameResult<PlayResult> {
+        match key {
+            Key::Up => {
+                self.selected_index = (self.selected_index + self.items.len() - 1) % self.items.len();
+            },
+            Key::Down => {
+                self.selected_index = (self.selected_index + 1) % self.items.len();
+            },
+            Key::Left => self.adjust(game_state, -1.0),
+            Key::Right => self.adjust(game_state, 1.0),
+            Key::Char('m') | Key::Char('\n') => self.toggle(game_state),
+            Key::Backspace => {
+                game_state.save_settings();
+                return Ok(PlayResult::Pop);
+            },
+            _ => {}
+        }
+
+        Ok(PlayResult::Still)
+    }
+
+    /// Hovering selects an item, the left and right buttons change it like Right and Left
+    fn on_mouse(&mut self, game_state: &mut GlobalState, event: MouseEvent) -> GameResult<PlayResult> {
+        match event {
+            MouseEvent::Press(MouseButton::WheelUp, _, _) => return self.on_key_pressed(game_state, Key::Up),
+            MouseEvent::Press(MouseButton::WheelDown, _, _) => return self.on_key_pressed(game_state, Key::Down),
+            event => {
+                let index = match self.item_at(game_state, event.position()) {
+                    Some(index) => index,
+                    None => return Ok(PlayResult::Still),
+                };
+
+                self.selected_index = index;
+
+                match event {
+                    MouseEvent::Press(MouseButton::Left, _, _) => self.adjust(game_state, 1.0),
+                    MouseEvent::Press(MouseButton::Right, _, _) => self.adjust(game_state, -1.0),
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(PlayResult::Still)
+    }
+}
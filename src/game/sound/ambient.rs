@@ -0,0 +1,37 @@
+//! Ambient sounds of a map: the map-wide ambience and positional sound sources
+
+use super::SoundHandle;
+
+/// Distance (in tiles) at which a source is panned fully to one side
+const PAN_DISTANCE: f32 = 8.0;
+
+/// Looped sound placed on a map tile
+#[derive(Clone)]
+pub struct AmbientSource {
+    pub id: String,
+    pub x: u16,
+    pub y: u16,
+    /// The source can't be heard farther than this (in tiles)
+    pub radius: f32,
+}
+
+impl AmbientSource {
+    /// Volume and stereo panning of the source heard at the listener position
+    pub fn spatialize(&self, listener: (u16, u16)) -> (f32, f32) {
+        let dx = self.x as f32 - listener.0 as f32;
+        let dy = self.y as f32 - listener.1 as f32;
+        let distance = (dx * dx + dy * dy).sqrt();
+
+        let volume = (1.0 - distance / self.radius.max(1.0)).max(0.0);
+        let pan = (dx / PAN_DISTANCE).max(-1.0).min(1.0);
+
+        // Squared falloff sounds more natural than the linear one
+        (volume * volume, pan)
+    }
+}
+
+/// Sounds started for the current map
+pub struct Scene {
+    pub ambience: Option<SoundHandle>,
+    pub sources: Vec<(AmbientSource, SoundHandle)>,
+}
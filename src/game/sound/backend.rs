@@ -0,0 +1,293 @@
+//! Audio output backends. The manager keeps channels, fades and limits,
+//! a backend only decodes and plays the sounds.
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs::File;
+use std::io::{Cursor, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use rodio::{Decoder, Sample, Sink, Source};
+use rodio::source::{Buffered, ChannelVolume};
+use log::{info, warn};
+
+use super::SoundHandle;
+
+pub trait Backend {
+    /// Decodes the sound file contents and keeps the sound ready for playing
+    fn load(&mut self, id: &str, data: &[u8]) -> Result<(), Box<dyn Error>>;
+    fn is_loaded(&self, id: &str) -> bool;
+    /// Starts playing a loaded sound with zero volume
+    fn start(&mut self, handle: SoundHandle, id: &str, looped: bool) -> Result<(), Box<dyn Error>>;
+    fn stop(&mut self, handle: SoundHandle);
+    fn set_volume(&mut self, handle: SoundHandle, volume: f32);
+    fn set_paused(&mut self, handle: SoundHandle, paused: bool);
+    /// Stereo panning from -1 (left) to 1 (right)
+    fn set_pan(&mut self, handle: SoundHandle, pan: f32);
+    fn is_finished(&self, handle: SoundHandle) -> bool;
+    fn name(&self) -> &'static str;
+}
+
+/// Left and right channel volumes. The center keeps the full volume on both channels.
+fn pan_volumes(pan: f32) -> (f32, f32) {
+    let angle = (pan.max(-1.0).min(1.0) + 1.0) * std::f32::consts::FRAC_PI_4;
+
+    ((angle.cos() * std::f32::consts::SQRT_2).min(1.0),
+     (angle.sin() * std::f32::consts::SQRT_2).min(1.0))
+}
+
+/// Mixes the source down to mono and plays it on two channels with the shared panning
+fn panned<S>(source: S, pan: Arc<Mutex<f32>>) -> impl Source<Item = S::Item>
+    where S: Source, S::Item: Sample {
+
+    ChannelVolume::new(source, vec![1.0, 1.0])
+        .periodic_access(Duration::from_millis(10), move |source| {
+            let (left, right) = pan_volumes(*pan.lock().unwrap());
+            source.set_volume(0, left);
+            source.set_volume(1, right);
+        })
+}
+
+struct Playing {
+    sink: Sink,
+    pan: Arc<Mutex<f32>>,
+}
+
+/// Plays sounds on the default output device
+pub struct RodioBackend {
+    device: rodio::Device,
+    sound_cache: HashMap<String, Buffered<Decoder<Cursor<Vec<u8>>>>>,
+    playing: HashMap<SoundHandle, Playing>,
+}
+
+impl RodioBackend {
+    /// None if there is no output device
+    pub fn new() -> Option<Self> {
+        let device = rodio::default_output_device()?;
+
+        Some(RodioBackend {
+            device,
+            sound_cache: HashMap::new(),
+            playing: HashMap::new(),
+        })
+    }
+}
+
+impl Backend for RodioBackend {
+    fn load(&mut self, id: &str, data: &[u8]) -> Result<(), Box<dyn Error>> {
+        let source = rodio::Decoder::new(Cursor::new(data.to_vec()))?.buffered();
+
+        self.sound_cache.insert(String::from(id), source);
+
+        Ok(())
+    }
+
+    fn is_loaded(&self, id: &str) -> bool {
+        self.sound_cache.contains_key(id)
+    }
+
+    fn start(&mut self, handle: SoundHandle, id: &str, looped: bool) -> Result<(), Box<dyn Error>> {
+        let sound = match self.sound_cache.get(id) {
+            Some(sound) => sound.clone(),
+            None => return Err(format!("sound {} is not loaded", id).into()),
+        };
+
+        let sink = Sink::new(&self.device);
+        let pan = Arc::new(Mutex::new(0.0));
+
+        sink.set_volume(0.0);
+        if looped {
+            sink.append(panned(sound.repeat_infinite(), pan.clone()));
+        } else {
+            sink.append(panned(sound, pan.clone()));
+        }
+
+        self.playing.insert(handle, Playing { sink, pan });
+
+        Ok(())
+    }
+
+    fn stop(&mut self, handle: SoundHandle) {
+        // Dropping the sink stops the sound
+        self.playing.remove(&handle);
+    }
+
+    fn set_volume(&mut self, handle: SoundHandle, volume: f32) {
+        if let Some(playing) = self.playing.get(&handle) {
+            playing.sink.set_volume(volume);
+        }
+    }
+
+    fn set_paused(&mut self, handle: SoundHandle, paused: bool) {
+        if let Some(playing) = self.playing.get(&handle) {
+            if playing.sink.is_paused() != paused {
+                match paused {
+                    true => playing.sink.pause(),
+                    false => playing.sink.play(),
+                }
+            }
+        }
+    }
+
+    fn set_pan(&mut self, handle: SoundHandle, pan: f32) {
+        if let Some(playing) = self.playing.get(&handle) {
+            *playing.pan.lock().unwrap() = pan;
+        }
+    }
+
+    fn is_finished(&self, handle: SoundHandle) -> bool {
+        match self.playing.get(&handle) {
+            Some(playing) => playing.sink.empty(),
+            None => true,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "rodio"
+    }
+}
+
+/// Plays nothing. One-shot sounds finish immediately, looped ones play until stopped.
+pub struct NullBackend {
+    loaded: HashSet<String>,
+    looped: HashSet<SoundHandle>,
+}
+
+impl NullBackend {
+    pub fn new() -> Self {
+        NullBackend {
+            loaded: HashSet::new(),
+            looped: HashSet::new(),
+        }
+    }
+}
+
+impl Backend for NullBackend {
+    fn load(&mut self, id: &str, _data: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.loaded.insert(String::from(id));
+        Ok(())
+    }
+
+    fn is_loaded(&self, id: &str) -> bool {
+        self.loaded.contains(id)
+    }
+
+    fn start(&mut self, handle: SoundHandle, _id: &str, looped: bool) -> Result<(), Box<dyn Error>> {
+        if looped {
+            self.looped.insert(handle);
+        }
+
+        Ok(())
+    }
+
+    fn stop(&mut self, handle: SoundHandle) {
+        self.looped.remove(&handle);
+    }
+
+    fn set_volume(&mut self, _handle: SoundHandle, _volume: f32) {}
+
+    fn set_paused(&mut self, _handle: SoundHandle, _paused: bool) {}
+
+    fn set_pan(&mut self, _handle: SoundHandle, _pan: f32) {}
+
+    fn is_finished(&self, handle: SoundHandle) -> bool {
+        !self.looped.contains(&handle)
+    }
+
+    fn name(&self) -> &'static str {
+        "null"
+    }
+}
+
+/// Sound triggered through the recording backend
+#[derive(Clone, Debug)]
+pub struct Played {
+    pub id: String,
+    pub looped: bool,
+}
+
+/// Plays nothing like the null backend, but remembers which sounds were triggered.
+/// The list is written to `output` (one id per line) when the backend is dropped.
+pub struct RecordingBackend {
+    null: NullBackend,
+    played: Arc<Mutex<Vec<Played>>>,
+    output: Option<PathBuf>,
+}
+
+impl RecordingBackend {
+    pub fn new(output: Option<PathBuf>) -> Self {
+        RecordingBackend {
+            null: NullBackend::new(),
+            played: Arc::new(Mutex::new(vec![])),
+            output,
+        }
+    }
+
+    /// Shared list of the triggered sounds
+    #[cfg(test)]
+    pub fn played(&self) -> Arc<Mutex<Vec<Played>>> {
+        self.played.clone()
+    }
+}
+
+impl Backend for RecordingBackend {
+    fn load(&mut self, id: &str, data: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.null.load(id, data)
+    }
+
+    fn is_loaded(&self, id: &str) -> bool {
+        self.null.is_loaded(id)
+    }
+
+    fn start(&mut self, handle: SoundHandle, id: &str, looped: bool) -> Result<(), Box<dyn Error>> {
+        info!("Sound triggered: {}", id);
+        self.played.lock().unwrap().push(Played { id: String::from(id), looped });
+        self.null.start(handle, id, looped)
+    }
+
+    fn stop(&mut self, handle: SoundHandle) {
+        self.null.stop(handle)
+    }
+
+    fn set_volume(&mut self, handle: SoundHandle, volume: f32) {
+        self.null.set_volume(handle, volume)
+    }
+
+    fn set_paused(&mut self, handle: SoundHandle, paused: bool) {
+        self.null.set_paused(handle, paused)
+    }
+
+    fn set_pan(&mut self, handle: SoundHandle, pan: f32) {
+        self.null.set_pan(handle, pan)
+    }
+
+    fn is_finished(&self, handle: SoundHandle) -> bool {
+        self.null.is_finished(handle)
+    }
+
+    fn name(&self) -> &'static str {
+        "recording"
+    }
+}
+
+impl Drop for RecordingBackend {
+    fn drop(&mut self) {
+        let path = match &self.output {
+            Some(path) => path,
+            None => return,
+        };
+
+        let result = File::create(path).and_then(|mut file| {
+            for played in self.played.lock().unwrap().iter() {
+                writeln!(file, "{}{}", played.id, if played.looped { " (looped)" } else { "" })?;
+            }
+
+            Ok(())
+        });
+
+        if let Err(err) = result {
+            warn!("Failed to write recorded sounds to {}: {}", path.display(), err);
+        }
+    }
+}
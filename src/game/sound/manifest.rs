@@ -0,0 +1,84 @@
+//! Audio manifest. Maps logical sound ids (e.g. `ui.button`) to sound files.
+//!
+//! Each line of the manifest is `id = file [max instances]`, file paths are relative
+//! to the sound asset directory.
+
+use std::collections::BTreeMap;
+
+use crate::assets::{AssetKind, AssetManager};
+
+pub struct Entry {
+    pub file: String,
+    /// How many instances of the sound can play at the same time
+    pub max_instances: Option<usize>,
+}
+
+pub struct Manifest {
+    entries: BTreeMap<String, Entry>,
+}
+
+impl Manifest {
+    pub fn new() -> Self {
+        Manifest {
+            entries: BTreeMap::new(),
+        }
+    }
+
+    pub fn load(assets: &AssetManager, id: &str) -> Result<Manifest, String> {
+        let text = assets.read_text(AssetKind::Sound, id)
+            .map_err(|err| err.to_string())?;
+
+        Manifest::parse(&text)
+            .map_err(|err| format!("{}: {}", assets.source(AssetKind::Sound, id), err))
+    }
+
+    pub fn parse(text: &str) -> Result<Manifest, String> {
+        let mut manifest = Manifest::new();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let index = line.find('=')
+                .ok_or(format!("line {}: expected `id = file`", number + 1))?;
+
+            let id = line[..index].trim();
+            let mut parts = line[index + 1..].split_whitespace();
+
+            let file = parts.next()
+                .ok_or(format!("line {}: no file for sound {}", number + 1, id))?;
+
+            let max_instances = match parts.next() {
+                Some(max) => Some(max.parse()
+                    .map_err(|_| format!("line {}: bad instance limit {}", number + 1, max))?),
+                None => None,
+            };
+
+            manifest.entries.insert(String::from(id), Entry {
+                file: String::from(file),
+                max_instances,
+            });
+        }
+
+        Ok(manifest)
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Entry> {
+        self.entries.get(id)
+    }
+
+    pub fn ids(&self) -> impl Iterator<Item = &String> {
+        self.entries.keys()
+    }
+
+    /// Returns the problems found (e.g. missing files)
+    pub fn validate(&self, assets: &AssetManager) -> Vec<String> {
+        self.entries.iter()
+            .filter(|(_, entry)| !assets.exists(AssetKind::Sound, &entry.file))
+            .map(|(id, entry)| format!("sound {}: file {} not found", id,
+                                       assets.path(AssetKind::Sound, &entry.file).display()))
+            .collect()
+    }
+}
@@ -0,0 +1,573 @@
+//! Sound subsystem code
+
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::Duration;
+use log::{info, warn};
+
+use crate::assets::{AssetKind, AssetManager};
+use crate::config::Config;
+use crate::error::{Error as GameError, Result as GameResult};
+use crate::game::animation::{Animation, Tween};
+use crate::game::console::{self, Registry};
+
+mod backend;
+mod manifest;
+mod ambient;
+
+pub use ambient::AmbientSource;
+
+use backend::Backend;
+use manifest::Manifest;
+
+pub const MANIFEST: &'static str = "manifest.cfg";
+
+const CROSSFADE_DURATION: Duration = Duration::from_millis(1500);
+const AMBIENT_FADE_DURATION: Duration = Duration::from_millis(1000);
+
+/// How many instances of the same sound can play at the same time by default
+const DEFAULT_MAX_INSTANCES: usize = 4;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Channel {
+    Ui,
+    Sfx,
+    Music,
+    Ambient,
+}
+
+impl Channel {
+    pub const ALL: [Channel; 4] = [Channel::Ui, Channel::Sfx, Channel::Music, Channel::Ambient];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Channel::Ui => "ui",
+            Channel::Sfx => "sfx",
+            Channel::Music => "music",
+            Channel::Ambient => "ambient",
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct ChannelSettings {
+    pub volume: f32,
+    pub muted: bool,
+    pub paused: bool,
+}
+
+impl Default for ChannelSettings {
+    fn default() -> Self {
+        ChannelSettings {
+            volume: 1.0,
+            muted: false,
+            paused: false,
+        }
+    }
+}
+
+/// Background music declared by a play state
+pub enum Music {
+    /// Keep playing whatever is playing now
+    Keep,
+    /// Fade the music out
+    Silence,
+    /// Loop the track, crossfading from the current one
+    Track(&'static str),
+}
+
+/// Identifies one playing instance of a sound
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct SoundHandle(u64);
+
+struct Voice {
+    handle: SoundHandle,
+    id: String,
+    channel: Channel,
+    volume: f32,
+    pan: f32,
+    fade: Tween<f32>,
+    /// Remove the voice once it is faded out
+    stopping: bool,
+}
+
+/// Backend chosen by the `RUST_RPG_AUDIO` environment variable:
+/// `null`, `record` or `record=<file>`. The default is the output device, if there is one.
+fn backend_from_env() -> Box<dyn Backend> {
+    let setting = std::env::var("RUST_RPG_AUDIO").unwrap_or_default();
+
+    if setting == "null" {
+        return Box::new(backend::NullBackend::new());
+    }
+
+    if setting == "record" {
+        return Box::new(backend::RecordingBackend::new(None));
+    }
+
+    if setting.starts_with("record=") {
+        let output = PathBuf::from(&setting["record=".len()..]);
+        return Box::new(backend::RecordingBackend::new(Some(output)));
+    }
+
+    match backend::RodioBackend::new() {
+        Some(backend) => Box::new(backend),
+        None => {
+            warn!("No audio output device, sound is disabled");
+            Box::new(backend::NullBackend::new())
+        }
+    }
+}
+
+fn audio_error<E: ToString>(id: &str, err: E) -> GameError {
+    GameError::Audio {
+        sound: String::from(id),
+        message: err.to_string(),
+    }
+}
+
+pub struct Manager {
+    assets: Rc<AssetManager>,
+    backend: Box<dyn Backend>,
+    manifest: Manifest,
+    /// Sounds of the manifest which are not loaded yet
+    preload_queue: VecDeque<String>,
+    channels: HashMap<Channel, ChannelSettings>,
+    voices: Vec<Voice>,
+    next_handle: u64,
+    /// Id and handle of the current music track
+    music: Option<(String, SoundHandle)>,
+    /// Ambient sounds of the current map
+    scene: Option<ambient::Scene>,
+}
+
+impl Manager {
+    pub fn new(assets: Rc<AssetManager>) -> Self {
+        Manager::with_backend(assets, backend_from_env())
+    }
+
+    /// Manager which plays nothing
+    pub fn silent(assets: Rc<AssetManager>) -> Self {
+        Manager::with_backend(assets, Box::new(backend::NullBackend::new()))
+    }
+
+    pub fn with_backend(assets: Rc<AssetManager>, backend: Box<dyn Backend>) -> Self {
+        info!("Audio backend: {}", backend.name());
+
+        let mut this = Manager {
+            assets,
+            backend,
+            manifest: Manifest::new(),
+            preload_queue: VecDeque::new(),
+            channels: Channel::ALL.iter().map(|c| (*c, ChannelSettings::default())).collect(),
+            voices: vec![],
+            next_handle: 0,
+            music: None,
+            scene: None,
+        };
+
+        this.load_manifest(MANIFEST);
+        this
+    }
+
+    /// Loads the manifest and reports the problems found in it
+    pub fn load_manifest(&mut self, id: &str) {
+        self.manifest = match Manifest::load(&self.assets, id) {
+            Ok(manifest) => manifest,
+            Err(err) => {
+                warn!("Failed to load audio manifest: {}", err);
+                Manifest::new()
+            }
+        };
+
+        for problem in self.manifest.validate(&self.assets) {
+            warn!("Audio manifest: {}", problem);
+        }
+
+        self.preload_queue = self.manifest.ids().cloned().collect();
+    }
+
+    /// Loads up to `count` sounds of the manifest, so they don't have to be
+    /// decoded on the first play. Returns true when everything is loaded.
+    pub fn preload(&mut self, count: usize) -> bool {
+        for _ in 0..count {
+            let id = match self.preload_queue.pop_front() {
+                Some(id) => id,
+                None => break,
+            };
+
+            if let Err(err) = self.load(&id) {
+                warn!("Failed to preload sound {}: {}", id, err);
+            }
+        }
+
+        self.preload_queue.is_empty()
+    }
+
+    fn load(&mut self, id: &str) -> GameResult<()> {
+        if self.backend.is_loaded(id) {
+            return Ok(());
+        }
+
+        let entry = self.manifest.get(id)
+            .ok_or_else(|| audio_error(id, "unknown sound id"))?;
+
+        let data = self.assets.read(AssetKind::Sound, &entry.file)?;
+        self.backend.load(id, &data).map_err(|err| audio_error(id, err))
+    }
+
+    /// Plays the sound once on the channel
+    pub fn play(&mut self, channel: Channel, id: &str) -> GameResult<SoundHandle> {
+        self.start(channel, id, false, Tween::new(1.0, 1.0, Duration::default()))
+    }
+
+    fn start(&mut self, channel: Channel, id: &str, looped: bool, fade: Tween<f32>)
+        -> GameResult<SoundHandle> {
+
+        self.load(id)?;
+        self.limit_instances(id);
+
+        let handle = SoundHandle(self.next_handle);
+        self.next_handle += 1;
+
+        self.backend.start(handle, id, looped).map_err(|err| audio_error(id, err))?;
+
+        self.voices.push(Voice {
+            handle,
+            id: String::from(id),
+            channel,
+            volume: 1.0,
+            pan: 0.0,
+            fade,
+            stopping: false,
+        });
+
+        self.update_voices();
+
+        Ok(handle)
+    }
+
+    /// Stops the oldest instances of the sound so that a new one fits into the limit
+    fn limit_instances(&mut self, id: &str) {
+        let max = self.manifest.get(id)
+            .and_then(|entry| entry.max_instances)
+            .unwrap_or(DEFAULT_MAX_INSTANCES)
+            .max(1);
+
+        let playing: Vec<SoundHandle> = self.voices.iter()
+            .filter(|voice| voice.id == id && !voice.stopping)
+            .map(|voice| voice.handle)
+            .collect();
+
+        if playing.len() < max {
+            return;
+        }
+
+        for handle in playing.iter().take(playing.len() + 1 - max) {
+            self.stop(*handle);
+        }
+    }
+
+    fn voice_mut(&mut self, handle: SoundHandle) -> Option<&mut Voice> {
+        self.voices.iter_mut().find(|voice| voice.handle == handle)
+    }
+
+    pub fn stop(&mut self, handle: SoundHandle) {
+        self.voices.retain(|voice| voice.handle != handle);
+        self.backend.stop(handle);
+    }
+
+    /// Fades the sound out and stops it
+    pub fn fade_out(&mut self, handle: SoundHandle, duration: Duration) {
+        if let Some(voice) = self.voice_mut(handle) {
+            voice.fade = Tween::new(voice.fade.value(), 0.0, duration);
+            voice.stopping = true;
+        }
+    }
+
+    pub fn channel(&self, channel: Channel) -> ChannelSettings {
+        self.channels[&channel]
+    }
+
+    pub fn set_channel_volume(&mut self, channel: Channel, volume: f32) {
+        self.channels.get_mut(&channel).unwrap().volume = volume.max(0.0).min(1.0);
+        self.update_voices();
+    }
+
+    pub fn set_channel_muted(&mut self, channel: Channel, muted: bool) {
+        self.channels.get_mut(&channel).unwrap().muted = muted;
+        self.update_voices();
+    }
+
+    pub fn set_channel_paused(&mut self, channel: Channel, paused: bool) {
+        self.channels.get_mut(&channel).unwrap().paused = paused;
+        self.update_voices();
+    }
+
+    /// Applies the music declared by the current play state
+    pub fn set_music(&mut self, music: Music) {
+        match music {
+            Music::Keep => {},
+            Music::Silence => self.stop_music(),
+            Music::Track(id) => self.play_music(id),
+        }
+    }
+
+    /// Starts looping the track. The current track is crossfaded into the new one.
+    pub fn play_music(&mut self, id: &str) {
+        if let Some((current, _)) = &self.music {
+            if current == id {
+                return;
+            }
+        }
+
+        self.stop_music();
+
+        info!("Playing music {}", id);
+
+        let fade = Tween::new(0.0, 1.0, CROSSFADE_DURATION);
+        match self.start(Channel::Music, id, true, fade) {
+            Ok(handle) => self.music = Some((String::from(id), handle)),
+            Err(err) => {
+                warn!("Failed to play music {}: {}", id, err);
+
+                // Remember the track anyway, so we don't try to load it every frame
+                self.music = Some((String::from(id), SoundHandle(self.next_handle)));
+                self.next_handle += 1;
+            }
+        }
+    }
+
+    /// Fades the current track out
+    pub fn stop_music(&mut self) {
+        if let Some((name, handle)) = self.music.take() {
+            info!("Stopping music {}", name);
+            self.fade_out(handle, CROSSFADE_DURATION);
+        }
+    }
+
+    /// Starts the ambient sounds of a map. The sounds of the previous map are faded out.
+    pub fn enter_ambient_scene(&mut self, ambience: Option<&str>, sources: &[AmbientSource],
+                               listener: (u16, u16)) {
+        self.leave_ambient_scene();
+
+        let fade_in = || Tween::new(0.0, 1.0, AMBIENT_FADE_DURATION);
+
+        let ambience = ambience.and_then(|id| {
+            match self.start(Channel::Ambient, id, true, fade_in()) {
+                Ok(handle) => Some(handle),
+                Err(err) => {
+                    warn!("Failed to play ambience {}: {}", id, err);
+                    None
+                }
+            }
+        });
+
+        let mut started = vec![];
+        for source in sources {
+            match self.start(Channel::Ambient, &source.id, true, fade_in()) {
+                Ok(handle) => started.push((source.clone(), handle)),
+                Err(err) => warn!("Failed to play ambient sound {}: {}", source.id, err),
+            }
+        }
+
+        self.scene = Some(ambient::Scene {
+            ambience,
+            sources: started,
+        });
+
+        self.set_listener(listener);
+    }
+
+    /// Fades out the ambient sounds of the current map
+    pub fn leave_ambient_scene(&mut self) {
+        if let Some(scene) = self.scene.take() {
+            let handles = scene.ambience.iter()
+                .chain(scene.sources.iter().map(|(_, handle)| handle));
+
+            for handle in handles.cloned().collect::<Vec<SoundHandle>>() {
+                self.fade_out(handle, AMBIENT_FADE_DURATION);
+            }
+        }
+    }
+
+    /// Updates volume and panning of the positional sounds for the new listener position
+    pub fn set_listener(&mut self, listener: (u16, u16)) {
+        let sources = match &self.scene {
+            Some(scene) => scene.sources.clone(),
+            None => return,
+        };
+
+        for (source, handle) in sources {
+            let (volume, pan) = source.spatialize(listener);
+
+            if let Some(voice) = self.voice_mut(handle) {
+                voice.volume = volume;
+                voice.pan = pan;
+            }
+        }
+
+        self.update_voices();
+    }
+
+    fn update_voices(&mut self) {
+        for voice in &self.voices {
+            let channel = &self.channels[&voice.channel];
+            let volume = match channel.muted {
+                true => 0.0,
+                false => channel.volume * voice.volume * voice.fade.value(),
+            };
+
+            self.backend.set_volume(voice.handle, volume);
+            self.backend.set_pan(voice.handle, voice.pan);
+            self.backend.set_paused(voice.handle, channel.paused);
+        }
+    }
+
+    /// Advances fades and drops finished sounds. Should be called every game loop tick.
+    pub fn update(&mut self, dt: Duration) {
+        for voice in &mut self.voices {
+            if !self.channels[&voice.channel].paused {
+                voice.fade.advance(dt);
+            }
+        }
+
+        let finished: Vec<SoundHandle> = self.voices.iter()
+            .filter(|voice| {
+                self.backend.is_finished(voice.handle)
+                    || (voice.stopping && voice.fade.is_finished())
+            })
+            .map(|voice| voice.handle)
+            .collect();
+
+        for handle in finished {
+            self.stop(handle);
+        }
+
+        self.update_voices();
+    }
+
+    /// Reads channel volumes saved by `store_settings`
+    pub fn load_settings(&mut self, config: &Config) {
+        for channel in Channel::ALL.iter() {
+            let settings = self.channels.get_mut(channel).unwrap();
+
+            if let Some(volume) = config.get_parsed::<f32>(format!("audio.{}.volume", channel.name()).as_str()) {
+                settings.volume = volume.max(0.0).min(1.0);
+            }
+
+            if let Some(muted) = config.get_parsed(format!("audio.{}.muted", channel.name()).as_str()) {
+                settings.muted = muted;
+            }
+        }
+
+        self.update_voices();
+    }
+
+    pub fn store_settings(&self, config: &mut Config) {
+        for channel in Channel::ALL.iter() {
+            let settings = &self.channels[channel];
+
+            config.set(format!("audio.{}.volume", channel.name()).as_str(), settings.volume);
+            config.set(format!("audio.{}.muted", channel.name()).as_str(), settings.muted);
+        }
+    }
+}
+
+fn channel_by_name(name: &str) -> Result<Channel, String> {
+    Channel::ALL.iter()
+        .find(|channel| channel.name() == name)
+        .cloned()
+        .ok_or(format!("unknown channel {}, expected ui, sfx, music or ambient", name))
+}
+
+pub fn register_commands(registry: &mut Registry) {
+    registry.register("play <channel> <sound>", "play the sound from the manifest", |context, args| {
+        let channel = channel_by_name(&console::arg::<String>(args, 0, "channel")?)?;
+        let id: String = console::arg(args, 1, "sound")?;
+
+        context.game_state.sound.play(channel, &id).map_err(|err| err.to_string())?;
+        Ok(format!("playing {}", id))
+    });
+
+    registry.register("volume <channel> <percent>", "set the channel volume", |context, args| {
+        let channel = channel_by_name(&console::arg::<String>(args, 0, "channel")?)?;
+        let percent: f32 = console::arg(args, 1, "percent")?;
+
+        context.game_state.sound.set_channel_volume(channel, percent / 100.0);
+        Ok(format!("{} volume is {}%", channel.name(), percent))
+    });
+
+    registry.register("pause <channel>", "pause or resume the channel", |context, args| {
+        let channel = channel_by_name(&console::arg::<String>(args, 0, "channel")?)?;
+        let paused = !context.game_state.sound.channel(channel).paused;
+
+        context.game_state.sound.set_channel_paused(channel, paused);
+        Ok(format!("{} is {}", channel.name(), if paused { "paused" } else { "playing" }))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use std::sync::{Arc, Mutex};
+
+    fn manager() -> (Manager, Arc<Mutex<Vec<backend::Played>>>) {
+        let assets = Rc::new(AssetManager::new(&Path::new(env!("CARGO_MANIFEST_DIR")).join("assets")));
+        let backend = backend::RecordingBackend::new(None);
+        let played = backend.played();
+
+        (Manager::with_backend(assets, Box::new(backend)), played)
+    }
+
+    fn channels_of(manager: &Manager, id: &str) -> Vec<Channel> {
+        manager.voices.iter().filter(|voice| voice.id == id).map(|voice| voice.channel).collect()
+    }
+
+    #[test]
+    fn sounds_are_played_on_their_channels() {
+        let (mut manager, played) = manager();
+
+        manager.play(Channel::Ui, "ui.button").unwrap();
+        manager.play_music("music.menu");
+        manager.enter_ambient_scene(Some("ambient.forest"), &[], (0, 0));
+
+        let played: Vec<(String, bool)> = played.lock().unwrap().iter()
+            .map(|played| (played.id.clone(), played.looped))
+            .collect();
+
+        assert_eq!(played, vec![
+            (String::from("ui.button"), false),
+            (String::from("music.menu"), true),
+            (String::from("ambient.forest"), true),
+        ]);
+
+        assert_eq!(channels_of(&manager, "ui.button"), vec![Channel::Ui]);
+        assert_eq!(channels_of(&manager, "music.menu"), vec![Channel::Music]);
+        assert_eq!(channels_of(&manager, "ambient.forest"), vec![Channel::Ambient]);
+    }
+
+    #[test]
+    fn instances_over_the_limit_are_stopped() {
+        let (mut manager, played) = manager();
+
+        let first = manager.play(Channel::Ui, "ui.button").unwrap();
+        manager.play(Channel::Ui, "ui.button").unwrap();
+        manager.play(Channel::Ui, "ui.button").unwrap();
+
+        assert_eq!(played.lock().unwrap().len(), 3);
+        assert_eq!(channels_of(&manager, "ui.button").len(), 2);
+        assert!(manager.voices.iter().all(|voice| voice.handle != first));
+    }
+
+    #[test]
+    fn the_same_track_is_not_restarted() {
+        let (mut manager, played) = manager();
+
+        manager.play_music("music.menu");
+        manager.play_music("music.menu");
+
+        assert_eq!(played.lock().unwrap().len(), 1);
+    }
+}
@@ -1,46 +1,49 @@
 //! Starting splash screen
 
 use crate::game::{GlobalState, PlayResult};
-use termion::color;
+use crate::error::Result as GameResult;
+use crate::game::sound::Music;
+use crate::game::animation::{Animation, Blink, Timer, Typewriter, lerp_color};
+use crate::game::transition::Effect;
+use termion::event::Key;
 use std::time::Duration;
 use crate::widgets::label::Label;
+use crate::unicode;
 use crate::render::{Drawable, Position};
 use crate::render::Coordinate::{Centered, Absolute};
 
 pub struct PlayState {
-
-}
-
-fn lerp_color(start: color::Rgb, end: color::Rgb, k: f32) -> color::Rgb {
-
-    let r = start.0 as f32 + ((end.0 as f32 - start.0 as f32) * k);
-    let g = start.1 as f32 + ((end.1 as f32 - start.1 as f32) * k);
-    let b = start.2 as f32 + ((end.2 as f32 - start.2 as f32) * k);
-
-    color::Rgb(r as u8, g as u8, b as u8)
+    timer: Timer,
+    org_text: Typewriter,
+    skip_hint: Blink,
 }
 
 impl PlayState {
-    pub fn new() -> Self {
-        PlayState{}
+    pub fn new(game_state: &GlobalState) -> Self {
+        PlayState{
+            timer: Timer::new(SPLASH_DURATION),
+            org_text: Typewriter::new(game_state.strings.get("splash.presents").as_str(), 30.0),
+            skip_hint: Blink::new(Duration::from_millis(1000)),
+        }
     }
-}
-
-const SPLASH_DURATION: Duration = Duration::from_secs(1);
 
-impl super::PlayState for PlayState {
-
-    fn play(&mut self, game_state: &mut GlobalState) -> PlayResult {
+    fn render_splash(&self, game_state: &mut GlobalState) {
         game_state.render.set_cursor_position((1, 1));
 
-        let finish_color = color::Rgb(10, 10, 10);
-        let start_color = color::Rgb(50, 50, 50);
+        let theme = &game_state.render.theme;
+        let finish_color = theme.background;
+        let start_color = lerp_color(theme.background, theme.text, 0.15);
 
-        let mut org_label = Label::new("Breeze software presents");
-        let mut game_label = Label::new("T H E | G A M E");
+        // Keep the label centered while the text is being typed
+        let org_text = unicode::fit(self.org_text.visible_text(), unicode::width(self.org_text.text()));
 
-        game_label.set_color(Box::new(color::Rgb(255, 255, 255)));
-        org_label.set_color(Box::new(color::Rgb(255, 255, 255)));
+        let mut org_label = Label::new(org_text.as_str());
+        let mut game_label = Label::new(game_state.strings.get("splash.title").as_str());
+        let mut hint_label = Label::new(game_state.strings.get("splash.skip").as_str());
+
+        game_label.set_color(theme.title);
+        org_label.set_color(theme.title);
+        hint_label.set_color(theme.hint);
         game_label.set_position(Position {
             x: Centered,
             y: Absolute(game_state.render.term_size.height / 2),
@@ -49,38 +52,72 @@ impl super::PlayState for PlayState {
             x: Centered,
             y: Absolute(game_state.render.term_size.height / 2 - 2),
         });
+        hint_label.set_position(Position {
+            x: Centered,
+            y: Absolute(game_state.render.term_size.height - 2),
+        });
 
         for x in 1..game_state.render.term_size.width {
             for y in 1..game_state.render.term_size.height {
                 let k = y as f32 / game_state.render.term_size.width as f32;
                 let color = lerp_color(start_color, finish_color, k);
 
-                game_state.render.set_pixel_color((x, y), Box::new(color));
+                game_state.render.set_pixel_color((x, y), color);
             }
         }
 
         game_state.render.draw(&game_label);
         game_state.render.draw(&org_label);
 
-        game_state.render.flash();
-        std::thread::sleep(SPLASH_DURATION);
+        if self.skip_hint.is_visible() {
+            game_state.render.draw(&hint_label);
+        }
+    }
 
-        let to_white_diff = 255 - finish_color.0;
-        let mut current_color = finish_color.0;
-        for x in 1..to_white_diff {
-            game_state.render.clear_color =
-                Box::new(color::Rgb(current_color, current_color, current_color));
-            current_color = current_color + 1;
+    fn next_state(&self, game_state: &mut GlobalState, effect: Effect) -> GameResult<PlayResult> {
+        Ok(PlayResult::Switch(Box::new(super::menu::PlayState::new(game_state)))
+            .with_transition(effect))
+    }
+}
+
+const SPLASH_DURATION: Duration = Duration::from_secs(2);
+const FADE_DURATION: Duration = Duration::from_millis(600);
+const SOUNDS_PER_TICK: usize = 1;
+const SKIP_WIPE_DURATION: Duration = Duration::from_millis(200);
+
+impl super::PlayState for PlayState {
+
+    fn update(&mut self, game_state: &mut GlobalState, dt: Duration) -> GameResult<PlayResult> {
+        // Decode the sounds while the splash is shown, so they don't hitch later
+        game_state.sound.preload(SOUNDS_PER_TICK);
+
+        self.org_text.advance(dt);
+        self.skip_hint.advance(dt);
 
-            game_state.render.clear_screen();
+        self.timer.advance(dt);
 
-            std::thread::sleep(Duration::from_millis(5));
+        if self.timer.is_finished() {
+            let background = game_state.render.theme.background;
+            return self.next_state(game_state, Effect::fade(background, FADE_DURATION));
         }
 
-        PlayResult::Switch(Box::new(super::menu::PlayState::new(game_state)))
+        Ok(PlayResult::Still)
+    }
+
+    fn render(&mut self, game_state: &mut GlobalState) {
+        self.render_splash(game_state);
+    }
+
+    fn music(&self) -> Music {
+        Music::Silence
     }
 
     fn to_string(&self) -> String {
         String::from("StartSplash")
     }
-}
\ No newline at end of file
+
+    fn on_key_pressed(&mut self, game_state: &mut GlobalState, _key: Key) -> GameResult<PlayResult> {
+        let background = game_state.render.theme.background;
+        self.next_state(game_state, Effect::wipe(background, SKIP_WIPE_DURATION))
+    }
+}
@@ -0,0 +1,137 @@
+//! Transition effects between play states.
+//!
+//! A transition first covers the outgoing state, then the wrapped `PlayResult` is
+//! applied to the state stack and the incoming state is uncovered again.
+
+use crate::game::PlayResult;
+use crate::game::animation::{Animation, Easing, Tween};
+use crate::render::Render;
+
+use std::time::Duration;
+use termion::color;
+
+#[derive(Clone, Copy)]
+pub enum Style {
+    /// Fade through a color using an ordered dither pattern
+    Fade,
+    /// Wipe from the left border to the right one
+    Wipe,
+    /// Cover cells in a random order
+    Dissolve,
+}
+
+#[derive(Clone, Copy)]
+pub struct Effect {
+    style: Style,
+    color: color::Rgb,
+    /// Duration of each half of the transition
+    duration: Duration,
+}
+
+impl Effect {
+    pub fn fade(color: color::Rgb, duration: Duration) -> Self {
+        Effect { style: Style::Fade, color, duration }
+    }
+
+    pub fn wipe(color: color::Rgb, duration: Duration) -> Self {
+        Effect { style: Style::Wipe, color, duration }
+    }
+
+    pub fn dissolve(color: color::Rgb, duration: Duration) -> Self {
+        Effect { style: Style::Dissolve, color, duration }
+    }
+
+    /// Value in range [0; 1). The cell is covered when the coverage is above it.
+    fn threshold(&self, x: u16, y: u16, width: u16) -> f32 {
+        match self.style {
+            Style::Fade => {
+                const BAYER: [[u8; 4]; 4] = [
+                    [0, 8, 2, 10],
+                    [12, 4, 14, 6],
+                    [3, 11, 1, 9],
+                    [15, 7, 13, 5],
+                ];
+
+                (BAYER[(y % 4) as usize][(x % 4) as usize] as f32 + 0.5) / 16.0
+            },
+            Style::Wipe => x as f32 / width.max(1) as f32,
+            Style::Dissolve => {
+                // Cheap integer hash, we just need the order to look random
+                let mut hash = (x as u32).wrapping_mul(374761393)
+                    .wrapping_add((y as u32).wrapping_mul(668265263));
+                hash = (hash ^ (hash >> 13)).wrapping_mul(1274126177);
+                hash ^= hash >> 16;
+
+                (hash % 1024) as f32 / 1024.0
+            }
+        }
+    }
+}
+
+enum Phase {
+    /// Covering the outgoing state
+    Out,
+    /// Uncovering the incoming state
+    In,
+}
+
+pub struct Transition {
+    effect: Effect,
+    phase: Phase,
+    coverage: Tween<f32>,
+    result: Option<PlayResult>,
+}
+
+impl Transition {
+    pub fn new(effect: Effect, result: PlayResult) -> Self {
+        Transition {
+            effect,
+            phase: Phase::Out,
+            coverage: Tween::new(0.0, 1.0, effect.duration).with_easing(Easing::EaseInOut),
+            result: Some(result),
+        }
+    }
+
+    /// Advances the transition. Returns the wrapped result once the outgoing state
+    /// is fully covered, it must be applied to the state stack by the caller.
+    pub fn advance(&mut self, dt: Duration) -> Option<PlayResult> {
+        self.coverage.advance(dt);
+
+        if let Phase::Out = self.phase {
+            if self.coverage.is_finished() {
+                self.phase = Phase::In;
+                self.coverage = Tween::new(1.0, 0.0, self.effect.duration)
+                    .with_easing(Easing::EaseInOut);
+                return self.result.take();
+            }
+        }
+
+        None
+    }
+
+    pub fn is_finished(&self) -> bool {
+        match self.phase {
+            Phase::Out => false,
+            Phase::In => self.coverage.is_finished(),
+        }
+    }
+
+    /// Draws the effect over whatever the current state has rendered
+    pub fn render(&self, render: &mut Render) {
+        let coverage = self.coverage.value();
+        if coverage <= 0.0 {
+            return;
+        }
+
+        let width = render.term_size.width;
+        let height = render.term_size.height;
+
+        for y in 1..=height {
+            for x in 1..=width {
+                if self.effect.threshold(x - 1, y - 1, width) < coverage {
+                    render.set_pixel_color((x, y), self.effect.color);
+                }
+            }
+        }
+    }
+}
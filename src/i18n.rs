@@ -0,0 +1,214 @@
+//! Localization. Every language has a string table `lang/<code>.lang` in the assets
+//! with `key = value` lines. Values can have parameters (`{name}`) and plural forms
+//! (`key.one`, `key.few`, `key.many`, `key.other`, picked by the rules of the language).
+//! Keys missing in a table are taken from English.
+
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::path::PathBuf;
+use log::warn;
+
+use crate::assets::{AssetKind, AssetManager};
+use crate::config::Config;
+use crate::error::Result;
+
+/// Language with every key, used for the missing ones
+pub const DEFAULT_LANGUAGE: &'static str = "en";
+
+/// The language of the system locale, e.g. `ru` for `LANG=ru_RU.UTF-8`
+pub fn system_language() -> Option<String> {
+    ["LC_ALL", "LC_MESSAGES", "LANG"].iter()
+        .filter_map(|variable| std::env::var(variable).ok())
+        .find(|locale| !locale.is_empty())
+        .map(|locale| locale.chars().take_while(|c| c.is_ascii_alphabetic()).collect::<String>())
+        .filter(|language| !language.is_empty() && language != "C")
+        .map(|language| language.to_lowercase())
+}
+
+/// Plural category of the number in the language (CLDR rules for cardinal numbers)
+pub fn plural_category(language: &str, count: i64) -> &'static str {
+    let n = count.abs();
+
+    match language {
+        "ru" | "uk" | "be" => match (n % 10, n % 100) {
+            (1, rest) if rest != 11 => "one",
+            (2..=4, rest) if !(12..=14).contains(&rest) => "few",
+            _ => "many",
+        },
+        "ja" | "zh" | "ko" => "other",
+        _ => match n {
+            1 => "one",
+            _ => "other",
+        },
+    }
+}
+
+/// Replaces `{name}` with the values of the parameters
+fn interpolate(text: &str, args: &[(&str, &dyn Display)]) -> String {
+    let mut text = String::from(text);
+
+    for (name, value) in args {
+        text = text.replace(format!("{{{}}}", name).as_str(), value.to_string().as_str());
+    }
+
+    text
+}
+
+pub struct Strings {
+    language: String,
+    table: Config,
+    /// English table, None if it is the language
+    fallback: Option<Config>,
+    /// Keys reported as missing, so the warning isn't repeated every frame
+    missing: RefCell<HashSet<String>>,
+}
+
+fn load_table(assets: &AssetManager, language: &str) -> Result<Config> {
+    let text = assets.read_text(AssetKind::Lang, language)?;
+
+    let mut table = Config::new(&PathBuf::from(assets.source(AssetKind::Lang, language)));
+    table.parse(&text);
+
+    Ok(table)
+}
+
+impl Strings {
+    pub fn load(assets: &AssetManager, language: &str) -> Result<Self> {
+        let table = load_table(assets, language)?;
+
+        let fallback = match language {
+            DEFAULT_LANGUAGE => None,
+            _ => Some(load_table(assets, DEFAULT_LANGUAGE)?),
+        };
+
+        Ok(Strings {
+            language: String::from(language),
+            table,
+            fallback,
+            missing: RefCell::new(HashSet::new()),
+        })
+    }
+
+    /// No strings, every key is shown as it is
+    pub fn empty() -> Self {
+        Strings {
+            language: String::from(DEFAULT_LANGUAGE),
+            table: Config::new(&PathBuf::new()),
+            fallback: None,
+            missing: RefCell::new(HashSet::new()),
+        }
+    }
+
+    pub fn language(&self) -> &str {
+        &self.language
+    }
+
+    fn lookup(&self, key: &str) -> Option<&str> {
+        self.table.get(key)
+            .or_else(|| self.fallback.as_ref().and_then(|fallback| fallback.get(key)))
+    }
+
+    /// The text of the key, the key itself if it is not translated
+    pub fn get(&self, key: &str) -> String {
+        match self.lookup(key) {
+            Some(text) => String::from(text),
+            None => {
+                if self.missing.borrow_mut().insert(String::from(key)) {
+                    warn!("No string {} for language {}", key, self.language);
+                }
+
+                String::from(key)
+            }
+        }
+    }
+
+    /// The text with the parameters, e.g. `("name", &"goblin")` for `{name}`
+    pub fn format(&self, key: &str, args: &[(&str, &dyn Display)]) -> String {
+        interpolate(&self.get(key), args)
+    }
+
+    /// The plural form of the key for the count. `{count}` is the count.
+    pub fn plural(&self, key: &str, count: i64, args: &[(&str, &dyn Display)]) -> String {
+        let category = plural_category(&self.language, count);
+
+        // Tables may leave out the forms which are the same as `other`
+        let text = [category, "other"].iter()
+            .filter_map(|category| self.lookup(format!("{}.{}", key, category).as_str()))
+            .next()
+            .map(String::from)
+            .unwrap_or_else(|| self.get(key));
+
+        interpolate(&interpolate(&text, &[("count", &count)]), args)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(text: &str) -> Config {
+        let mut table = Config::new(&PathBuf::new());
+        table.parse(text);
+        table
+    }
+
+    fn strings(language: &str, text: &str, english: &str) -> Strings {
+        Strings {
+            language: String::from(language),
+            table: table(text),
+            fallback: Some(table(english)),
+            missing: RefCell::new(HashSet::new()),
+        }
+    }
+
+    #[test]
+    fn russian_plural_rules() {
+        let category = |count| plural_category("ru", count);
+
+        for count in [1, 21, 101, 1001, -1].iter() {
+            assert_eq!(category(*count), "one", "{}", count);
+        }
+
+        for count in [2, 3, 4, 22, 34, 102].iter() {
+            assert_eq!(category(*count), "few", "{}", count);
+        }
+
+        for count in [0, 5, 11, 12, 13, 14, 15, 20, 111, 112, 114, 211].iter() {
+            assert_eq!(category(*count), "many", "{}", count);
+        }
+    }
+
+    #[test]
+    fn other_languages_plural_rules() {
+        assert_eq!(plural_category("en", 1), "one");
+        assert_eq!(plural_category("en", 0), "other");
+        assert_eq!(plural_category("en", 21), "other");
+        assert_eq!(plural_category("ja", 1), "other");
+    }
+
+    #[test]
+    fn plural_falls_back_to_other_and_then_to_english() {
+        let strings = strings("ru",
+            "coins.one = {count} монета\ncoins.other = {count} монет\nkeys.few = {count} ключа",
+            "coins.one = {count} coin\ncoins.other = {count} coins\nkeys.other = {count} keys\ngold = gold");
+
+        assert_eq!(strings.plural("coins", 21, &[]), "21 монета");
+        // No `many` form, `other` of the table is used
+        assert_eq!(strings.plural("coins", 5, &[]), "5 монет");
+        assert_eq!(strings.plural("keys", 3, &[]), "3 ключа");
+        // Neither `many` nor `other` in the table, English `other` is used
+        assert_eq!(strings.plural("keys", 5, &[]), "5 keys");
+        // No plural forms at all, the plain key
+        assert_eq!(strings.plural("gold", 5, &[]), "gold");
+        assert_eq!(strings.plural("missing", 5, &[]), "missing");
+    }
+
+    #[test]
+    fn parameters_are_replaced() {
+        let strings = strings("en", "hit = {name} hits {damage}\nfound.other = {name} found {count}", "");
+
+        assert_eq!(strings.plural("found", 2, &[("name", &"Ann")]), "Ann found 2");
+        assert_eq!(strings.format("hit", &[("name", &"goblin"), ("damage", &3)]), "goblin hits 3");
+    }
+}
@@ -1,3 +1,13 @@
 pub mod game;
 pub mod render;
-pub mod widgets;
\ No newline at end of file
+pub mod widgets;
+pub mod config;
+pub mod assets;
+pub mod bundle;
+pub mod cli;
+pub mod logging;
+pub mod error;
+pub mod theme;
+pub mod unicode;
+pub mod i18n;
+pub mod rng;
@@ -0,0 +1,166 @@
+//! Logging setup. Records go to the log file and to a ring buffer
+//! which is shown by the in-game log console.
+//!
+//! The logging config (`log.cfg` in the config directory) is a `key = value` file:
+//! ```text
+//! file = game.log
+//! level = info
+//! pattern = {l}: {m}{n}
+//! buffer = 500
+//! module.rust_rpg::game::sound = debug
+//! ```
+
+use std::collections::VecDeque;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use log::{Level, LevelFilter, Record};
+use log4rs::append::Append;
+use log4rs::append::file::FileAppender;
+use log4rs::config::{Appender, Config as LogConfig, Logger, Root};
+use log4rs::encode::pattern::PatternEncoder;
+
+use crate::config::{self, Config};
+
+pub const LOG_CONFIG_FILE: &'static str = "log.cfg";
+
+const DEFAULT_FILE: &'static str = "game.log";
+const DEFAULT_LEVEL: LevelFilter = LevelFilter::Info;
+const DEFAULT_PATTERN: &'static str = "{l}: {m}{n}";
+const DEFAULT_BUFFER: usize = 500;
+
+const MODULE_PREFIX: &'static str = "module.";
+
+#[derive(Clone, Debug)]
+pub struct LogLine {
+    pub level: Level,
+    pub target: String,
+    pub message: String,
+}
+
+/// Last log records, shared between the appender and the console
+#[derive(Clone, Debug)]
+pub struct LogBuffer {
+    lines: Arc<Mutex<VecDeque<LogLine>>>,
+    capacity: usize,
+}
+
+impl LogBuffer {
+    pub fn new(capacity: usize) -> Self {
+        LogBuffer {
+            lines: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+        }
+    }
+
+    pub fn push(&self, line: LogLine) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut lines = self.lines.lock().unwrap();
+        if lines.len() == self.capacity {
+            lines.pop_front();
+        }
+
+        lines.push_back(line);
+    }
+
+    /// The last `count` lines passing the filter, oldest first
+    pub fn last(&self, filter: LevelFilter, count: usize) -> Vec<LogLine> {
+        let lines = self.lines.lock().unwrap();
+        let mut last: Vec<LogLine> = lines.iter().rev()
+            .filter(|line| line.level <= filter)
+            .take(count)
+            .cloned()
+            .collect();
+
+        last.reverse();
+        last
+    }
+}
+
+/// Appender which feeds the log buffer
+#[derive(Debug)]
+pub struct BufferAppender {
+    buffer: LogBuffer,
+}
+
+impl Append for BufferAppender {
+    fn append(&self, record: &Record) -> Result<(), Box<dyn Error + Sync + Send>> {
+        self.buffer.push(LogLine {
+            level: record.level(),
+            target: String::from(record.target()),
+            message: record.args().to_string(),
+        });
+
+        Ok(())
+    }
+
+    fn flush(&self) {}
+}
+
+pub struct LogSettings {
+    pub file: PathBuf,
+    pub level: LevelFilter,
+    pub pattern: String,
+    /// How many records the in-game console keeps
+    pub buffer: usize,
+    /// Levels of the modules (e.g. `rust_rpg::game::sound`) which differ from the root level
+    pub modules: Vec<(String, LevelFilter)>,
+}
+
+impl LogSettings {
+    pub fn from_config(config: &Config) -> Self {
+        let modules = config.entries()
+            .filter(|(key, _)| key.starts_with(MODULE_PREFIX))
+            .filter_map(|(key, _)| config.get_parsed(key)
+                .map(|level| (String::from(&key[MODULE_PREFIX.len()..]), level)))
+            .collect();
+
+        LogSettings {
+            file: config.get("file").map_or(PathBuf::from(DEFAULT_FILE), PathBuf::from),
+            level: config.get_parsed("level").unwrap_or(DEFAULT_LEVEL),
+            pattern: String::from(config.get("pattern").unwrap_or(DEFAULT_PATTERN)),
+            buffer: config.get_parsed("buffer").unwrap_or(DEFAULT_BUFFER),
+            modules,
+        }
+    }
+
+    /// Settings from the logging config in the config directory
+    pub fn load() -> Self {
+        LogSettings::from_config(&Config::load(&config::config_dir().join(LOG_CONFIG_FILE)))
+    }
+}
+
+/// Starts logging to the file and the returned buffer.
+/// `file` and `level` override the logging config (e.g. from the command line).
+pub fn init(settings: LogSettings, file: Option<&Path>, level: Option<LevelFilter>) -> Result<LogBuffer, String> {
+    let file = file.unwrap_or(&settings.file);
+    let level = level.unwrap_or(settings.level);
+
+    let file_appender = FileAppender::builder()
+        .encoder(Box::new(PatternEncoder::new(&settings.pattern)))
+        .append(false)
+        .build(file)
+        .map_err(|err| format!("failed to open log file {}: {}", file.display(), err))?;
+
+    let buffer = LogBuffer::new(settings.buffer);
+    let buffer_appender = BufferAppender { buffer: buffer.clone() };
+
+    let mut builder = LogConfig::builder()
+        .appender(Appender::builder().build("file", Box::new(file_appender)))
+        .appender(Appender::builder().build("console", Box::new(buffer_appender)));
+
+    for (module, module_level) in settings.modules {
+        builder = builder.logger(Logger::builder().build(module, module_level));
+    }
+
+    let config = builder
+        .build(Root::builder().appender("file").appender("console").build(level))
+        .map_err(|err| format!("bad logging config: {}", err))?;
+
+    log4rs::init_config(config).map_err(|err| format!("failed to init logging: {}", err))?;
+
+    Ok(buffer)
+}
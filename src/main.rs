@@ -1,30 +1,188 @@
 use rust_rpg::*;
 use std::process;
-use log4rs::append::file::FileAppender;
-use log4rs::encode::pattern::PatternEncoder;
-use log4rs::config::{Config, Appender, Root};
-use log::LevelFilter;
+use std::path::Path;
 
-fn main() {
-    // Init logger
-    let appender = FileAppender::builder()
-        .encoder(Box::new(PatternEncoder::new("{l}: {m}\n")))
-        .append(false)
-        .build("game.log")
-        .unwrap();
+use rust_rpg::assets::AssetKind;
+use rust_rpg::cli::{Command, Options};
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("{}\n\n{}", message, cli::USAGE);
+    process::exit(2);
+}
+
+fn pack(dir: &Path, output: &Path) {
+    match bundle::pack(dir, output) {
+        Ok(count) => println!("Packed {} files into {}", count, output.display()),
+        Err(err) => {
+            eprintln!("Failed to pack {}: {}", dir.display(), err);
+            process::exit(1);
+        }
+    }
+}
 
-    let config = Config::builder()
-        .appender(Appender::builder().build("filelog", Box::new(appender)))
-        .build(Root::builder().appender("filelog").build(LevelFilter::Trace))
-        .unwrap();
+fn verify(file: &Path) {
+    let bundle = match bundle::Bundle::open(file) {
+        Ok(bundle) => bundle,
+        Err(err) => {
+            eprintln!("Failed to open {}: {}", file.display(), err);
+            process::exit(1);
+        }
+    };
+
+    let problems = bundle.verify();
+    if !problems.is_empty() {
+        for problem in problems {
+            eprintln!("{}", problem);
+        }
+
+        process::exit(1);
+    }
+
+    println!("{}: {} files OK", bundle.name(), bundle.paths().count());
+}
+
+fn play(options: Options) {
+    let log_buffer = logging::init(logging::LogSettings::load(),
+                                   options.log_file.as_deref(),
+                                   options.log_level)
+        .unwrap_or_else(|err| {
+            eprintln!("Failed to start the game: {}", err);
+            process::exit(1);
+        });
+
+    let root = match assets::find_root(options.assets.as_deref()) {
+        Ok(root) => root,
+        Err(err) => {
+            eprintln!("Failed to start the game: {}", err);
+            process::exit(1);
+        }
+    };
+
+    // A replay doesn't depend on the settings, saves and mods of the player, and doesn't change them
+    let isolated_dir = options.replay.as_ref().map(|_| {
+        config::isolate().unwrap_or_else(|err| {
+            eprintln!("Failed to start the replay: {}", err);
+            process::exit(1);
+        })
+    });
+
+    // Bundles from the mods directory go first, so the command line ones override them
+    let mut bundles = assets::find_bundles(&config::config_dir().join("mods"));
+    bundles.extend(options.bundles);
+
+    let mut asset_manager = assets::AssetManager::new(&root);
+    for file in bundles {
+        match bundle::Bundle::open(&file) {
+            Ok(bundle) => asset_manager.mount(bundle),
+            Err(err) => {
+                eprintln!("Failed to open bundle {}: {}", file.display(), err);
+                process::exit(1);
+            }
+        }
+    }
+
+    let recording = options.replay.as_ref().map(|path| {
+        game::Recording::load(path).unwrap_or_else(|err| {
+            eprintln!("Failed to load the replay: {}", err);
+            process::exit(1);
+        })
+    });
+
+    let start = match (options.map, options.save) {
+        (Some(map), _) => game::Start::Map(map),
+        (None, Some(path)) => match game::Save::load(&path) {
+            Ok(save) => game::Start::Save(save),
+            Err(err) => {
+                eprintln!("Failed to load the game: {}", err);
+                process::exit(1);
+            }
+        },
+        (None, None) if options.skip_splash => game::Start::Menu,
+        (None, None) => game::Start::Splash,
+    };
+
+    let mut required = game::REQUIRED_ASSETS.to_vec();
+    let checked_start = match (&start, &recording) {
+        (_, Some(recording)) => &recording.start,
+        (start, None) => start,
+    };
+
+    match checked_start {
+        // Generated levels have no map file
+        game::Start::Map(map) if !game::is_generated_map(map) => required.push((AssetKind::Map, map.as_str())),
+        game::Start::Save(save) if !game::is_generated_map(&save.map) => {
+            required.push((AssetKind::Map, save.map.as_str()))
+        },
+        _ => {}
+    }
 
-    let _handle = log4rs::init_config(config).unwrap();
+    let missing = asset_manager.check(&required);
+    if !missing.is_empty() {
+        eprintln!("Failed to start the game, assets in {} are missing:", root.display());
+        for line in missing {
+            eprintln!("  {}", line);
+        }
+
+        process::exit(1);
+    }
+
+    let mut game_state = game::Game::new(asset_manager);
+    game_state.set_start(start);
+    game_state.set_log_buffer(log_buffer);
+    game_state.set_sound_enabled(!options.no_sound);
+    game_state.set_color_mode(options.color);
+    if let Some(fps) = options.fps {
+        game_state.set_fps(fps);
+    }
+
+    if let Some(seed) = options.seed {
+        game_state.set_seed(seed);
+    }
+
+    if let Some(path) = options.record {
+        game_state.set_record(path);
+    }
+
+    let headless = options.headless;
+    if let (Some(path), Some(recording)) = (options.replay, recording) {
+        game_state.set_replay(path, recording, options.speed, headless);
+    }
 
-    let mut game_state = game::Game::new();
     if let Err(error) = game_state.run() {
-        println!("Game crashed: {}", error);
+        match headless {
+            true => eprintln!("Replay failed: {}", error),
+            false => eprintln!("Game crashed: {}", error),
+        }
+
         process::exit(1);
     }
 
+    // Kept after a failure, it has the crash dump
+    if let Some(dir) = isolated_dir {
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    if headless {
+        println!("Replay OK");
+        return;
+    }
+
     println!("Thanks for playing! Goodbye!");
 }
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    let command = match cli::parse(&args) {
+        Ok(command) => command,
+        Err(err) => usage_error(&err),
+    };
+
+    match command {
+        Command::Help => println!("{}", cli::USAGE),
+        Command::Version => println!("rust-rpg {}", env!("CARGO_PKG_VERSION")),
+        Command::Pack { dir, output } => pack(&dir, &output),
+        Command::Verify(file) => verify(&file),
+        Command::Play(options) => play(options),
+    }
+}
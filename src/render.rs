@@ -4,6 +4,10 @@ use termion::{color, cursor, clear, style};
 use std::io::{Error, Write, Stdout, StdoutLock};
 use termion::raw::{IntoRawMode, RawTerminal};
 use std::cmp::max;
+use std::str::FromStr;
+use std::fmt::{Display, Formatter};
+
+use crate::theme::Theme;
 
 #[doc(hidden)]
 pub fn _print(args: ::core::fmt::Arguments) {
@@ -54,14 +58,158 @@ impl Position {
     }
 }
 
+/// Colors the terminal can show
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ColorMode {
+    /// Detect from the terminal
+    Auto,
+    TrueColor,
+    Ansi256,
+    Ansi16,
+    /// No colors, only glyphs and attributes
+    Mono,
+}
+
+impl ColorMode {
+    pub const ALL: [ColorMode; 5] = [ColorMode::Auto, ColorMode::TrueColor, ColorMode::Ansi256,
+                                     ColorMode::Ansi16, ColorMode::Mono];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ColorMode::Auto => "auto",
+            ColorMode::TrueColor => "truecolor",
+            ColorMode::Ansi256 => "256",
+            ColorMode::Ansi16 => "16",
+            ColorMode::Mono => "mono",
+        }
+    }
+
+    /// Color support of the terminal from `NO_COLOR`, `COLORTERM` and `TERM`
+    pub fn detect() -> ColorMode {
+        if std::env::var_os("NO_COLOR").is_some() {
+            return ColorMode::Mono;
+        }
+
+        let colorterm = std::env::var("COLORTERM").unwrap_or_default();
+        if colorterm == "truecolor" || colorterm == "24bit" {
+            return ColorMode::TrueColor;
+        }
+
+        let term = std::env::var("TERM").unwrap_or_default();
+        if term.is_empty() || term == "dumb" {
+            ColorMode::Mono
+        } else if term.contains("256color") || term.contains("direct") {
+            ColorMode::Ansi256
+        } else {
+            ColorMode::Ansi16
+        }
+    }
+
+    /// Auto is replaced with the detected mode
+    pub fn resolve(self) -> ColorMode {
+        match self {
+            ColorMode::Auto => ColorMode::detect(),
+            mode => mode,
+        }
+    }
+}
+
+impl Display for ColorMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for ColorMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(ColorMode::Auto),
+            "truecolor" | "24bit" => Ok(ColorMode::TrueColor),
+            "256" => Ok(ColorMode::Ansi256),
+            "16" => Ok(ColorMode::Ansi16),
+            "mono" => Ok(ColorMode::Mono),
+            _ => Err(format!("unknown color mode {}", s)),
+        }
+    }
+}
+
+/// The standard 16 colors as the VGA text mode shows them
+const ANSI_16: [(u8, u8, u8); 16] = [
+    (0, 0, 0), (170, 0, 0), (0, 170, 0), (170, 85, 0),
+    (0, 0, 170), (170, 0, 170), (0, 170, 170), (170, 170, 170),
+    (85, 85, 85), (255, 85, 85), (85, 255, 85), (255, 255, 85),
+    (85, 85, 255), (255, 85, 255), (85, 255, 255), (255, 255, 255),
+];
+
+/// Levels of the 6x6x6 color cube of the 256-color palette
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+/// Glyphs for the brightness of a cell in the monochrome mode, from dark to bright
+const SHADES: [char; 5] = [' ', '░', '▒', '▓', '█'];
+
+fn distance(a: (u8, u8, u8), b: (u8, u8, u8)) -> u32 {
+    let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2) as u32;
+
+    // Weighted by how sensitive the eye is to each channel
+    2 * d(a.0, b.0) + 4 * d(a.1, b.1) + 3 * d(a.2, b.2)
+}
+
+fn nearest_cube_level(value: u8) -> usize {
+    (0..CUBE_LEVELS.len())
+        .min_by_key(|i| (CUBE_LEVELS[*i] as i32 - value as i32).abs())
+        .unwrap()
+}
+
+/// Index of the closest color of the 256-color palette (cube or gray ramp)
+pub fn nearest_256(color: color::Rgb) -> u8 {
+    let rgb = (color.0, color.1, color.2);
+
+    let (r, g, b) = (nearest_cube_level(rgb.0), nearest_cube_level(rgb.1), nearest_cube_level(rgb.2));
+    let cube = (CUBE_LEVELS[r], CUBE_LEVELS[g], CUBE_LEVELS[b]);
+    let cube_index = (16 + 36 * r + 6 * g + b) as u8;
+
+    // Gray ramp is 232..=255 with levels 8, 18, .. 238
+    let average = (rgb.0 as u32 + rgb.1 as u32 + rgb.2 as u32) / 3;
+    let gray_step = ((average.max(8) - 8 + 5) / 10).min(23) as u8;
+    let gray_level = 8 + gray_step * 10;
+    let gray = (gray_level, gray_level, gray_level);
+
+    match distance(rgb, gray) < distance(rgb, cube) {
+        true => 232 + gray_step,
+        false => cube_index,
+    }
+}
+
+/// Index of the closest of the standard 16 colors
+pub fn nearest_16(color: color::Rgb) -> u8 {
+    let rgb = (color.0, color.1, color.2);
+
+    (0..ANSI_16.len())
+        .min_by_key(|i| distance(rgb, ANSI_16[*i]))
+        .unwrap() as u8
+}
+
+/// Perceived brightness from 0 to 255
+pub fn luminance(color: color::Rgb) -> u8 {
+    ((color.0 as u32 * 299 + color.1 as u32 * 587 + color.2 as u32 * 114) / 1000) as u8
+}
+
 pub struct Render {
     pub term_size: TermSize,
-    pub clear_color: Box<dyn color::Color>,
-    pub hide_cursor: bool
+    /// Never `Auto`, it is resolved by `set_color_mode`
+    color_mode: ColorMode,
+    pub clear_color: color::Rgb,
+    pub hide_cursor: bool,
+    /// Colors of the UI
+    pub theme: Theme,
+    /// Nothing is written to the terminal, e.g. for a replay checked without a screen
+    headless: bool,
 }
 
 pub trait Drawable {
-    fn draw(&self) -> String;
+    fn draw(&self, render: &Render) -> String;
     fn get_width(&self) -> u16;
     fn get_height(&self) -> u16;
     fn get_position(&self) -> &Position;
@@ -82,14 +230,67 @@ impl Render {
 
         Render {
             term_size,
-            clear_color: Box::new(color::Black),
-            hide_cursor: true
+            color_mode: ColorMode::detect(),
+            clear_color: color::Rgb(0, 0, 0),
+            hide_cursor: true,
+            theme: Theme::default(),
+            headless: false,
         }
     }
 
+    /// Stops the output, the layout goes on as if the terminal had the size
+    pub fn set_headless(&mut self, size: TermSize) {
+        self.term_size = size;
+        self.headless = true;
+    }
+
+    pub fn color_mode(&self) -> ColorMode {
+        self.color_mode
+    }
+
+    pub fn set_color_mode(&mut self, mode: ColorMode) {
+        self.color_mode = mode.resolve();
+    }
+
+    /// Escape sequence for the foreground color in the current color mode
+    pub fn fg(&self, color: color::Rgb) -> String {
+        match self.color_mode {
+            ColorMode::Auto | ColorMode::TrueColor => color::Fg(color).to_string(),
+            ColorMode::Ansi256 => color::Fg(color::AnsiValue(nearest_256(color))).to_string(),
+            ColorMode::Ansi16 => match nearest_16(color) {
+                index if index < 8 => format!("\x1b[{}m", 30 + index),
+                index => format!("\x1b[{}m", 90 + index - 8),
+            },
+            ColorMode::Mono => String::new(),
+        }
+    }
+
+    /// Escape sequence for the background color in the current color mode
+    pub fn bg(&self, color: color::Rgb) -> String {
+        match self.color_mode {
+            ColorMode::Auto | ColorMode::TrueColor => color::Bg(color).to_string(),
+            ColorMode::Ansi256 => color::Bg(color::AnsiValue(nearest_256(color))).to_string(),
+            ColorMode::Ansi16 => match nearest_16(color) {
+                index if index < 8 => format!("\x1b[{}m", 40 + index),
+                index => format!("\x1b[{}m", 100 + index - 8),
+            },
+            ColorMode::Mono => String::new(),
+        }
+    }
+
+    /// Glyph which stands for the color of a cell in the monochrome mode
+    pub fn shade(&self, color: color::Rgb) -> char {
+        SHADES[luminance(color) as usize * SHADES.len() / 256]
+    }
+
     pub fn clear_screen(&mut self) {
-        render!("{}{}{}",
-               color::Bg(self.clear_color.as_ref()),
+        if self.headless {
+            return;
+        }
+
+        render!("{}{}{}{}",
+               style::Reset,
+               self.bg(self.clear_color),
                clear::All,
                cursor::Goto(1, 1));
 
@@ -113,14 +314,26 @@ impl Render {
     }
 
     pub fn set_cursor_position(&mut self, coord: (u16, u16)) {
+        if self.headless {
+            return;
+        }
+
         render!("{}", cursor::Goto(coord.0, coord.1));
     }
 
-    pub fn set_pixel_color(&mut self, coord: (u16, u16), color: Box<dyn color::Color>) {
-        print!("{}{} ", cursor::Goto(coord.0, coord.1), color::Bg(color.as_ref()));
+    pub fn set_pixel_color(&mut self, coord: (u16, u16), color: color::Rgb) {
+        if self.headless {
+            return;
+        }
+
+        match self.color_mode {
+            ColorMode::Mono => print!("{}{}", cursor::Goto(coord.0, coord.1), self.shade(color)),
+            _ => print!("{}{} ", cursor::Goto(coord.0, coord.1), self.bg(color)),
+        }
     }
 
-    pub fn draw<T: Drawable>(&mut self, drawable: &T) {
+    /// Screen cell of the top left corner of the drawable
+    pub fn resolve_position<T: Drawable>(&self, drawable: &T) -> (u16, u16) {
         let position = drawable.get_position();
 
         let x = match position.x {
@@ -139,20 +352,90 @@ impl Render {
             Coordinate::FromBorder(y) => max(1, self.term_size.height - y)
         };
 
-        render!("{}{}", cursor::Goto(x, y), drawable.draw());
+        (x, y)
+    }
+
+    /// Whether the cell (e.g. under the mouse) is inside the drawable
+    pub fn contains<T: Drawable>(&self, drawable: &T, (x, y): (u16, u16)) -> bool {
+        let (left, top) = self.resolve_position(drawable);
+
+        (left..left + drawable.get_width()).contains(&x) && (top..top + drawable.get_height()).contains(&y)
+    }
+
+    pub fn draw<T: Drawable>(&mut self, drawable: &T) {
+        if self.headless {
+            return;
+        }
+
+        let (x, y) = self.resolve_position(drawable);
+        render!("{}{}", cursor::Goto(x, y), drawable.draw(self));
     }
 
     pub fn draw_raw(&mut self, string: &str) {
+        if self.headless {
+            return;
+        }
+
         render!("{}", string);
     }
 
     pub fn flash(&self) {
+        if self.headless {
+            return;
+        }
+
         std::io::stdout().flush().unwrap();
     }
 }
 
 impl Drop for Render {
     fn drop(&mut self) {
+        if self.headless {
+            return;
+        }
+
         print!("{}{}{}{}", clear::All, style::Reset, cursor::Show, cursor::Goto(1, 1));
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn primaries_map_to_the_cube_and_the_16_colors() {
+        assert_eq!(nearest_256(color::Rgb(255, 0, 0)), 196);
+        assert_eq!(nearest_256(color::Rgb(0, 255, 0)), 46);
+        assert_eq!(nearest_256(color::Rgb(0, 0, 255)), 21);
+        assert_eq!(nearest_256(color::Rgb(0, 0, 0)), 16);
+        assert_eq!(nearest_256(color::Rgb(255, 255, 255)), 231);
+
+        assert_eq!(nearest_16(color::Rgb(255, 0, 0)), 1);
+        assert_eq!(nearest_16(color::Rgb(0, 255, 0)), 2);
+        assert_eq!(nearest_16(color::Rgb(0, 0, 255)), 4);
+        assert_eq!(nearest_16(color::Rgb(0, 0, 0)), 0);
+        assert_eq!(nearest_16(color::Rgb(255, 255, 255)), 15);
+    }
+
+    #[test]
+    fn grays_land_on_the_gray_ramp() {
+        assert_eq!(nearest_256(color::Rgb(8, 8, 8)), 232);
+        assert_eq!(nearest_256(color::Rgb(128, 128, 128)), 244);
+        assert_eq!(nearest_256(color::Rgb(238, 238, 238)), 255);
+
+        for level in 8..=238 {
+            let index = nearest_256(color::Rgb(level, level, level));
+            assert!(index >= 232 || [16, 59, 102, 145, 188, 231].contains(&index), "{} gave {}", level, index);
+        }
+    }
+
+    #[test]
+    fn shade_goes_from_blank_to_full() {
+        let mut render = Render::new();
+        render.set_headless(TermSize::default());
+
+        assert_eq!(render.shade(color::Rgb(0, 0, 0)), ' ');
+        assert_eq!(render.shade(color::Rgb(255, 255, 255)), '█');
+        assert_eq!(render.shade(color::Rgb(128, 128, 128)), '▒');
+    }
+}
@@ -0,0 +1,259 @@
+//! Seeded random numbers. The same seed always gives the same sequence on every
+//! platform, so generated levels and other random events can be reproduced.
+//!
+//! The generator is SplitMix64: small, fast and good enough for a game, not for
+//! anything secure.
+//!
+//! All randomness of a game goes through `Random`, which has a separate stream
+//! for every subsystem. Using one stream more or less (e.g. an extra monster
+//! decision) doesn't change what the other ones give, so a level stays the same
+//! even if the monsters before it moved differently. Loot is part of the level
+//! and comes from its seed.
+
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: u64,
+}
+
+/// A seed from the clock, for when no seed is given
+pub fn random_seed() -> u64 {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_nanos() as u64);
+
+    // One round of mixing so close timestamps give unrelated seeds
+    Rng::new(nanos).next_u64()
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng { state: seed }
+    }
+
+    /// Everything needed to continue the sequence, `Rng::new(state)` goes on from here
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `min..max`, `min` if the range is empty
+    pub fn range(&mut self, min: i32, max: i32) -> i32 {
+        if max <= min {
+            return min;
+        }
+
+        let span = (max as i64 - min as i64) as u64;
+        (min as i64 + (self.next_u64() % span) as i64) as i32
+    }
+
+    /// A number in `0.0..1.0`
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /// True with the probability (`0.0..=1.0`)
+    pub fn chance(&mut self, probability: f32) -> bool {
+        self.next_f32() < probability
+    }
+
+    /// An element of the slice, None if it is empty
+    pub fn choose<'a, T>(&mut self, items: &'a [T]) -> Option<&'a T> {
+        match items.len() {
+            0 => None,
+            len => items.get(self.range(0, len as i32) as usize),
+        }
+    }
+
+    /// An element picked by its weight, None if no element has a weight
+    pub fn weighted<'a, T, F>(&mut self, items: &'a [T], weight: F) -> Option<&'a T>
+        where F: Fn(&T) -> u32 {
+
+        let total: u32 = items.iter().map(&weight).sum();
+        if total == 0 {
+            return None;
+        }
+
+        let mut roll = self.range(0, total as i32) as u32;
+        for item in items {
+            let weight = weight(item);
+            if roll < weight {
+                return Some(item);
+            }
+
+            roll -= weight;
+        }
+
+        None
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Stream {
+    /// Seeds of the generated levels
+    Generation,
+    /// Decisions of the monsters
+    Ai,
+}
+
+impl Stream {
+    pub const ALL: [Stream; 2] = [Stream::Generation, Stream::Ai];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Stream::Generation => "generation",
+            Stream::Ai => "ai",
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+impl Display for Stream {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Stream {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        Stream::ALL.iter().cloned()
+            .find(|stream| stream.name() == text)
+            .ok_or(format!("unknown stream {}", text))
+    }
+}
+
+/// The random streams of a game, all made from one seed
+#[derive(Clone, Debug)]
+pub struct Random {
+    seed: u64,
+    streams: Vec<Rng>,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Self {
+        // Every stream starts from its own mix of the seed, so they don't repeat each other
+        let streams = Stream::ALL.iter()
+            .map(|stream| seed ^ (stream.index() as u64 + 1).wrapping_mul(0xD6E8_FEB8_6659_FD93))
+            .map(|mixed| Rng::new(Rng::new(mixed).next_u64()))
+            .collect();
+
+        Random { seed, streams }
+    }
+
+    /// The game with the seed continued from the saved states of the streams.
+    /// Missing streams start over.
+    pub fn restore(seed: u64, states: &BTreeMap<String, u64>) -> Self {
+        let mut random = Random::new(seed);
+
+        for stream in Stream::ALL.iter() {
+            if let Some(state) = states.get(stream.name()) {
+                random.streams[stream.index()] = Rng::new(*state);
+            }
+        }
+
+        random
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn stream(&mut self, stream: Stream) -> &mut Rng {
+        &mut self.streams[stream.index()]
+    }
+
+    /// States of the streams by their names, for `restore`
+    pub fn states(&self) -> BTreeMap<String, u64> {
+        Stream::ALL.iter()
+            .map(|stream| (String::from(stream.name()), self.streams[stream.index()].state()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn take(rng: &mut Rng, count: usize) -> Vec<u64> {
+        (0..count).map(|_| rng.next_u64()).collect()
+    }
+
+    #[test]
+    fn same_seed_gives_same_numbers() {
+        assert_eq!(take(&mut Rng::new(99), 16), take(&mut Rng::new(99), 16));
+        assert_ne!(take(&mut Rng::new(99), 16), take(&mut Rng::new(100), 16));
+    }
+
+    #[test]
+    fn streams_are_independent() {
+        let mut quiet = Random::new(5);
+        let mut busy = Random::new(5);
+
+        // Drawing from one stream leaves the others where they were
+        take(busy.stream(Stream::Ai), 1000);
+
+        assert_eq!(take(quiet.stream(Stream::Generation), 16), take(busy.stream(Stream::Generation), 16));
+        assert_ne!(take(quiet.stream(Stream::Ai), 16), take(busy.stream(Stream::Ai), 16));
+
+        // And the streams of one seed don't repeat each other
+        let mut random = Random::new(5);
+        let generation = take(random.stream(Stream::Generation), 16);
+        assert_ne!(generation, take(random.stream(Stream::Ai), 16));
+    }
+
+    #[test]
+    fn restore_continues_the_streams() {
+        let mut random = Random::new(11);
+        take(random.stream(Stream::Generation), 3);
+        take(random.stream(Stream::Ai), 7);
+
+        let mut restored = Random::restore(random.seed(), &random.states());
+        assert_eq!(restored.states(), random.states());
+
+        for stream in Stream::ALL.iter() {
+            assert_eq!(take(restored.stream(*stream), 16), take(random.stream(*stream), 16), "{}", stream);
+        }
+    }
+
+    #[test]
+    fn restore_starts_missing_streams_over() {
+        let mut random = Random::new(11);
+        take(random.stream(Stream::Ai), 7);
+
+        let mut states = random.states();
+        states.remove(Stream::Ai.name());
+
+        let mut restored = Random::restore(11, &states);
+        assert_eq!(take(restored.stream(Stream::Ai), 16), take(Random::new(11).stream(Stream::Ai), 16));
+    }
+
+    #[test]
+    fn ranges_stay_in_bounds() {
+        let mut rng = Rng::new(3);
+
+        for _ in 0..1000 {
+            let value = rng.range(-5, 5);
+            assert!((-5..5).contains(&value));
+            assert!(rng.next_f32() < 1.0);
+        }
+
+        assert_eq!(rng.range(4, 4), 4);
+        assert_eq!(rng.weighted(&[1, 2], |_| 0), None);
+        assert_eq!(rng.choose::<u8>(&[]), None);
+    }
+}
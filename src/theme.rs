@@ -0,0 +1,238 @@
+//! UI color themes. Built-in themes plus `key = value` theme files in the
+//! `themes` directory of the config dir, e.g. `themes/solarized.theme`:
+//!
+//! ```text
+//! base = dark
+//! background = #002b36
+//! highlight = 133, 153, 0
+//! ```
+
+use std::fs;
+use std::path::PathBuf;
+use termion::color::Rgb;
+
+use crate::config::{self, Config};
+
+pub const EXTENSION: &'static str = "theme";
+
+/// Used when no theme is chosen or the chosen one can't be loaded
+pub const DEFAULT_THEME: &'static str = "light";
+
+pub const BUILTIN_THEMES: [&'static str; 3] = ["light", "dark", "high-contrast"];
+
+#[derive(Clone, Debug)]
+pub struct Theme {
+    pub name: String,
+    /// Background of the screens
+    pub background: Rgb,
+    pub text: Rgb,
+    pub title: Rgb,
+    /// Selected item
+    pub highlight: Rgb,
+    /// Hints and other less important text
+    pub hint: Rgb,
+    /// Background of the overlays (log, console, status line)
+    pub panel: Rgb,
+    pub panel_text: Rgb,
+    /// Title bars and input lines of the overlays
+    pub border: Rgb,
+    /// Errors
+    pub danger: Rgb,
+    /// Warnings and the debug info
+    pub warning: Rgb,
+}
+
+pub fn themes_dir() -> PathBuf {
+    config::config_dir().join("themes")
+}
+
+/// Parses `#rrggbb` or `r, g, b`
+pub fn parse_color(text: &str) -> Option<Rgb> {
+    let text = text.trim();
+
+    if text.starts_with('#') && text.len() == 7 {
+        let channel = |index: usize| u8::from_str_radix(text.get(index..index + 2)?, 16).ok();
+        return Some(Rgb(channel(1)?, channel(3)?, channel(5)?));
+    }
+
+    let channels: Vec<u8> = text.split(',')
+        .map(|channel| channel.trim().parse())
+        .collect::<Result<_, _>>()
+        .ok()?;
+
+    match channels.as_slice() {
+        [r, g, b] => Some(Rgb(*r, *g, *b)),
+        _ => None,
+    }
+}
+
+impl Theme {
+    pub fn light() -> Self {
+        Theme {
+            name: String::from("light"),
+            background: Rgb(255, 255, 255),
+            text: Rgb(0, 0, 0),
+            title: Rgb(0, 0, 0),
+            highlight: Rgb(0, 170, 0),
+            hint: Rgb(120, 120, 120),
+            panel: Rgb(20, 20, 30),
+            panel_text: Rgb(220, 220, 220),
+            border: Rgb(60, 60, 90),
+            danger: Rgb(200, 0, 0),
+            warning: Rgb(200, 140, 0),
+        }
+    }
+
+    pub fn dark() -> Self {
+        Theme {
+            name: String::from("dark"),
+            background: Rgb(20, 20, 25),
+            text: Rgb(210, 210, 210),
+            title: Rgb(255, 255, 255),
+            highlight: Rgb(120, 220, 120),
+            hint: Rgb(120, 120, 120),
+            panel: Rgb(10, 10, 15),
+            panel_text: Rgb(220, 220, 220),
+            border: Rgb(60, 60, 90),
+            danger: Rgb(255, 80, 80),
+            warning: Rgb(255, 200, 0),
+        }
+    }
+
+    pub fn high_contrast() -> Self {
+        Theme {
+            name: String::from("high-contrast"),
+            background: Rgb(0, 0, 0),
+            text: Rgb(255, 255, 255),
+            title: Rgb(255, 255, 0),
+            highlight: Rgb(0, 255, 255),
+            hint: Rgb(200, 200, 200),
+            panel: Rgb(0, 0, 0),
+            panel_text: Rgb(255, 255, 255),
+            border: Rgb(0, 0, 170),
+            danger: Rgb(255, 0, 0),
+            warning: Rgb(255, 255, 0),
+        }
+    }
+
+    pub fn builtin(name: &str) -> Option<Self> {
+        match name {
+            "light" => Some(Theme::light()),
+            "dark" => Some(Theme::dark()),
+            "high-contrast" => Some(Theme::high_contrast()),
+            _ => None,
+        }
+    }
+
+    /// Theme from a theme file. Missing colors are taken from the `base` theme (light by default).
+    pub fn from_config(name: &str, config: &Config) -> Result<Self, String> {
+        let base = config.get("base").unwrap_or(DEFAULT_THEME);
+        let mut theme = Theme::builtin(base).ok_or(format!("unknown base theme {}", base))?;
+        theme.name = String::from(name);
+
+        for (key, value) in config.entries().filter(|(key, _)| *key != "base") {
+            let color = parse_color(value).ok_or(format!("bad color of {}: {}", key, value))?;
+
+            match key {
+                "background" => theme.background = color,
+                "text" => theme.text = color,
+                "title" => theme.title = color,
+                "highlight" => theme.highlight = color,
+                "hint" => theme.hint = color,
+                "panel" => theme.panel = color,
+                "panel_text" => theme.panel_text = color,
+                "border" => theme.border = color,
+                "danger" => theme.danger = color,
+                "warning" => theme.warning = color,
+                _ => return Err(format!("unknown key {}", key)),
+            }
+        }
+
+        Ok(theme)
+    }
+
+    /// Built-in theme or the theme file with the name
+    pub fn load(name: &str) -> Result<Self, String> {
+        if let Some(theme) = Theme::builtin(name) {
+            return Ok(theme);
+        }
+
+        let path = themes_dir().join(format!("{}.{}", name, EXTENSION));
+        if !path.is_file() {
+            return Err(format!("no theme {}", name));
+        }
+
+        Theme::from_config(name, &Config::load(&path))
+            .map_err(|err| format!("{}: {}", path.display(), err))
+    }
+
+    /// Names of the built-in themes followed by the theme files
+    pub fn available() -> Vec<String> {
+        let mut files: Vec<String> = fs::read_dir(themes_dir())
+            .map(|entries| entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| path.extension().map_or(false, |extension| extension == EXTENSION))
+                .filter_map(|path| path.file_stem().map(|stem| stem.to_string_lossy().into_owned()))
+                .filter(|name| Theme::builtin(name).is_none())
+                .collect())
+            .unwrap_or_default();
+        files.sort();
+
+        BUILTIN_THEMES.iter().map(|name| String::from(*name)).chain(files).collect()
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme::light()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn rgb(color: Rgb) -> (u8, u8, u8) {
+        (color.0, color.1, color.2)
+    }
+
+    fn from_text(text: &str) -> Result<Theme, String> {
+        let mut config = Config::new(Path::new("test.theme"));
+        config.parse(text);
+        Theme::from_config("test", &config)
+    }
+
+    #[test]
+    fn colors_are_parsed() {
+        assert_eq!(parse_color("#002b36").map(rgb), Some((0, 43, 54)));
+        assert_eq!(parse_color(" #FFFFFF ").map(rgb), Some((255, 255, 255)));
+        assert_eq!(parse_color("133, 153, 0").map(rgb), Some((133, 153, 0)));
+        assert_eq!(parse_color("1,2,3").map(rgb), Some((1, 2, 3)));
+
+        for bad in ["", "#12345", "#1234567", "#gg0000", "1, 2", "1, 2, 3, 4", "256, 0, 0", "red"].iter() {
+            assert!(parse_color(bad).is_none(), "{} was parsed", bad);
+        }
+    }
+
+    #[test]
+    fn theme_file_overrides_its_base() {
+        let theme = from_text("base = dark\nbackground = #002b36\nhighlight = 133, 153, 0").unwrap();
+
+        assert_eq!(theme.name, "test");
+        assert_eq!(rgb(theme.background), (0, 43, 54));
+        assert_eq!(rgb(theme.highlight), (133, 153, 0));
+        assert_eq!(rgb(theme.text), rgb(Theme::dark().text));
+
+        let theme = from_text("text = 1, 2, 3").unwrap();
+        assert_eq!(rgb(theme.background), rgb(Theme::light().background));
+    }
+
+    #[test]
+    fn broken_theme_files_are_errors() {
+        assert_eq!(from_text("text = blue").unwrap_err(), "bad color of text: blue");
+        assert_eq!(from_text("shadow = #000000").unwrap_err(), "unknown key shadow");
+        assert_eq!(from_text("base = solarized").unwrap_err(), "unknown base theme solarized");
+    }
+}
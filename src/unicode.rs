@@ -0,0 +1,351 @@
+//! Display width of text in terminal cells. Text is measured by grapheme clusters
+//! (a character with its combining marks, an emoji ZWJ sequence, a flag), wide
+//! East Asian characters and emoji take two cells.
+//!
+//! The tables cover the common ranges of Unicode, not every code point.
+
+/// Zero width: combining marks, joiners, variation selectors and emoji modifiers
+const ZERO_WIDTH: [(u32, u32); 22] = [
+    (0x0300, 0x036F), (0x0483, 0x0489), (0x0591, 0x05BD), (0x0610, 0x061A),
+    (0x064B, 0x065F), (0x0670, 0x0670), (0x06D6, 0x06DC), (0x0900, 0x0902),
+    (0x093C, 0x093C), (0x0941, 0x0948), (0x0E31, 0x0E31), (0x0E34, 0x0E3A),
+    (0x1AB0, 0x1AFF), (0x1DC0, 0x1DFF), (0x200B, 0x200F), (0x2060, 0x2064),
+    (0x20D0, 0x20FF), (0xFE00, 0xFE0F), (0xFE20, 0xFE2F), (0xFEFF, 0xFEFF),
+    (0x1F3FB, 0x1F3FF), (0xE0000, 0xE0FFF),
+];
+
+/// Two cells: East Asian wide and fullwidth characters and emoji
+const WIDE: [(u32, u32); 55] = [
+    (0x1100, 0x115F), (0x231A, 0x231B), (0x2329, 0x232A), (0x23E9, 0x23EC),
+    (0x23F0, 0x23F0), (0x23F3, 0x23F3), (0x25FD, 0x25FE), (0x2614, 0x2615),
+    (0x2648, 0x2653), (0x267F, 0x267F), (0x2693, 0x2693), (0x26A1, 0x26A1),
+    (0x26AA, 0x26AB), (0x26BD, 0x26BE), (0x26C4, 0x26C5), (0x26CE, 0x26CE),
+    (0x26D4, 0x26D4), (0x26EA, 0x26EA), (0x26F2, 0x26F3), (0x26F5, 0x26F5),
+    (0x26FA, 0x26FA), (0x26FD, 0x26FD), (0x2705, 0x2705), (0x270A, 0x270B),
+    (0x2728, 0x2728), (0x274C, 0x274C), (0x274E, 0x274E), (0x2753, 0x2755),
+    (0x2757, 0x2757), (0x2795, 0x2797), (0x27B0, 0x27B0), (0x27BF, 0x27BF),
+    (0x2B1B, 0x2B1C), (0x2B50, 0x2B50), (0x2B55, 0x2B55), (0x2E80, 0x303E),
+    (0x3041, 0x33FF), (0x3400, 0x4DBF), (0x4E00, 0x9FFF), (0xA000, 0xA4CF),
+    (0xAC00, 0xD7A3), (0xF900, 0xFAFF), (0xFE30, 0xFE6F), (0xFF00, 0xFF60),
+    (0xFFE0, 0xFFE6), (0x1F004, 0x1F004), (0x1F0CF, 0x1F0CF), (0x1F18E, 0x1F18E),
+    (0x1F191, 0x1F19A), (0x1F200, 0x1F251), (0x1F300, 0x1F64F), (0x1F680, 0x1F6FF),
+    (0x1F7E0, 0x1F7EB), (0x1F900, 0x1FAFF), (0x20000, 0x3FFFD),
+];
+
+const ZERO_WIDTH_JOINER: char = '\u{200D}';
+/// Asks for the emoji presentation of the previous character
+const EMOJI_PRESENTATION: char = '\u{FE0F}';
+
+fn in_ranges(c: char, ranges: &[(u32, u32)]) -> bool {
+    let code = c as u32;
+
+    ranges.binary_search_by(|(start, end)| {
+        if code < *start {
+            std::cmp::Ordering::Greater
+        } else if code > *end {
+            std::cmp::Ordering::Less
+        } else {
+            std::cmp::Ordering::Equal
+        }
+    }).is_ok()
+}
+
+fn is_regional_indicator(c: char) -> bool {
+    ('\u{1F1E6}'..='\u{1F1FF}').contains(&c)
+}
+
+/// Cells taken by the character alone
+pub fn char_width(c: char) -> usize {
+    if c.is_control() || in_ranges(c, &ZERO_WIDTH) {
+        0
+    } else if in_ranges(c, &WIDE) {
+        2
+    } else {
+        1
+    }
+}
+
+/// Iterator over the grapheme clusters of a string
+pub struct Graphemes<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Iterator for Graphemes<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        let mut chars = self.rest.char_indices();
+        let (_, first) = chars.next()?;
+
+        let mut end = self.rest.len();
+        let mut previous = first;
+        let mut regional_indicators = is_regional_indicator(first) as usize;
+
+        for (index, c) in chars {
+            let joined = match c {
+                '\n' => previous == '\r',
+                _ if previous == ZERO_WIDTH_JOINER => true,
+                c if c == ZERO_WIDTH_JOINER || in_ranges(c, &ZERO_WIDTH) => true,
+                // Flags are pairs of regional indicators
+                c if is_regional_indicator(c) => regional_indicators % 2 == 1,
+                _ => false,
+            };
+
+            if !joined {
+                end = index;
+                break;
+            }
+
+            regional_indicators += is_regional_indicator(c) as usize;
+            previous = c;
+        }
+
+        let (grapheme, rest) = self.rest.split_at(end);
+        self.rest = rest;
+        Some(grapheme)
+    }
+}
+
+pub fn graphemes(text: &str) -> Graphemes<'_> {
+    Graphemes { rest: text }
+}
+
+/// Cells taken by one grapheme cluster
+pub fn grapheme_width(grapheme: &str) -> usize {
+    let mut chars = grapheme.chars();
+    let first = match chars.next() {
+        Some(first) => first,
+        None => return 0,
+    };
+
+    if is_regional_indicator(first) || grapheme.contains(EMOJI_PRESENTATION) {
+        return 2;
+    }
+
+    // Combining marks and the rest of a ZWJ sequence are drawn in the cells of the first character
+    char_width(first)
+}
+
+/// Cells taken by the text
+pub fn width(text: &str) -> usize {
+    graphemes(text).map(grapheme_width).sum()
+}
+
+/// The longest start of the text which fits into `width` cells
+pub fn truncate(text: &str, width: usize) -> &str {
+    let mut used = 0;
+    let mut end = 0;
+
+    for grapheme in graphemes(text) {
+        used += grapheme_width(grapheme);
+        if used > width {
+            break;
+        }
+
+        end += grapheme.len();
+    }
+
+    &text[..end]
+}
+
+/// The longest end of the text which fits into `width` cells
+pub fn tail(text: &str, width: usize) -> &str {
+    let clusters: Vec<&str> = graphemes(text).collect();
+    let mut used = 0;
+    let mut start = text.len();
+
+    for grapheme in clusters.iter().rev() {
+        used += grapheme_width(grapheme);
+        if used > width {
+            break;
+        }
+
+        start -= grapheme.len();
+    }
+
+    &text[start..]
+}
+
+/// The text cut or padded with spaces to exactly `width` cells
+pub fn fit(text: &str, width: usize) -> String {
+    let text = truncate(text, width);
+    // A wide character which doesn't fit leaves one cell to pad
+    format!("{}{}", text, " ".repeat(width - self::width(text)))
+}
+
+/// Removes the last grapheme cluster
+pub fn pop_grapheme(text: &mut String) {
+    let length = graphemes(text).last().map_or(0, str::len);
+    text.truncate(text.len() - length);
+}
+
+/// Splits the text into lines of at most `width` cells. Lines are broken at spaces
+/// and between wide characters, words longer than a line are broken anywhere.
+pub fn wrap(text: &str, width: usize) -> Vec<String> {
+    let width = width.max(1);
+    let mut lines = vec![];
+
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        let mut line_width = 0;
+
+        for word in words(paragraph) {
+            let word_width = self::width(word.text);
+            let space = (word.space_before && !line.is_empty()) as usize;
+
+            if !line.is_empty() && line_width + space + word_width > width {
+                lines.push(std::mem::replace(&mut line, String::new()));
+                line_width = 0;
+            } else if space == 1 {
+                line.push(' ');
+                line_width += 1;
+            }
+
+            for grapheme in graphemes(word.text) {
+                let cells = grapheme_width(grapheme);
+                if line_width + cells > width && !line.is_empty() {
+                    lines.push(std::mem::replace(&mut line, String::new()));
+                    line_width = 0;
+                }
+
+                line.push_str(grapheme);
+                line_width += cells;
+            }
+        }
+
+        lines.push(line);
+    }
+
+    lines
+}
+
+/// Part of a line which is not broken
+struct Word<'a> {
+    text: &'a str,
+    /// Separated from the previous word by a space. Wide characters are words without spaces.
+    space_before: bool,
+}
+
+fn words(text: &str) -> Vec<Word<'_>> {
+    let mut words = vec![];
+    let mut start = None;
+    let mut space_before = false;
+    let mut offset = 0;
+
+    for grapheme in graphemes(text) {
+        let is_space = grapheme.chars().all(char::is_whitespace);
+        let is_wide = grapheme_width(grapheme) == 2;
+
+        if is_space || is_wide {
+            if let Some(word_start) = start.take() {
+                words.push(Word { text: &text[word_start..offset], space_before });
+                space_before = false;
+            }
+        }
+
+        if is_space {
+            space_before = true;
+        } else if is_wide {
+            words.push(Word { text: grapheme, space_before });
+            space_before = false;
+        } else if start.is_none() {
+            start = Some(offset);
+        }
+
+        offset += grapheme.len();
+    }
+
+    if let Some(word_start) = start {
+        words.push(Word { text: &text[word_start..], space_before });
+    }
+
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FAMILY: &'static str = "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}";
+    const FLAG_JP: &'static str = "\u{1F1EF}\u{1F1F5}";
+    const FLAG_US: &'static str = "\u{1F1FA}\u{1F1F8}";
+
+    #[test]
+    fn tables_are_sorted_for_the_binary_search() {
+        for table in [&ZERO_WIDTH[..], &WIDE[..]].iter() {
+            for (start, end) in table.iter() {
+                assert!(start <= end, "{:X}..{:X}", start, end);
+            }
+
+            for pair in table.windows(2) {
+                assert!(pair[0].1 < pair[1].0, "{:X} overlaps {:X}", pair[0].1, pair[1].0);
+            }
+        }
+    }
+
+    #[test]
+    fn characters_have_their_widths() {
+        assert_eq!(char_width('a'), 1);
+        assert_eq!(char_width('ж'), 1);
+        assert_eq!(char_width('\t'), 0);
+        assert_eq!(char_width('\u{0301}'), 0);
+        assert_eq!(char_width('\u{200B}'), 0);
+        assert_eq!(char_width('\u{1F3FB}'), 0);
+        assert_eq!(char_width('日'), 2);
+        assert_eq!(char_width('あ'), 2);
+        assert_eq!(char_width('한'), 2);
+        assert_eq!(char_width('Ａ'), 2);
+        assert_eq!(char_width('\u{1F600}'), 2);
+        assert_eq!(char_width('\u{2E7F}'), 1);
+        assert_eq!(char_width('\u{A000}'), 2);
+        assert_eq!(char_width('\u{A4D0}'), 1);
+    }
+
+    #[test]
+    fn clusters_are_kept_together() {
+        let text = format!("e\u{0301}x{}{}{}\r\n!", FAMILY, FLAG_JP, FLAG_US);
+
+        assert_eq!(graphemes(&text).collect::<Vec<&str>>(), vec![
+            "e\u{0301}", "x", FAMILY, FLAG_JP, FLAG_US, "\r\n", "!",
+        ]);
+
+        assert_eq!(grapheme_width("e\u{0301}"), 1);
+        assert_eq!(grapheme_width(FAMILY), 2);
+        assert_eq!(grapheme_width(FLAG_JP), 2);
+        assert_eq!(grapheme_width("\u{2764}\u{FE0F}"), 2);
+        assert_eq!(width(&text), 1 + 1 + 2 + 2 + 2 + 1);
+    }
+
+    #[test]
+    fn truncate_and_tail_keep_whole_clusters() {
+        assert_eq!(truncate("日本語", 3), "日");
+        assert_eq!(truncate("日本語", 4), "日本");
+        assert_eq!(truncate("e\u{0301}e\u{0301}", 1), "e\u{0301}");
+        assert_eq!(truncate(&format!("a{}", FAMILY), 2), "a");
+        assert_eq!(truncate("abc", 0), "");
+
+        assert_eq!(tail("日本語", 5), "本語");
+        assert_eq!(tail(&format!("{}{}", FLAG_JP, FLAG_US), 3), FLAG_US);
+        assert_eq!(tail("abc", 0), "");
+
+        assert_eq!(fit("日本", 3), "日 ");
+        assert_eq!(fit("ab", 4), "ab  ");
+    }
+
+    #[test]
+    fn pop_removes_a_whole_cluster() {
+        let mut text = format!("a{}", FAMILY);
+        pop_grapheme(&mut text);
+        assert_eq!(text, "a");
+    }
+
+    #[test]
+    fn wrap_breaks_at_spaces_and_wide_characters() {
+        assert_eq!(wrap("hello big world", 9), vec!["hello big", "world"]);
+        assert_eq!(wrap("abcdefgh", 3), vec!["abc", "def", "gh"]);
+        assert_eq!(wrap("日本語のテキスト", 5), vec!["日本", "語の", "テキ", "スト"]);
+        assert_eq!(wrap("ab日本", 3), vec!["ab", "日", "本"]);
+        assert_eq!(wrap("日本", 1), vec!["日", "本"]);
+        assert_eq!(wrap("one\n\ntwo", 10), vec!["one", "", "two"]);
+    }
+}
@@ -1,13 +1,20 @@
-use crate::render::{Drawable, Render, Position};
+use crate::render::{Drawable, Render, Position, ColorMode};
+use crate::widgets::text::{self, Span, Style};
+use crate::unicode;
 
-use termion::color::Color;
 use termion::color;
 use std::cmp::max;
 
 pub struct Label {
-    pub color: Box<dyn Color>,
-    pub selected_color: Box<dyn Color>,
+    /// The text color of the theme if not set
+    pub color: Option<color::Rgb>,
+    /// The highlight color of the theme if not set
+    pub selected_color: Option<color::Rgb>,
+    /// Background and attributes of the whole label, its foreground is replaced by the colors above
+    pub style: Style,
     pub text: String,
+    /// The text has the markup of `widgets::text`
+    pub markup: bool,
     pub position: Position,
     pub selected: bool
 }
@@ -18,16 +25,38 @@ impl Label {
         Label { text: String::from(text), .. default}
     }
 
-    pub fn set_color(&mut self, color: Box<dyn Color>) -> &mut Self {
-        self.color = color;
+    /// Label with styled parts, e.g. `"You hit the [red]goblin[/] for [b]5[/b]"`
+    pub fn markup(text: &str) -> Self {
+        Label { markup: true, .. Label::new(text) }
+    }
+
+    pub fn set_color(&mut self, color: color::Rgb) -> &mut Self {
+        self.color = Some(color);
+        self
+    }
+
+    pub fn set_selected_color(&mut self, color: color::Rgb) -> &mut Self {
+        self.selected_color = Some(color);
+        self
+    }
+
+    pub fn set_style(&mut self, style: Style) -> &mut Self {
+        self.style = style;
         self
     }
 
-    pub fn set_selected_color(&mut self, color: Box<dyn Color>) -> &mut Self {
-        self.selected_color = color;
+    pub fn set_background(&mut self, color: color::Rgb) -> &mut Self {
+        self.style.bg = Some(color);
         self
     }
 
+    fn spans(&self) -> Vec<Span> {
+        match self.markup {
+            true => text::parse(&self.text),
+            false => vec![Span { text: self.text.clone(), style: Style::new() }],
+        }
+    }
+
     pub fn set_selected(&mut self, selected: bool) -> &mut Self {
         self.selected = selected;
         self
@@ -35,17 +64,28 @@ impl Label {
 }
 
 impl Drawable for Label {
-    fn draw(&self) -> String {
-        let color = match self.selected {
-            true => self.selected_color.as_ref(),
-            false => self.color.as_ref()
-        };
+    fn draw(&self, render: &Render) -> String {
+        let mut style = self.style;
+        style.fg = Some(match self.selected {
+            true => self.selected_color.unwrap_or(render.theme.highlight),
+            false => self.color.unwrap_or(render.theme.text)
+        });
 
-        format!("{}{}", color::Fg(color), self.text)
+        // Without colors the selection is shown inverted
+        if self.selected && render.color_mode() == ColorMode::Mono {
+            style.reverse = !style.reverse;
+        }
+
+        text::draw_spans(&self.spans(), &style, render)
     }
 
     fn get_width(&self) -> u16 {
-        max(self.text.len() as u16, 1)
+        let length = match self.markup {
+            true => unicode::width(&text::plain(&self.text)),
+            false => unicode::width(&self.text)
+        };
+
+        max(length as u16, 1)
     }
 
     fn get_height(&self) -> u16 {
@@ -64,9 +104,11 @@ impl Drawable for Label {
 impl Default for Label {
     fn default() -> Self {
         Label {
-            color: Box::new(color::Black),
-            selected_color: Box::new(color::Green),
+            color: None,
+            selected_color: None,
+            style: Style::new(),
             text: "".to_string(),
+            markup: false,
             position: Position::from(1, 1),
             selected: false
         }
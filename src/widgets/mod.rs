@@ -1,3 +1,4 @@
 pub mod label;
+pub mod text;
 
 use label::*;
\ No newline at end of file
@@ -0,0 +1,303 @@
+//! Styled text. A string with markup like `"You hit the [red]goblin[/] for [b]5[/b]"`
+//! is parsed into spans which have their own style.
+//!
+//! Tags: `[b]` bold, `[i]` italic, `[u]` underline, `[r]` reverse, `[dim]`,
+//! a color (`[red]`, `[#ff8000]`, `[255,128,0]`) and a background (`[bg=blue]`).
+//! `[/]` closes the last tag, `[/b]` the last `[b]` and the tags opened after it.
+//! `[[` is a literal `[`, unknown tags are kept as text.
+
+use termion::color::Rgb;
+use termion::style;
+
+use crate::render::Render;
+use crate::theme;
+
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct Style {
+    pub fg: Option<Rgb>,
+    /// The clear color of the screen if not set
+    pub bg: Option<Rgb>,
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub reverse: bool,
+    pub dim: bool,
+}
+
+impl Style {
+    pub fn new() -> Self {
+        Style::default()
+    }
+
+    pub fn fg(mut self, color: Rgb) -> Self {
+        self.fg = Some(color);
+        self
+    }
+
+    pub fn bg(mut self, color: Rgb) -> Self {
+        self.bg = Some(color);
+        self
+    }
+
+    pub fn bold(mut self) -> Self {
+        self.bold = true;
+        self
+    }
+
+    pub fn italic(mut self) -> Self {
+        self.italic = true;
+        self
+    }
+
+    pub fn underline(mut self) -> Self {
+        self.underline = true;
+        self
+    }
+
+    pub fn reverse(mut self) -> Self {
+        self.reverse = true;
+        self
+    }
+
+    pub fn dim(mut self) -> Self {
+        self.dim = true;
+        self
+    }
+
+    /// This style with the colors and attributes set in `other`
+    pub fn patch(&self, other: &Style) -> Style {
+        Style {
+            fg: other.fg.or(self.fg),
+            bg: other.bg.or(self.bg),
+            bold: self.bold || other.bold,
+            italic: self.italic || other.italic,
+            underline: self.underline || other.underline,
+            reverse: self.reverse || other.reverse,
+            dim: self.dim || other.dim,
+        }
+    }
+
+    /// Escape sequences which switch from any style to this one
+    pub fn start(&self, render: &Render) -> String {
+        let mut sequence = format!("{}{}{}{}{}", style::NoFaint, style::NoItalic, style::NoUnderline,
+                                   style::NoInvert, render.bg(self.bg.unwrap_or(render.clear_color)));
+
+        if let Some(fg) = self.fg {
+            sequence.push_str(render.fg(fg).as_str());
+        }
+
+        let attributes = [
+            (self.bold, style::Bold.to_string()),
+            (self.dim, style::Faint.to_string()),
+            (self.italic, style::Italic.to_string()),
+            (self.underline, style::Underline.to_string()),
+            (self.reverse, style::Invert.to_string()),
+        ];
+
+        for (_, attribute) in attributes.iter().filter(|(on, _)| *on) {
+            sequence.push_str(attribute);
+        }
+
+        sequence
+    }
+
+    /// Escape sequences which turn the attributes off and restore the background of the screen.
+    /// The foreground color is kept like after a plain colored text.
+    pub fn end(render: &Render) -> String {
+        format!("{}{}{}{}{}", style::NoFaint, style::NoItalic, style::NoUnderline, style::NoInvert,
+                render.bg(render.clear_color))
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Span {
+    pub text: String,
+    /// Only the parts set by the markup, applied over the style of the widget
+    pub style: Style,
+}
+
+fn named_color(name: &str) -> Option<Rgb> {
+    let color = match name {
+        "black" => Rgb(0, 0, 0),
+        "red" => Rgb(220, 40, 40),
+        "green" => Rgb(40, 180, 40),
+        "yellow" => Rgb(230, 200, 0),
+        "blue" => Rgb(50, 90, 230),
+        "magenta" => Rgb(200, 50, 200),
+        "cyan" => Rgb(0, 190, 190),
+        "white" => Rgb(255, 255, 255),
+        "gray" | "grey" => Rgb(128, 128, 128),
+        "orange" => Rgb(230, 120, 0),
+        _ => return None,
+    };
+
+    Some(color)
+}
+
+fn parse_color(text: &str) -> Option<Rgb> {
+    named_color(text).or_else(|| theme::parse_color(text))
+}
+
+/// What the opening tag adds to the style, None if the tag is unknown
+fn tag_style(tag: &str) -> Option<Style> {
+    let style = Style::new();
+
+    match tag {
+        "b" | "bold" => Some(style.bold()),
+        "i" | "italic" => Some(style.italic()),
+        "u" | "underline" => Some(style.underline()),
+        "r" | "reverse" => Some(style.reverse()),
+        "dim" => Some(style.dim()),
+        tag if tag.starts_with("bg=") => parse_color(&tag[3..]).map(|color| style.bg(color)),
+        tag => parse_color(tag).map(|color| style.fg(color)),
+    }
+}
+
+/// The short name of the tag, so `[/b]` closes `[bold]`
+fn canonical(tag: &str) -> &str {
+    match tag {
+        "bold" => "b",
+        "italic" => "i",
+        "underline" => "u",
+        "reverse" => "r",
+        "grey" => "gray",
+        tag => tag,
+    }
+}
+
+/// Splits the markup into spans. Adjacent text with the same style is one span.
+pub fn parse(markup: &str) -> Vec<Span> {
+    let mut spans: Vec<Span> = vec![];
+    // Open tags with the style inside them
+    let mut stack: Vec<(&str, Style)> = vec![];
+    let mut text = String::new();
+    let mut rest = markup;
+
+    let current = |stack: &Vec<(&str, Style)>| stack.last().map_or(Style::new(), |(_, style)| *style);
+
+    fn flush(spans: &mut Vec<Span>, text: &mut String, style: Style) {
+        if text.is_empty() {
+            return;
+        }
+
+        match spans.last_mut() {
+            Some(span) if span.style == style => span.text.push_str(text),
+            _ => spans.push(Span { text: text.clone(), style }),
+        }
+
+        text.clear();
+    }
+
+    while let Some(open) = rest.find('[') {
+        text.push_str(&rest[..open]);
+        rest = &rest[open..];
+
+        if rest.starts_with("[[") {
+            text.push('[');
+            rest = &rest[2..];
+            continue;
+        }
+
+        let tag = match rest.find(']') {
+            Some(close) => &rest[1..close],
+            None => break,
+        };
+
+        if let Some(name) = tag.strip_prefix('/') {
+            let position = match canonical(name) {
+                "" => stack.len().checked_sub(1),
+                name => stack.iter().rposition(|(open, _)| *open == name),
+            };
+
+            if let Some(position) = position {
+                flush(&mut spans, &mut text, current(&stack));
+                stack.truncate(position);
+                rest = &rest[tag.len() + 2..];
+                continue;
+            }
+        } else if let Some(style) = tag_style(tag) {
+            flush(&mut spans, &mut text, current(&stack));
+            let style = current(&stack).patch(&style);
+            stack.push((canonical(tag), style));
+            rest = &rest[tag.len() + 2..];
+            continue;
+        }
+
+        // Not a tag, keep the bracket as text
+        text.push('[');
+        rest = &rest[1..];
+    }
+
+    text.push_str(rest);
+    flush(&mut spans, &mut text, current(&stack));
+
+    spans
+}
+
+/// The text without the markup
+pub fn plain(markup: &str) -> String {
+    parse(markup).into_iter().map(|span| span.text).collect()
+}
+
+/// The spans styled over `base`, ends with the attributes turned off
+pub fn draw_spans(spans: &[Span], base: &Style, render: &Render) -> String {
+    let mut text = String::new();
+
+    for span in spans {
+        text.push_str(base.patch(&span.style).start(render).as_str());
+        text.push_str(span.text.as_str());
+    }
+
+    text.push_str(Style::end(render).as_str());
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(text: &str, style: Style) -> Span {
+        Span { text: String::from(text), style }
+    }
+
+    #[test]
+    fn double_bracket_is_a_bracket() {
+        assert_eq!(parse("[[b] and [[[b]x[/b]"), vec![
+            span("[b] and [", Style::new()),
+            span("x", Style::new().bold()),
+        ]);
+    }
+
+    #[test]
+    fn short_and_long_names_close_each_other() {
+        assert_eq!(parse("[bold]a[i]b[/b]c"), vec![
+            span("a", Style::new().bold()),
+            span("b", Style::new().bold().italic()),
+            span("c", Style::new()),
+        ]);
+
+        assert_eq!(parse("[b]a[underline]b[/u]c[/bold]d[grey]e[/gray]"), vec![
+            span("a", Style::new().bold()),
+            span("b", Style::new().bold().underline()),
+            span("c", Style::new().bold()),
+            span("d", Style::new()),
+            span("e", Style::new().fg(Rgb(128, 128, 128))),
+        ]);
+    }
+
+    #[test]
+    fn unknown_and_unterminated_tags_are_text() {
+        assert_eq!(plain("[foo]a[/foo] [/b]"), "[foo]a[/foo] [/b]");
+        assert_eq!(parse("a[b]b[i"), vec![
+            span("a", Style::new()),
+            span("b[i", Style::new().bold()),
+        ]);
+        assert_eq!(plain("[x"), "[x");
+    }
+
+    #[test]
+    fn same_style_spans_are_merged() {
+        assert_eq!(parse("[b]a[/b][bold]b[/bold]"), vec![span("ab", Style::new().bold())]);
+        assert_eq!(parse("a[red][/red]b[/]"), vec![span("ab[/]", Style::new())]);
+    }
+}